@@ -120,7 +120,7 @@ fn bench_dense_sparse(c: &mut Criterion, loader: &FaerLoader) {
                 par,
             );
             let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req).unwrap();
-            let mut stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
 
             let mut output = Mat::zeros(lhs_rows, loader.ncols);
             group.bench_with_input(
@@ -136,7 +136,7 @@ fn bench_dense_sparse(c: &mut Criterion, loader: &FaerLoader) {
                             1.0,
                             *par,
                             strategy,
-                            &mut stack,
+                            stack,
                             Some(par_dense_sparse),
                         );
                     })
@@ -178,6 +178,7 @@ fn parallel_scaling_benchmarks(c: &mut Criterion) {
     //create_synthetic_benchmark_parallel(c);
 }
 
+#[allow(dead_code)]
 fn create_synthetic_benchmark_parallel(c: &mut Criterion) {
     let matrix_params = [
         (100, 0.01),
@@ -0,0 +1,135 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::num::NonZero;
+
+use faer::{Mat, MatRef, Par, sparse::SparseColMatRef};
+
+use par_matvec::{
+    reorder::{
+        metrics::{ForeignWriteReport, MatrixMetrics},
+        partition::metis_symmetric,
+        rcm::rcm,
+    },
+    sparse_dense_impl::simple,
+    spmv_drivers::{SpMvStrategy, sparse_dense_matmul},
+    test_utils::{FaerLoader, medium_matrix_paths},
+};
+
+fn bench_ordering(
+    c: &mut Criterion,
+    group_name: &str,
+    ordering: &str,
+    matrix: SparseColMatRef<'_, usize, f64>,
+    rhs: MatRef<'_, f64>,
+    par: Par,
+) {
+    let strategy = SpMvStrategy::new(matrix.symbolic(), par);
+    println!(
+        "  {:>8}: {}",
+        ordering,
+        MatrixMetrics::new(matrix.symbolic(), &strategy)
+    );
+
+    let stack_req = simple::sparse_dense_scratch(matrix, rhs, &strategy, par);
+    let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req).unwrap();
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let mut output = Mat::zeros(matrix.nrows(), rhs.ncols());
+
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(100);
+    group.bench_function(BenchmarkId::new("sparse_dense_simple", ordering), |b| {
+        b.iter(|| {
+            sparse_dense_matmul(
+                output.as_mut(),
+                faer::Accum::Replace,
+                matrix,
+                rhs,
+                1.0,
+                par,
+                &strategy,
+                stack,
+                Some(simple::par_sparse_dense),
+            );
+        })
+    });
+    group.finish();
+}
+
+fn bench_reorderings(c: &mut Criterion, loader: &FaerLoader) {
+    let num_threads = num_cpus::get();
+    let par = match NonZero::new(num_threads) {
+        Some(n_threads) if num_threads > 1 => Par::Rayon(n_threads),
+        _ => Par::Seq,
+    };
+    let matrix = loader.faer_csc.as_ref();
+    let group_name = format!(
+        "reorder_{}-{}x{}_nnz{}_{}_threads",
+        loader.matrix_name, loader.nrows, loader.ncols, loader.nnz, num_threads
+    );
+
+    let rcm_reordering = rcm(matrix.symbolic());
+    let rcm_matrix = rcm_reordering.permute_matrix(matrix);
+    let rcm_rhs = rcm_reordering.permute_rhs(loader.rhs_vector.col(0));
+
+    bench_ordering(
+        c,
+        &group_name,
+        "original",
+        matrix,
+        loader.rhs_vector.as_ref(),
+        par,
+    );
+    bench_ordering(
+        c,
+        &group_name,
+        "rcm",
+        rcm_matrix.as_ref(),
+        rcm_rhs.as_mat(),
+        par,
+    );
+
+    match metis_symmetric(matrix.symbolic(), num_threads) {
+        Ok(metis_reordering) => {
+            let metis_matrix = metis_reordering.permute_matrix(matrix);
+            let metis_rhs = metis_reordering.permute_rhs(loader.rhs_vector.col(0));
+            println!(
+                "{}",
+                ForeignWriteReport::new(matrix.symbolic(), metis_matrix.symbolic(), par)
+            );
+            bench_ordering(
+                c,
+                &group_name,
+                "metis",
+                metis_matrix.as_ref(),
+                metis_rhs.as_mat(),
+                par,
+            );
+        }
+        Err(e) => eprintln!("METIS failed on '{}': {}", loader.matrix_name, e),
+    }
+}
+
+fn reorder_benchmarks(c: &mut Criterion) {
+    println!("Running reordering benchmark...");
+
+    for matrix_path in medium_matrix_paths() {
+        match FaerLoader::load_from_matrix_market(&matrix_path, 1) {
+            Ok(loader) => {
+                if loader.nrows != loader.ncols {
+                    continue;
+                }
+                println!(
+                    "Loaded matrix '{}': {}x{} with {} non-zeros",
+                    loader.matrix_name, loader.nrows, loader.ncols, loader.nnz
+                );
+
+                bench_reorderings(c, &loader);
+            }
+            Err(e) => {
+                eprintln!("Failed to load matrix '{}': {}", matrix_path.display(), e);
+            }
+        }
+    }
+}
+
+criterion_group!(all, reorder_benchmarks);
+criterion_main!(all);
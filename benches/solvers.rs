@@ -0,0 +1,203 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::num::NonZero;
+
+use faer::{Col, Par, sparse::SparseColMatRef};
+
+use par_matvec::{
+    operator::SparseOperator,
+    smoothers::jacobi::{JacobiDiagonal, JacobiSmoother},
+    solvers::{
+        StoppingCriteria,
+        bicgstab::{bicgstab, bicgstab_scratch},
+        cg::{cg, cg_scratch, pcg},
+        gmres::{gmres, gmres_scratch},
+    },
+    spmv_drivers::SpMvBackend,
+    test_utils::{convection_diffusion_2d, laplacian_2d},
+};
+
+/// GMRES restart length
+const RESTART: usize = 30;
+
+fn thread_counts() -> Vec<usize> {
+    let mut thread_counts = vec![1];
+    let cpus = num_cpus::get();
+    let mut n_threads = 2;
+    while n_threads <= cpus {
+        thread_counts.push(n_threads);
+        n_threads *= 2;
+    }
+    thread_counts
+}
+
+fn bench_cg(c: &mut Criterion, name: &str, matrix: SparseColMatRef<'_, usize, f64>) {
+    let mut group = c.benchmark_group(format!(
+        "solve_{}-{}x{}_nnz{}",
+        name,
+        matrix.nrows(),
+        matrix.ncols(),
+        matrix.compute_nnz()
+    ));
+    group.sample_size(10);
+    let thread_counts = thread_counts();
+
+    let b = Col::from_fn(matrix.nrows(), |i| 1.0 + (i % 7) as f64 * 0.1);
+    let criteria = StoppingCriteria {
+        rtol: 1e-8,
+        max_iters: 10_000,
+        ..Default::default()
+    };
+
+    for &num_threads in &thread_counts {
+        let par = match NonZero::new(num_threads) {
+            Some(n_threads) if num_threads > 1 => Par::Rayon(n_threads),
+            _ => Par::Seq,
+        };
+        let jacobi = JacobiSmoother::new(matrix, JacobiDiagonal::Plain, 1.0, par);
+        let mut x = Col::zeros(matrix.nrows());
+
+        // the backend only matters for the parallel SpMV
+        let backends = match par {
+            Par::Seq => &SpMvBackend::ALL[..1],
+            Par::Rayon(_) => &SpMvBackend::ALL[..],
+        };
+        for &backend in backends {
+            let a = SparseOperator::with_backend(matrix, backend, par);
+            let stack_req = cg_scratch(&a, Some(&jacobi));
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req).unwrap();
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+            let info = cg(&a, x.as_mut(), b.as_ref(), &criteria, par, stack);
+            println!(
+                "  {} threads, {}: CG converged {} in {} iterations",
+                num_threads,
+                backend.name(),
+                info.converged,
+                info.iterations
+            );
+            group.bench_function(
+                BenchmarkId::new(
+                    format!("cg_{}", backend.name()),
+                    format!("{}_threads", num_threads),
+                ),
+                |bencher| {
+                    bencher.iter(|| {
+                        x.fill(0.0);
+                        cg(&a, x.as_mut(), b.as_ref(), &criteria, par, stack)
+                    })
+                },
+            );
+            if backend == SpMvBackend::Simple {
+                group.bench_function(
+                    BenchmarkId::new("pcg_jacobi", format!("{}_threads", num_threads)),
+                    |bencher| {
+                        bencher.iter(|| {
+                            x.fill(0.0);
+                            pcg(&a, &jacobi, x.as_mut(), b.as_ref(), &criteria, par, stack)
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_nonsymmetric(c: &mut Criterion, name: &str, matrix: SparseColMatRef<'_, usize, f64>) {
+    let mut group = c.benchmark_group(format!(
+        "solve_{}-{}x{}_nnz{}",
+        name,
+        matrix.nrows(),
+        matrix.ncols(),
+        matrix.compute_nnz()
+    ));
+    group.sample_size(10);
+    let thread_counts = thread_counts();
+
+    let b = Col::from_fn(matrix.nrows(), |i| 1.0 + (i % 7) as f64 * 0.1);
+    let criteria = StoppingCriteria {
+        rtol: 1e-8,
+        max_iters: 10_000,
+        ..Default::default()
+    };
+
+    for &num_threads in &thread_counts {
+        let par = match NonZero::new(num_threads) {
+            Some(n_threads) if num_threads > 1 => Par::Rayon(n_threads),
+            _ => Par::Seq,
+        };
+        let a = SparseOperator::new(matrix, par);
+        let stack_req = gmres_scratch(&a, None, RESTART).or(bicgstab_scratch(&a, None));
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req).unwrap();
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+        let mut x = Col::zeros(matrix.nrows());
+
+        let info = gmres(
+            &a,
+            None,
+            x.as_mut(),
+            b.as_ref(),
+            RESTART,
+            &criteria,
+            par,
+            stack,
+        );
+        println!(
+            "  {} threads: GMRES({}) converged {} in {} iterations",
+            num_threads, RESTART, info.converged, info.iterations
+        );
+        x.fill(0.0);
+        let info = bicgstab(&a, None, x.as_mut(), b.as_ref(), &criteria, par, stack);
+        println!(
+            "  {} threads: BiCGStab converged {} in {} iterations",
+            num_threads, info.converged, info.iterations
+        );
+        group.bench_function(
+            BenchmarkId::new(
+                format!("gmres_{}", RESTART),
+                format!("{}_threads", num_threads),
+            ),
+            |bencher| {
+                bencher.iter(|| {
+                    x.fill(0.0);
+                    gmres(
+                        &a,
+                        None,
+                        x.as_mut(),
+                        b.as_ref(),
+                        RESTART,
+                        &criteria,
+                        par,
+                        stack,
+                    )
+                })
+            },
+        );
+        group.bench_function(
+            BenchmarkId::new("bicgstab", format!("{}_threads", num_threads)),
+            |bencher| {
+                bencher.iter(|| {
+                    x.fill(0.0);
+                    bicgstab(&a, None, x.as_mut(), b.as_ref(), &criteria, par, stack)
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn solver_benchmarks(c: &mut Criterion) {
+    println!("Running end-to-end solver benchmark...");
+
+    for size in [100, 300] {
+        let matrix = laplacian_2d(size, size);
+        bench_cg(c, &format!("laplacian_{}", size), matrix.as_ref());
+        let matrix = convection_diffusion_2d(size, size, 10.0, 5.0);
+        bench_nonsymmetric(c, &format!("convection_{}", size), matrix.as_ref());
+    }
+}
+
+criterion_group!(all, solver_benchmarks);
+criterion_main!(all);
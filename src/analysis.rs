@@ -0,0 +1,141 @@
+//! Partition quality of an `SpMvStrategy` plan.
+//!
+//! Reports what every thread is handed by the plan and how much of its output it owns under the
+//! `buffer_foreign` row blocking, together with the scratch every algorithm asks for. Useful to
+//! tell load imbalance, foreign-write traffic and workspace size apart when a kernel is slow.
+use std::fmt;
+
+use faer::{Index, MatRef, Par, sparse::SparseColMatRef, traits::ComplexField};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::dense_sparse_impl::dense_sparse_scratch;
+use crate::sparse_dense_impl::{
+    buffer_foreign::{self, B_ROWS, assign_blocks},
+    merge, simple,
+};
+use crate::spmv_drivers::SpMvStrategy;
+
+/// What a single thread is handed by the plan
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadReport {
+    pub nnz: usize,
+    /// Columns touched, including the (possibly shared) boundary columns
+    pub cols: usize,
+    /// Distinct `B_ROWS` output blocks written to but owned by another thread
+    pub foreign_blocks: usize,
+    /// Fraction of the nonzeros landing in rows owned by this thread
+    pub owned_row_fraction: f64,
+}
+
+/// Scratch requested by each algorithm for a single right hand side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorkspaceBytes {
+    pub dense_sparse: usize,
+    pub simple: usize,
+    pub merge: usize,
+    pub buffer_foreign: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionReport {
+    pub threads: Vec<ThreadReport>,
+    pub workspace_bytes: WorkspaceBytes,
+}
+
+impl PartitionReport {
+    /// Analyse `strategy`, which must have been built from `mat.symbolic()` with `par`
+    pub fn new<I: Index, T: ComplexField>(
+        mat: SparseColMatRef<'_, I, T>,
+        strategy: &SpMvStrategy,
+        par: Par,
+    ) -> Self {
+        let m = mat.nrows();
+        let n_threads = strategy.thread_cols.len().saturating_sub(1);
+        let (owner_of_block, _) = assign_blocks(m, B_ROWS, &strategy.thread_rows);
+        let n_blocks = owner_of_block.len();
+        let row_indices = mat.symbolic().row_idx();
+
+        let threads = (0..n_threads)
+            .into_par_iter()
+            .map(|tid| {
+                let idx_start = strategy.thread_indptrs[tid];
+                let idx_end = strategy.thread_indptrs[tid + 1];
+                let mut touched = vec![false; n_blocks];
+                let mut owned = 0;
+                for row in &row_indices[idx_start..idx_end] {
+                    let block_id = row.zx() / B_ROWS;
+                    if owner_of_block[block_id] == tid {
+                        owned += 1;
+                    } else {
+                        touched[block_id] = true;
+                    }
+                }
+                let nnz = idx_end - idx_start;
+                ThreadReport {
+                    nnz,
+                    cols: 1 + strategy.thread_cols[tid + 1] - strategy.thread_cols[tid],
+                    foreign_blocks: touched.iter().filter(|&&t| t).count(),
+                    owned_row_fraction: if nnz == 0 {
+                        1.0
+                    } else {
+                        owned as f64 / nnz as f64
+                    },
+                }
+            })
+            .collect();
+
+        // the scratch functions only look at the number of vectors
+        let rhs = MatRef::<T>::from_column_major_slice(&[], 0, 1);
+        let workspace_bytes = WorkspaceBytes {
+            dense_sparse: dense_sparse_scratch(rhs.transpose(), mat, strategy, par).size_bytes(),
+            simple: simple::sparse_dense_scratch(mat, rhs, strategy, par).size_bytes(),
+            merge: merge::sparse_dense_scratch(mat, rhs, strategy, par).size_bytes(),
+            buffer_foreign: buffer_foreign::sparse_dense_scratch(mat, rhs, strategy, par)
+                .size_bytes(),
+        };
+
+        Self {
+            threads,
+            workspace_bytes,
+        }
+    }
+
+    /// Largest thread nnz over the mean thread nnz, 1.0 is a perfect split
+    pub fn nnz_imbalance(&self) -> f64 {
+        let max = self.threads.iter().map(|t| t.nnz).max().unwrap_or(0);
+        let total: usize = self.threads.iter().map(|t| t.nnz).sum();
+        if total == 0 {
+            1.0
+        } else {
+            max as f64 * self.threads.len() as f64 / total as f64
+        }
+    }
+}
+
+impl fmt::Display for PartitionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>12} {:>10} {:>15} {:>11}",
+            "thread", "nnz", "cols", "foreign blocks", "owned rows"
+        )?;
+        for (tid, thread) in self.threads.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>6} {:>12} {:>10} {:>15} {:>10.1}%",
+                tid,
+                thread.nnz,
+                thread.cols,
+                thread.foreign_blocks,
+                100.0 * thread.owned_row_fraction
+            )?;
+        }
+        writeln!(f, "nnz imbalance (max / mean): {:.3}", self.nnz_imbalance())?;
+        let bytes = &self.workspace_bytes;
+        write!(
+            f,
+            "workspace bytes: dense_sparse {}, simple {}, merge {}, buffer_foreign {}",
+            bytes.dense_sparse, bytes.simple, bytes.merge, bytes.buffer_foreign
+        )
+    }
+}
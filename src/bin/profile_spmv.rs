@@ -4,6 +4,7 @@ use std::time::{Duration, Instant};
 
 use faer::{Accum, Par};
 
+use par_matvec::analysis::PartitionReport;
 use par_matvec::dense_sparse_impl::{dense_sparse_scratch, par_dense_sparse};
 use par_matvec::sparse_dense_impl::{buffer_foreign, merge, simple};
 use par_matvec::spmv_drivers::{SpMvStrategy, dense_sparse_matmul, sparse_dense_matmul};
@@ -59,7 +60,7 @@ fn profile_dense_sparse(
     let lhs = rhs.transpose();
     let stack_req = dense_sparse_scratch(lhs.as_ref(), matrix, strategy, par);
     let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req).unwrap();
-    let mut stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
     let mut result = faer::Mat::zeros(lhs.nrows(), loader.ncols);
     let mut iterations = 0;
 
@@ -72,7 +73,7 @@ fn profile_dense_sparse(
             1.0,
             par,
             strategy,
-            &mut stack,
+            stack,
             Some(par_dense_sparse),
         );
         iterations += 1;
@@ -134,6 +135,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Par::Rayon(std::num::NonZeroUsize::new(num_threads).unwrap())
     };
     let strategy = SpMvStrategy::new(loader.faer_csc.symbolic(), par);
+    println!(
+        "Partition report:\n{}",
+        PartitionReport::new(loader.faer_csc.as_ref(), &strategy, par)
+    );
 
     println!("Starting 10-second profiling loop...");
     let start_time = Instant::now();
@@ -0,0 +1,96 @@
+//! Cost model for the planner.
+//!
+//! `SpMvStrategy::new` gives every thread the same number of nonzeros, but a thread also pays for
+//! every column it walks: loading the column pointers and the right hand side entry and restarting
+//! the inner loop. `CostModel` charges `nnz_cost` per nonzero and `col_cost` per column, which
+//! `SpMvStrategy::new_weighted` balances instead of the raw nnz.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use faer::{Col, Index, sparse::SparseColMatRef, traits::ComplexField};
+
+use crate::sparse_dense_impl::simple::hot_loop;
+
+/// Minimum time spent timing each of the two calibration sweeps
+const CALIBRATION_TIME: Duration = Duration::from_millis(20);
+
+/// Cost of a column with `nnz` nonzeros is `nnz_cost * nnz + col_cost`, in arbitrary units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CostModel {
+    pub nnz_cost: f64,
+    pub col_cost: f64,
+}
+
+impl Default for CostModel {
+    /// Pure nnz balancing, as done by `SpMvStrategy::new`
+    fn default() -> Self {
+        Self {
+            nnz_cost: 1.0,
+            col_cost: 0.0,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn new(nnz_cost: f64, col_cost: f64) -> Self {
+        assert!(nnz_cost.is_finite() && nnz_cost >= 0.0);
+        assert!(col_cost.is_finite() && col_cost >= 0.0);
+        assert!(nnz_cost + col_cost > 0.0);
+        Self { nnz_cost, col_cost }
+    }
+
+    pub fn column_cost(&self, col_nnz: usize) -> f64 {
+        self.nnz_cost * col_nnz as f64 + self.col_cost
+    }
+
+    /// Measure the coefficients on this machine with the scatter loop of the `simple` kernel.
+    ///
+    /// Times a single threaded sweep over all columns of `mat` and one over the first half of
+    /// every column. Both walk the same columns, so the difference in time is down to the
+    /// nonzeros alone. The result is normalised to `nnz_cost == 1`. Falls back to the default
+    /// when `mat` is too small for the timings to tell the two apart.
+    pub fn calibrate<I: Index, T: ComplexField>(mat: SparseColMatRef<'_, I, T>) -> Self {
+        let (symbolic, values) = mat.parts();
+        let nnz = symbolic.compute_nnz();
+        let half_nnz: usize = (0..mat.ncols())
+            .map(|j| symbolic.col_range(j).len() / 2)
+            .sum();
+        if mat.ncols() == 0 || nnz == half_nnz {
+            return Self::default();
+        }
+
+        let rhs_k = T::one_impl();
+        let mut work = Col::<T>::zeros(mat.nrows());
+        let mut sweep = |half: bool| {
+            let start = Instant::now();
+            let mut sweeps = 0;
+            while start.elapsed() < CALIBRATION_TIME {
+                for j in 0..mat.ncols() {
+                    let mut col_range = symbolic.col_range(j);
+                    if half {
+                        col_range.end = col_range.start + col_range.len() / 2;
+                    }
+                    hot_loop(
+                        col_range,
+                        symbolic.row_idx(),
+                        values,
+                        black_box(&rhs_k),
+                        work.as_mut(),
+                    );
+                }
+                black_box(&mut work);
+                sweeps += 1;
+            }
+            start.elapsed().as_secs_f64() / sweeps as f64
+        };
+        let full = sweep(false);
+        let half = sweep(true);
+
+        let nnz_cost = (full - half) / (nnz - half_nnz) as f64;
+        if nnz_cost.is_nan() || nnz_cost <= 0.0 {
+            return Self::default();
+        }
+        let col_cost = (full - nnz_cost * nnz as f64) / mat.ncols() as f64;
+        Self::new(1.0, (col_cost / nnz_cost).max(0.0))
+    }
+}
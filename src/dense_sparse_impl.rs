@@ -4,8 +4,11 @@ use faer::{
     Accum, Index, MatRef, Par, RowMut, RowRef,
     dyn_stack::{MemStack, StackReq},
     prelude::Reborrow,
-    sparse::SparseColMatRef,
-    traits::{ComplexField, math_utils::zero},
+    sparse::{SparseColMatRef, SparseRowMatRef},
+    traits::{
+        ComplexField,
+        math_utils::{conj, zero},
+    },
 };
 
 use crate::spmv_drivers::SpMvStrategy;
@@ -33,6 +36,17 @@ pub fn dense_sparse_scratch<I: Index, T: ComplexField>(
     }
 }
 
+/// Scratch for `dst = lhs * rhs` with a CSR `lhs`, which runs the `dense_sparse` kernel on the
+/// transposed (CSC) views. See `spmv_drivers::sparse_row_dense_matmul`.
+pub fn sparse_row_dense_scratch<I: Index, T: ComplexField>(
+    lhs: SparseRowMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    strategy: &SpMvStrategy,
+    par: Par,
+) -> StackReq {
+    dense_sparse_scratch(rhs.transpose(), lhs.transpose(), strategy, par)
+}
+
 pub fn par_dense_sparse<I: Index, T: ComplexField>(
     dst: RowMut<'_, T>,
     beta: Accum,
@@ -43,112 +57,202 @@ pub fn par_dense_sparse<I: Index, T: ComplexField>(
     strategy: &SpMvStrategy,
     _stack: &mut MemStack,
 ) {
-    /*
-    let global_counter: usize = strategy
-        .thread_cols
-        .iter()
-        .zip(strategy.thread_cols.iter().skip(1))
-        .map(|(start, end)| 1 + end - start)
-        .sum();
-    */
-
-    //let (mut work, _) = temp_mat_zeroed::<T, _, _>(global_counter, 1, stack);
-    //let work = work.as_mat_mut();
-    //let work = work.rb();
-    let (rhs_symbolic, rhs_values) = rhs.parts();
-    let row_indices = rhs_symbolic.row_idx();
+    dense_sparse_stitched(dst, beta, lhs, rhs, alpha, None, n_threads, strategy);
+}
 
+/// `dst = beta * dst + alpha * lhs * rhs`, returning `<dst, v> = sum_j conj(dst_j) v_j`.
+///
+/// Every thread accumulates the dot product over the columns it owns right after writing them,
+/// the boundary columns are added by the root thread once they are stitched.
+pub fn par_dense_sparse_dot<I: Index, T: ComplexField>(
+    dst: RowMut<'_, T>,
+    beta: Accum,
+    lhs: RowRef<'_, T>,
+    rhs: SparseColMatRef<'_, I, T>,
+    alpha: &T,
+    v: RowRef<'_, T>,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    _stack: &mut MemStack,
+) -> T {
+    dense_sparse_stitched(dst, beta, lhs, rhs, alpha, Some(v), n_threads, strategy)
+}
+
+/// Shared body of `par_dense_sparse` and `par_dense_sparse_dot`, the returned dot product is zero
+/// if `v` is `None`
+fn dense_sparse_stitched<I: Index, T: ComplexField>(
+    dst: RowMut<'_, T>,
+    beta: Accum,
+    lhs: RowRef<'_, T>,
+    rhs: SparseColMatRef<'_, I, T>,
+    alpha: &T,
+    v: Option<RowRef<'_, T>>,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+) -> T {
     let mut dst = dst;
     if let Accum::Replace = beta {
         dst.fill(zero());
     }
 
-    //println!();
-
-    thread::scope(|s| {
+    let stitch: Vec<(T, T, T)> = thread::scope(|s| {
         let dst = dst.rb();
         let mut handles = Vec::with_capacity(n_threads);
         for tid in 0..n_threads {
             let handle = s.spawn(move || {
-                //let start_time = Instant::now();
-                let col_start = strategy.thread_cols[tid];
-                let col_end = strategy.thread_cols[tid + 1];
-                let idx_start = strategy.thread_indptrs[tid];
-                let idx_end = strategy.thread_indptrs[tid + 1];
-
-                // SAFETY: each thread gets 2 workspace values. Could probably just return these as
-                // tuple...
-                /*
-                let mut work = unsafe {
-                    work.col(0)
-                        .const_cast()
-                        .try_as_col_major_mut()
-                        .unwrap()
-                        .subrows_mut(n_threads * 2, 2)
-                };
-                */
                 // SAFETY: the ranges (col_start+1)..col_end are non-overlapping per thread
-                let mut dst_owned = unsafe { dst.const_cast() };
-
-                let mut left_contrib = T::zero_impl();
-                let mut right_contrib = T::zero_impl();
-                if col_start == col_end {
-                    for idx in col_start..col_end {
-                        let k = row_indices[idx].zx();
-                        let lhs_k = lhs[k].mul_by_ref(alpha);
-                        let rhs_kj = &rhs_values[idx];
-                        //work[0] = work[0].add_by_ref(&lhs_k.mul_by_ref(&rhs_kj));
-                        left_contrib = left_contrib.add_by_ref(&lhs_k.mul_by_ref(&rhs_kj));
-                    }
-                } else {
-                    let mut col_range = rhs_symbolic.col_range(col_start);
-                    col_range.start = idx_start;
-                    for idx in col_range {
-                        let k = row_indices[idx].zx();
-                        let lhs_k = lhs[k].mul_by_ref(alpha);
-                        let rhs_kj = &rhs_values[idx];
-                        left_contrib = left_contrib.add_by_ref(&lhs_k.mul_by_ref(&rhs_kj));
-                    }
-
-                    for j in col_start + 1..col_end {
-                        for idx in rhs_symbolic.col_range(j) {
-                            let k = row_indices[idx].zx();
-                            let lhs_k = lhs[k].mul_by_ref(alpha);
-                            let rhs_kj = &rhs_values[idx];
-                            dst_owned[j] = dst_owned[j].add_by_ref(&lhs_k.mul_by_ref(&rhs_kj));
-                        }
-                    }
-
-                    let mut col_range = rhs_symbolic.col_range(col_end);
-                    col_range.end = idx_end;
-                    for idx in col_range {
-                        let k = row_indices[idx].zx();
-                        let lhs_k = lhs[k].mul_by_ref(alpha);
-                        let rhs_kj = &rhs_values[idx];
-                        right_contrib = right_contrib.add_by_ref(&lhs_k.mul_by_ref(&rhs_kj));
-                    }
-                }
-                //let end_time = Instant::now();
-                //println!("{}: {:?}", tid, end_time.duration_since(start_time));
-                (left_contrib, right_contrib)
+                unsafe { stitched_thread_range(tid, dst, lhs, rhs, alpha, v, strategy) }
             });
             handles.push(handle);
         }
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    stitch_boundaries(dst, &stitch, v, strategy)
+}
 
-        let stitch: Vec<(T, T)> = handles.into_iter().map(|h| h.join().unwrap()).collect();
-
-        //let work = work.col(0);
-        // SAFETY: all workers have been joined so root thread is only one with access
-        let mut dst = unsafe { dst.const_cast() };
-        //for tid in 0..n_threads {
-        for (tid, (left_v, right_v)) in stitch.iter().enumerate() {
-            let left = strategy.thread_cols[tid];
-            let right = strategy.thread_cols[tid + 1];
-            //let work_idx = tid * 2;
-            //let left_v = &work[work_idx];
-            //let right_v = &work[work_idx + 1];
-            dst[left] = dst[left].add_by_ref(left_v);
-            dst[right] = dst[right].add_by_ref(right_v);
+/// Add `alpha * lhs * rhs` to the columns `(col_start+1)..col_end` thread `tid` owns in `dst`,
+/// returning the partial products of its two boundary columns and, if `v` is given, the dot
+/// product over its owned columns
+///
+/// # Safety
+///
+/// No other thread may access the owned columns of `dst` during the call.
+#[inline]
+pub(crate) unsafe fn stitched_thread_range<I: Index, T: ComplexField>(
+    tid: usize,
+    dst: RowRef<'_, T>,
+    lhs: RowRef<'_, T>,
+    rhs: SparseColMatRef<'_, I, T>,
+    alpha: &T,
+    v: Option<RowRef<'_, T>>,
+    strategy: &SpMvStrategy,
+) -> (T, T, T) {
+    // SAFETY: guaranteed by the caller
+    unsafe { stitched_thread_range_permuted(tid, dst, None, lhs, None, rhs, alpha, v, strategy) }
+}
+
+/// `stitched_thread_range` reading entry `k` of `lhs` from `lhs[lhs_idx[k]]` and writing column
+/// `j` of the product to `dst[dst_idx[j]]` (and pairing it with `v[dst_idx[j]]`), which applies a
+/// permutation of `lhs` and of `dst` without permuted copies
+///
+/// # Safety
+///
+/// `dst_idx` must be injective, and no other thread may access the entries of `dst` the owned
+/// columns map to during the call.
+#[inline]
+pub(crate) unsafe fn stitched_thread_range_permuted<I: Index, T: ComplexField>(
+    tid: usize,
+    dst: RowRef<'_, T>,
+    dst_idx: Option<&[I]>,
+    lhs: RowRef<'_, T>,
+    lhs_idx: Option<&[I]>,
+    rhs: SparseColMatRef<'_, I, T>,
+    alpha: &T,
+    v: Option<RowRef<'_, T>>,
+    strategy: &SpMvStrategy,
+) -> (T, T, T) {
+    let (rhs_symbolic, rhs_values) = rhs.parts();
+    let row_indices = rhs_symbolic.row_idx();
+    let col_start = strategy.thread_cols[tid];
+    let col_end = strategy.thread_cols[tid + 1];
+    let idx_start = strategy.thread_indptrs[tid];
+    let idx_end = strategy.thread_indptrs[tid + 1];
+
+    // SAFETY: guaranteed by the caller
+    let mut dst_owned = unsafe { dst.const_cast() };
+    let lhs_at = |k: usize| match lhs_idx {
+        Some(lhs_idx) => &lhs[lhs_idx[k].zx()],
+        None => &lhs[k],
+    };
+
+    let mut left_contrib = T::zero_impl();
+    let mut right_contrib = T::zero_impl();
+    let mut owned_dot = T::zero_impl();
+    if col_start == col_end {
+        for idx in idx_start..idx_end {
+            let k = row_indices[idx].zx();
+            let lhs_k = lhs_at(k).mul_by_ref(alpha);
+            let rhs_kj = &rhs_values[idx];
+            left_contrib = left_contrib.add_by_ref(&lhs_k.mul_by_ref(rhs_kj));
         }
-    });
+    } else {
+        let mut col_range = rhs_symbolic.col_range(col_start);
+        col_range.start = idx_start;
+        for idx in col_range {
+            let k = row_indices[idx].zx();
+            let lhs_k = lhs_at(k).mul_by_ref(alpha);
+            let rhs_kj = &rhs_values[idx];
+            left_contrib = left_contrib.add_by_ref(&lhs_k.mul_by_ref(rhs_kj));
+        }
+
+        for j in col_start + 1..col_end {
+            let out = dst_idx.map_or(j, |dst_idx| dst_idx[j].zx());
+            for idx in rhs_symbolic.col_range(j) {
+                let k = row_indices[idx].zx();
+                let lhs_k = lhs_at(k).mul_by_ref(alpha);
+                let rhs_kj = &rhs_values[idx];
+                dst_owned[out] = dst_owned[out].add_by_ref(&lhs_k.mul_by_ref(rhs_kj));
+            }
+            if let Some(v) = v {
+                owned_dot = owned_dot.add_by_ref(&conj(&dst_owned[out]).mul_by_ref(&v[out]));
+            }
+        }
+
+        let mut col_range = rhs_symbolic.col_range(col_end);
+        col_range.end = idx_end;
+        for idx in col_range {
+            let k = row_indices[idx].zx();
+            let lhs_k = lhs_at(k).mul_by_ref(alpha);
+            let rhs_kj = &rhs_values[idx];
+            right_contrib = right_contrib.add_by_ref(&lhs_k.mul_by_ref(rhs_kj));
+        }
+    }
+    (left_contrib, right_contrib, owned_dot)
+}
+
+/// Add the boundary partials of `stitched_thread_range` of every thread to `dst`, returning the
+/// full dot product with `v` (zero if `v` is `None`)
+pub(crate) fn stitch_boundaries<T: ComplexField>(
+    dst: RowMut<'_, T>,
+    stitch: &[(T, T, T)],
+    v: Option<RowRef<'_, T>>,
+    strategy: &SpMvStrategy,
+) -> T {
+    stitch_boundaries_permuted::<usize, T>(dst, None, stitch, v, strategy)
+}
+
+/// `stitch_boundaries` for `stitched_thread_range_permuted`, boundary column `j` is added to
+/// `dst[dst_idx[j]]`
+pub(crate) fn stitch_boundaries_permuted<I: Index, T: ComplexField>(
+    dst: RowMut<'_, T>,
+    dst_idx: Option<&[I]>,
+    stitch: &[(T, T, T)],
+    v: Option<RowRef<'_, T>>,
+    strategy: &SpMvStrategy,
+) -> T {
+    let out = |j: usize| dst_idx.map_or(j, |dst_idx| dst_idx[j].zx());
+    let mut dst = dst;
+    for (tid, (left_v, right_v, _)) in stitch.iter().enumerate() {
+        let left = out(strategy.thread_cols[tid]);
+        let right = out(strategy.thread_cols[tid + 1]);
+        dst[left] = dst[left].add_by_ref(left_v);
+        dst[right] = dst[right].add_by_ref(right_v);
+    }
+
+    let Some(v) = v else {
+        return T::zero_impl();
+    };
+    let mut dot = T::zero_impl();
+    for (_, _, owned_dot) in stitch {
+        dot = dot.add_by_ref(owned_dot);
+    }
+    // `thread_cols` is sorted so shared boundary columns are adjacent
+    let mut boundary = strategy.thread_cols.clone();
+    boundary.dedup();
+    for j in boundary {
+        let j = out(j);
+        dot = dot.add_by_ref(&conj(&dst[j]).mul_by_ref(&v[j]));
+    }
+    dot
 }
@@ -0,0 +1,4 @@
+//! Kernels which fuse an SpMV with the work that usually surrounds it, saving full passes over
+//! the matrix or the vectors.
+pub mod normal;
+pub mod residual;
@@ -0,0 +1,319 @@
+//! Normal equation products `A^T A x` and `A A^T x` on a single CSC matrix.
+//!
+//! `A^T A x` needs all of `t = A x` before any column dot product can start, so it is not fused:
+//! it runs the `simple` kernel into `t` and then the race-free `dense_sparse` column-dot pass, see
+//! `par_ata_matvec_two_pass`.
+//!
+//! `A A^T x` fuses both passes per column: every thread computes `t_j = A[:, j] . x` for a column
+//! it owns and immediately scatters `t_j A[:, j]` into its workspace while the column is still in
+//! cache. Only the (at most two) boundary columns of each thread need the stitched dot product, so
+//! those are scattered in a short second pass before the usual workspace reduction.
+use std::thread;
+
+use faer::{
+    Accum, ColMut, ColRef, Index, MatMut, MatRef, Par,
+    col::AsColMut,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    sparse::{
+        SparseColMatRef,
+        linalg::matmul::{
+            dense_sparse_matmul as seq_dense_sparse, sparse_dense_matmul as seq_sparse_dense,
+        },
+    },
+    traits::{ComplexField, math_utils::zero},
+};
+
+use crate::dense_sparse_impl::par_dense_sparse;
+use crate::sparse_dense_impl::simple;
+use crate::spmv_drivers::SpMvStrategy;
+
+pub fn ata_scratch<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    strategy: &SpMvStrategy,
+    par: Par,
+) -> StackReq {
+    match par {
+        Par::Seq => temp_mat_scratch::<T>(mat.nrows(), rhs.ncols()),
+        Par::Rayon(_) => temp_mat_scratch::<T>(mat.nrows(), 1)
+            .and(simple::sparse_dense_scratch(mat, rhs, strategy, par)),
+    }
+}
+
+pub fn aat_scratch<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    strategy: &SpMvStrategy,
+    par: Par,
+) -> StackReq {
+    let _ = strategy;
+    match par {
+        Par::Seq => temp_mat_scratch::<T>(mat.ncols(), rhs.ncols()),
+        Par::Rayon(n_threads) => temp_mat_scratch::<T>(mat.nrows(), n_threads.get()),
+    }
+}
+
+/// `dst = beta * dst + alpha * A^T A rhs`.
+///
+/// Not fused: this is the `simple` kernel into a temporary `t = alpha * A rhs` followed by the
+/// `dense_sparse` kernel on `t`, each on its own scoped threads. It only saves the caller the
+/// temporary and the second plan.
+pub fn par_ata_matvec_two_pass<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    let (mut intermediate, stack) = temp_mat_zeroed::<T, _, _>(mat.nrows(), 1, stack);
+    let mut intermediate = intermediate.as_mat_mut();
+    simple::par_sparse_dense(
+        intermediate.rb_mut().col_mut(0),
+        Accum::Replace,
+        mat,
+        rhs,
+        alpha,
+        n_threads,
+        strategy,
+        stack,
+    );
+    par_dense_sparse(
+        dst.transpose_mut(),
+        beta,
+        intermediate.rb().col(0).transpose(),
+        mat,
+        &T::one_impl(),
+        n_threads,
+        strategy,
+        stack,
+    );
+}
+
+/// Dot product of `rhs` with the entries of a (possibly clipped) column
+#[inline]
+fn partial_col_dot<I: Index, T: ComplexField>(
+    col_range: std::ops::Range<usize>,
+    row_indices: &[I],
+    values: &[T],
+    rhs: ColRef<'_, T>,
+) -> T {
+    let mut acc = T::zero_impl();
+    for idx in col_range {
+        let k = row_indices[idx].zx();
+        acc = acc.add_by_ref(&rhs[k].mul_by_ref(&values[idx]));
+    }
+    acc
+}
+
+#[inline]
+fn scatter_col<I: Index, T: ComplexField>(
+    col_range: std::ops::Range<usize>,
+    row_indices: &[I],
+    values: &[T],
+    scale: &T,
+    mut work: ColMut<'_, T>,
+) {
+    for idx in col_range {
+        let i = row_indices[idx].zx();
+        work[i] = work[i].add_by_ref(&values[idx].mul_by_ref(scale));
+    }
+}
+
+/// `dst = beta * dst + alpha * A A^T rhs`
+pub fn par_aat_matvec<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    let m = mat.nrows();
+
+    let (mut work, _) = temp_mat_zeroed::<T, _, _>(m, n_threads, stack);
+    let work = work.as_mat_mut();
+    let work = work.rb();
+    let (symbolic, values) = mat.parts();
+    let row_indices = symbolic.row_idx();
+
+    let clipped_range = |tid: usize, col: usize| {
+        let mut col_range = symbolic.col_range(col);
+        if col == strategy.thread_cols[tid] {
+            col_range.start = strategy.thread_indptrs[tid];
+        }
+        if col == strategy.thread_cols[tid + 1] {
+            col_range.end = strategy.thread_indptrs[tid + 1];
+        }
+        col_range
+    };
+
+    // Partial dot products of the boundary columns, interior columns are scattered right away
+    let partials: Vec<(T, T)> = thread::scope(|s| {
+        let mut handles = Vec::with_capacity(n_threads);
+        for tid in 0..n_threads {
+            let handle = s.spawn(move || {
+                // SAFETY each thread gets its own workspace vector to be summed when all complete
+                let mut work =
+                    unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
+
+                let col_start = strategy.thread_cols[tid];
+                let col_end = strategy.thread_cols[tid + 1];
+
+                let left = partial_col_dot(clipped_range(tid, col_start), row_indices, values, rhs);
+                if col_start == col_end {
+                    return (left, T::zero_impl());
+                }
+
+                for j in col_start + 1..col_end {
+                    let col_range = symbolic.col_range(j);
+                    let t_j = partial_col_dot(col_range.clone(), row_indices, values, rhs)
+                        .mul_by_ref(alpha);
+                    scatter_col(col_range, row_indices, values, &t_j, work.as_col_mut());
+                }
+
+                let right = partial_col_dot(clipped_range(tid, col_end), row_indices, values, rhs);
+                (left, right)
+            });
+            handles.push(handle);
+        }
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    // Stitch the boundary dot products. `thread_cols` is sorted so threads sharing a boundary
+    // column are adjacent.
+    let mut boundary: Vec<(usize, T)> = Vec::with_capacity(2 * n_threads);
+    for (tid, (left, right)) in partials.iter().enumerate() {
+        let col_start = strategy.thread_cols[tid];
+        let col_end = strategy.thread_cols[tid + 1];
+        for (col, partial) in [(col_start, left), (col_end, right)] {
+            match boundary.last_mut() {
+                Some((last_col, sum)) if *last_col == col => *sum = sum.add_by_ref(partial),
+                _ => boundary.push((col, partial.clone())),
+            }
+        }
+    }
+    for (_, t_j) in boundary.iter_mut() {
+        *t_j = t_j.mul_by_ref(alpha);
+    }
+    let boundary = &boundary;
+
+    thread::scope(|s| {
+        for tid in 0..n_threads {
+            s.spawn(move || {
+                // SAFETY each thread gets its own workspace vector to be summed when all complete
+                let mut work =
+                    unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
+
+                let col_start = strategy.thread_cols[tid];
+                let col_end = strategy.thread_cols[tid + 1];
+                let cols = [col_start, col_end];
+                let n_cols = if col_end == col_start { 1 } else { 2 };
+                for &col in &cols[..n_cols] {
+                    let t_j = &boundary
+                        .iter()
+                        .find(|(boundary_col, _)| *boundary_col == col)
+                        .unwrap()
+                        .1;
+                    scatter_col(
+                        clipped_range(tid, col),
+                        row_indices,
+                        values,
+                        t_j,
+                        work.as_col_mut(),
+                    );
+                }
+            });
+        }
+    });
+
+    let mut dst = dst;
+    if let Accum::Replace = beta {
+        dst.fill(zero());
+    }
+    simple::reduce_workspaces_rayon(strategy, work, dst);
+}
+
+/// `dst = beta * dst + alpha * A^T A rhs` for each column of `rhs`
+pub fn ata_matmul<I: Index, T: ComplexField>(
+    dst: MatMut<'_, T>,
+    beta: Accum,
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    alpha: T,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    match par {
+        Par::Seq => {
+            let (mut intermediate, _) = temp_mat_zeroed::<T, _, _>(mat.nrows(), rhs.ncols(), stack);
+            let mut intermediate = intermediate.as_mat_mut();
+            for (intermediate, rhs) in intermediate.rb_mut().col_iter_mut().zip(rhs.col_iter()) {
+                seq_sparse_dense(
+                    intermediate.as_mat_mut(),
+                    Accum::Replace,
+                    mat,
+                    rhs.as_mat(),
+                    alpha.clone(),
+                    par,
+                );
+            }
+            seq_dense_sparse(
+                dst.transpose_mut(),
+                beta,
+                intermediate.rb().transpose(),
+                mat,
+                T::one_impl(),
+                par,
+            );
+        }
+        Par::Rayon(n_threads) => {
+            let n_threads = n_threads.get();
+            for (dst, rhs) in dst.col_iter_mut().zip(rhs.col_iter()) {
+                par_ata_matvec_two_pass(dst, beta, mat, rhs, &alpha, n_threads, strategy, stack);
+            }
+        }
+    }
+}
+
+/// `dst = beta * dst + alpha * A A^T rhs` for each column of `rhs`
+pub fn aat_matmul<I: Index, T: ComplexField>(
+    dst: MatMut<'_, T>,
+    beta: Accum,
+    mat: SparseColMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    alpha: T,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    match par {
+        Par::Seq => {
+            let (mut intermediate, _) = temp_mat_zeroed::<T, _, _>(mat.ncols(), rhs.ncols(), stack);
+            let mut intermediate = intermediate.as_mat_mut();
+            seq_dense_sparse(
+                intermediate.rb_mut().transpose_mut(),
+                Accum::Replace,
+                rhs.transpose(),
+                mat,
+                alpha,
+                par,
+            );
+            seq_sparse_dense(dst, beta, mat, intermediate.rb(), T::one_impl(), par);
+        }
+        Par::Rayon(n_threads) => {
+            let n_threads = n_threads.get();
+            for (dst, rhs) in dst.col_iter_mut().zip(rhs.col_iter()) {
+                par_aat_matvec(dst, beta, mat, rhs, &alpha, n_threads, strategy, stack);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Residual `r = b - A x` fused with `||r||^2` and optionally `<r, z>`.
+//!
+//! This is the `simple` kernel with `alpha = -1`: the scatter pass accumulates `-A x` into the
+//! per-thread workspaces and the reduction sweep adds `b`, writes `r` and accumulates the norm and
+//! dot product partials while each block of `r` is still in cache.
+use std::thread;
+
+use faer::{
+    Accum, ColMut, ColRef, Index, Par,
+    col::AsColMut,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    sparse::{SparseColMatRef, linalg::matmul::sparse_dense_matmul as seq_sparse_dense},
+    traits::{
+        AddByRef, ComplexField,
+        math_utils::{abs2, conj, neg, one, zero},
+    },
+};
+
+use crate::sparse_dense_impl::simple::{reduce_workspaces_with, scatter_thread_range};
+use crate::spmv_drivers::SpMvStrategy;
+
+/// Reductions computed alongside a residual
+#[derive(Clone, Debug)]
+pub struct ResidualNorms<T: ComplexField> {
+    /// `||r||_2^2`
+    pub norm_sq: T::Real,
+    /// `<r, z> = sum_i conj(r_i) z_i`, if a `z` was given
+    pub dot: Option<T>,
+}
+
+/// Per chunk partials of the reduction sweep
+struct Partials<T: ComplexField> {
+    norm_sq: T::Real,
+    dot: T,
+}
+
+impl<T: ComplexField> Default for Partials<T> {
+    fn default() -> Self {
+        Self {
+            norm_sq: zero(),
+            dot: zero(),
+        }
+    }
+}
+
+pub fn residual_scratch<I: Index, T: ComplexField>(
+    lhs: SparseColMatRef<'_, I, T>,
+    par: Par,
+) -> StackReq {
+    match par {
+        Par::Seq => StackReq::empty(),
+        Par::Rayon(n_threads) => temp_mat_scratch::<T>(lhs.nrows(), n_threads.get()),
+    }
+}
+
+/// `dst = b - lhs * rhs`, returning `||dst||^2` and `<dst, z>`
+pub fn par_residual<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    z: Option<ColRef<'_, T>>,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> ResidualNorms<T> {
+    let m = lhs.nrows();
+    let neg_one = neg(&one::<T>());
+
+    let (mut work, _) = temp_mat_zeroed::<T, _, _>(m, n_threads, stack);
+    let work = work.as_mat_mut();
+    let work = work.rb();
+
+    thread::scope(|s| {
+        for tid in 0..n_threads {
+            let neg_one = &neg_one;
+            s.spawn(move || {
+                // SAFETY each thread gets its own workspace vector to be summed when all complete
+                let mut work =
+                    unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
+                scatter_thread_range(tid, lhs, rhs, neg_one, strategy, work.as_col_mut());
+            });
+        }
+    });
+
+    let dst = dst.rb();
+    let partials =
+        reduce_workspaces_with(strategy, work, |row_start, sums, acc: &mut Partials<T>| {
+            // SAFETY: every row is handed to exactly one call of the closure
+            let mut dst = unsafe { dst.const_cast() };
+            for (i, neg_ax) in (row_start..).zip(sums) {
+                let r_i = b[i].add_by_ref(neg_ax);
+                acc.norm_sq = acc.norm_sq.add_by_ref(&abs2(&r_i));
+                if let Some(z) = z {
+                    acc.dot = acc.dot.add_by_ref(&conj(&r_i).mul_by_ref(&z[i]));
+                }
+                dst[i] = r_i;
+            }
+        });
+
+    let mut norms = ResidualNorms::<T> {
+        norm_sq: zero(),
+        dot: z.map(|_| zero()),
+    };
+    for partial in partials {
+        norms.norm_sq = norms.norm_sq.add_by_ref(&partial.norm_sq);
+        if let Some(dot) = norms.dot.as_mut() {
+            *dot = dot.add_by_ref(&partial.dot);
+        }
+    }
+    norms
+}
+
+/// `dst = b - lhs * rhs`, returning `||dst||^2` and `<dst, z>`
+pub fn residual<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    z: Option<ColRef<'_, T>>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> ResidualNorms<T> {
+    match par {
+        Par::Seq => {
+            let mut dst = dst;
+            dst.copy_from(b);
+            seq_sparse_dense(
+                dst.rb_mut().as_mat_mut(),
+                Accum::Add,
+                lhs,
+                rhs.as_mat(),
+                neg(&one::<T>()),
+                par,
+            );
+
+            let mut norms = ResidualNorms::<T> {
+                norm_sq: zero(),
+                dot: z.map(|_| zero()),
+            };
+            for (i, r_i) in dst.iter().enumerate() {
+                norms.norm_sq = norms.norm_sq.add_by_ref(&abs2(r_i));
+                if let (Some(dot), Some(z)) = (norms.dot.as_mut(), z) {
+                    *dot = dot.add_by_ref(&conj(r_i).mul_by_ref(&z[i]));
+                }
+            }
+            norms
+        }
+        Par::Rayon(n_threads) => {
+            par_residual(dst, b, lhs, rhs, z, n_threads.get(), strategy, stack)
+        }
+    }
+}
@@ -1,4 +1,23 @@
+#![allow(clippy::too_many_arguments)]
+
+pub mod analysis;
+pub mod cost_model;
 pub mod dense_sparse_impl;
+pub mod fused;
+pub mod numa;
+pub mod operator;
+pub mod plan_cache;
+pub mod plan_io;
+pub mod reorder;
+pub mod schedule;
+pub mod smoothers;
+pub mod solvers;
 pub mod sparse_dense_impl;
+pub mod spectral;
+pub mod spgemm;
+pub mod spmspv;
 pub mod spmv_drivers;
 pub mod test_utils;
+pub mod transpose;
+pub mod triangular;
+mod vector_ops;
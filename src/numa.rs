@@ -0,0 +1,213 @@
+//! NUMA-aware first-touch placement.
+//!
+//! Linux backs a page with memory on the node of the CPU that first writes to it. Matrices built
+//! by the main thread therefore live on a single node and every worker on the other sockets reads
+//! its `thread_indptrs` range remotely. The copies made here are written by workers pinned the
+//! same way `merge` and `buffer_foreign` pin theirs (thread `tid` on core `tid`), so each range
+//! lands on the node of the thread that reads it.
+//!
+//! Workspaces are not placed: the `simple` and fused kernels spawn unpinned workers and reduce on
+//! the rayon pool, so no thread is tied to the node its workspace column would be touched on.
+//!
+//! On machines with a single node (or without `/sys` topology) there is nothing to gain and the
+//! policy functions fall back to plain copies.
+use std::fs;
+use std::thread;
+
+use faer::{
+    Index, Par,
+    sparse::{SparseColMat, SparseColMatRef, SymbolicSparseColMat},
+    traits::ComplexField,
+};
+
+use crate::spmv_drivers::SpMvStrategy;
+
+const SYS_NODE_DIR: &str = "/sys/devices/system/node";
+
+/// CPUs of every NUMA node, as listed in `/sys/devices/system/node/node*/cpulist`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumaTopology {
+    pub node_cpus: Vec<Vec<usize>>,
+}
+
+impl NumaTopology {
+    /// Read the topology from `/sys`, `None` if it isn't available
+    pub fn detect() -> Option<Self> {
+        let mut nodes = Vec::new();
+        for entry in fs::read_dir(SYS_NODE_DIR).ok()? {
+            let entry = entry.ok()?;
+            let name = entry.file_name();
+            let Some(node) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            let cpulist = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            nodes.push((node, parse_cpulist(&cpulist)?));
+        }
+        if nodes.is_empty() {
+            return None;
+        }
+        nodes.sort_unstable();
+        Some(Self {
+            node_cpus: nodes.into_iter().map(|(_, cpus)| cpus).collect(),
+        })
+    }
+
+    pub fn n_nodes(&self) -> usize {
+        self.node_cpus.len()
+    }
+
+    pub fn node_of_cpu(&self, cpu: usize) -> Option<usize> {
+        self.node_cpus.iter().position(|cpus| cpus.contains(&cpu))
+    }
+}
+
+/// Parse a kernel cpu list such as `0-3,8-11,16`
+pub fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().ok()?;
+                let last: usize = last.parse().ok()?;
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Whether first-touch placement can make a difference on this machine
+pub fn is_numa() -> bool {
+    NumaTopology::detect().is_some_and(|topology| topology.n_nodes() > 1)
+}
+
+/// Run `f(tid, work[tid])` on a worker per item of `work`, pinned like the `merge` /
+/// `buffer_foreign` workers. Threads beyond the number of cores wrap around, and run unpinned if
+/// the core ids are unknown.
+fn for_each_pinned<W: Send, F: Fn(usize, W) + Sync>(work: Vec<W>, f: F) {
+    let core_ids = core_affinity::get_core_ids().unwrap_or_default();
+    thread::scope(|s| {
+        for (tid, work) in work.into_iter().enumerate() {
+            let core_id = (!core_ids.is_empty()).then(|| core_ids[tid % core_ids.len()]);
+            let f = &f;
+            s.spawn(move || {
+                if let Some(core_id) = core_id {
+                    let res = core_affinity::set_for_current(core_id);
+                    debug_assert!(res);
+                }
+                f(tid, work);
+            });
+        }
+    });
+}
+
+/// Split `slice` at the (sorted) `bounds`, the first chunk starts at 0 and the last one ends at
+/// `slice.len()`
+fn split_at_bounds<'a, T>(mut slice: &'a mut [T], bounds: &[usize]) -> Vec<&'a mut [T]> {
+    let mut chunks = Vec::with_capacity(bounds.len() + 1);
+    let mut offset = 0;
+    for &bound in bounds {
+        let (chunk, rest) = slice.split_at_mut(bound - offset);
+        chunks.push(chunk);
+        slice = rest;
+        offset = bound;
+    }
+    chunks.push(slice);
+    chunks
+}
+
+fn plain_copy<I: Index, T: ComplexField>(mat: SparseColMatRef<'_, I, T>) -> SparseColMat<I, T> {
+    let (symbolic, values) = mat.parts();
+    SparseColMat::new(symbolic.to_owned().unwrap(), values.to_vec())
+}
+
+/// Copy `mat` so that every thread's `thread_indptrs` range of the row indices and values (and
+/// its column pointers) is first written by the pinned worker that will read it. `strategy` must
+/// be built from `mat.symbolic()` with `par`.
+pub fn pinned_first_touch_copy<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    strategy: &SpMvStrategy,
+    par: Par,
+) -> SparseColMat<I, T> {
+    let (symbolic, values) = mat.parts();
+    assert!(
+        symbolic.col_nnz().is_none(),
+        "first-touch copy requires a compressed matrix"
+    );
+    let n_threads = match par {
+        Par::Seq => return plain_copy(mat),
+        Par::Rayon(n_threads) => n_threads.get(),
+    };
+    assert_eq!(strategy.thread_cols.len(), n_threads + 1);
+    let m = mat.nrows();
+    let n = mat.ncols();
+    let nnz = symbolic.compute_nnz();
+    let col_ptr = symbolic.col_ptr();
+    let row_idx = symbolic.row_idx();
+
+    // `with_capacity` only reserves address space, nothing is touched until the workers write
+    let mut dst_col_ptr: Vec<I> = Vec::with_capacity(n + 1);
+    let mut dst_row_idx: Vec<I> = Vec::with_capacity(nnz);
+    let mut dst_values: Vec<T> = Vec::with_capacity(nnz);
+
+    let col_bounds: Vec<usize> = strategy.thread_cols[1..n_threads]
+        .iter()
+        .map(|col| col + 1)
+        .collect();
+    let idx_bounds = &strategy.thread_indptrs[1..n_threads];
+    let col_ptr_chunks =
+        split_at_bounds(&mut dst_col_ptr.spare_capacity_mut()[..n + 1], &col_bounds);
+    let row_idx_chunks = split_at_bounds(&mut dst_row_idx.spare_capacity_mut()[..nnz], idx_bounds);
+    let values_chunks = split_at_bounds(&mut dst_values.spare_capacity_mut()[..nnz], idx_bounds);
+
+    let chunks: Vec<_> = col_ptr_chunks
+        .into_iter()
+        .zip(row_idx_chunks)
+        .zip(values_chunks)
+        .collect();
+    for_each_pinned(
+        chunks,
+        |tid, ((col_ptr_chunk, row_idx_chunk), values_chunk)| {
+            let col_start = if tid == 0 { 0 } else { col_bounds[tid - 1] };
+            for (dst, src) in col_ptr_chunk.iter_mut().zip(&col_ptr[col_start..]) {
+                dst.write(*src);
+            }
+            let idx_start = strategy.thread_indptrs[tid];
+            for (dst, src) in row_idx_chunk.iter_mut().zip(&row_idx[idx_start..]) {
+                dst.write(*src);
+            }
+            for (dst, src) in values_chunk.iter_mut().zip(&values[idx_start..]) {
+                dst.write(src.clone());
+            }
+        },
+    );
+
+    // SAFETY: the chunks partition `0..n + 1` and `0..nnz` and every entry was written above
+    unsafe {
+        dst_col_ptr.set_len(n + 1);
+        dst_row_idx.set_len(nnz);
+        dst_values.set_len(nnz);
+    }
+    // SAFETY: exact copy of a valid matrix
+    let symbolic =
+        unsafe { SymbolicSparseColMat::new_unchecked(m, n, dst_col_ptr, None, dst_row_idx) };
+    SparseColMat::new(symbolic, dst_values)
+}
+
+/// `pinned_first_touch_copy` on NUMA machines, a plain copy otherwise
+pub fn first_touch_copy<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    strategy: &SpMvStrategy,
+    par: Par,
+) -> SparseColMat<I, T> {
+    if is_numa() {
+        pinned_first_touch_copy(mat, strategy, par)
+    } else {
+        plain_copy(mat)
+    }
+}
@@ -0,0 +1,372 @@
+//! Lazy sums, products and shifts of operators.
+//!
+//! A composite borrows its operands and applies them one after the other, so `P^T A P` costs three
+//! parallel SpMVs instead of a sparse triple product. The intermediate vectors come from the stack,
+//! which makes `scratch_req` of the composite the single requirement of the whole expression:
+//! the temporaries of a step and everything its operands need below them. The vector updates in
+//! between run on the fixed blocks of the solvers, and `apply_dot` folds the dot product into the
+//! last of them, or into the last operand's own `apply_dot` for products.
+use faer::{
+    ColMut, ColRef, Par,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    traits::{
+        ComplexField,
+        math_utils::{conj, one},
+    },
+};
+
+use super::LinearOperator;
+use crate::vector_ops::{Sum, for_each_block};
+
+/// `A^T`
+pub struct TransposedOperator<'a, T: ComplexField> {
+    op: &'a dyn LinearOperator<T>,
+}
+
+impl<'a, T: ComplexField> TransposedOperator<'a, T> {
+    pub fn new(op: &'a dyn LinearOperator<T>) -> Self {
+        Self { op }
+    }
+}
+
+impl<T: ComplexField> LinearOperator<T> for TransposedOperator<'_, T> {
+    fn nrows(&self) -> usize {
+        self.op.ncols()
+    }
+
+    fn ncols(&self) -> usize {
+        self.op.nrows()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        self.op.scratch_req()
+    }
+
+    fn par(&self) -> Par {
+        self.op.par()
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.op.apply_transpose(dst, rhs, stack);
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.op.apply(dst, rhs, stack);
+    }
+}
+
+/// `A + B`
+pub struct SumOperator<'a, T: ComplexField> {
+    lhs: &'a dyn LinearOperator<T>,
+    rhs: &'a dyn LinearOperator<T>,
+    par: Par,
+}
+
+impl<'a, T: ComplexField> SumOperator<'a, T> {
+    /// `lhs + rhs`, with the vector updates on `par`
+    pub fn new(lhs: &'a dyn LinearOperator<T>, rhs: &'a dyn LinearOperator<T>, par: Par) -> Self {
+        assert_eq!((lhs.nrows(), lhs.ncols()), (rhs.nrows(), rhs.ncols()));
+        Self { lhs, rhs, par }
+    }
+
+    /// `dst = lhs x + rhs x` with `lhs` and `rhs` applied by `apply`, returning `<dst, v>` if `v`
+    /// is given
+    fn apply_with(
+        &self,
+        dst: ColMut<'_, T>,
+        x: ColRef<'_, T>,
+        v: Option<ColRef<'_, T>>,
+        stack: &mut MemStack,
+        apply: impl Fn(&dyn LinearOperator<T>, ColMut<'_, T>, ColRef<'_, T>, &mut MemStack),
+    ) -> T {
+        let mut dst = dst;
+        apply(self.lhs, dst.rb_mut(), x, stack);
+        let (mut tmp, stack) = temp_mat_zeroed::<T, _, _>(dst.nrows(), 1, stack);
+        let mut tmp = tmp.as_mat_mut().col_mut(0);
+        apply(self.rhs, tmp.rb_mut(), x, stack);
+        let tmp = tmp.rb();
+        update(dst, v, self.par, |i, dst_i| dst_i.add_by_ref(&tmp[i]))
+    }
+}
+
+impl<T: ComplexField> LinearOperator<T> for SumOperator<'_, T> {
+    fn nrows(&self) -> usize {
+        self.lhs.nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.lhs.ncols()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        let tmp = temp_mat_scratch::<T>(self.nrows(), 1).or(temp_mat_scratch::<T>(self.ncols(), 1));
+        tmp.and(self.lhs.scratch_req().or(self.rhs.scratch_req()))
+    }
+
+    fn par(&self) -> Par {
+        self.par
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.apply_with(dst, rhs, None, stack, |op, dst, rhs, stack| {
+            op.apply(dst, rhs, stack)
+        });
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.apply_with(dst, rhs, None, stack, |op, dst, rhs, stack| {
+            op.apply_transpose(dst, rhs, stack)
+        });
+    }
+
+    fn apply_dot(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        v: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) -> T {
+        self.apply_with(dst, rhs, Some(v), stack, |op, dst, rhs, stack| {
+            op.apply(dst, rhs, stack)
+        })
+    }
+}
+
+/// `A B`
+pub struct ProductOperator<'a, T: ComplexField> {
+    lhs: &'a dyn LinearOperator<T>,
+    rhs: &'a dyn LinearOperator<T>,
+}
+
+impl<'a, T: ComplexField> ProductOperator<'a, T> {
+    /// `lhs rhs`, applying `rhs` first
+    pub fn new(lhs: &'a dyn LinearOperator<T>, rhs: &'a dyn LinearOperator<T>) -> Self {
+        assert_eq!(lhs.ncols(), rhs.nrows());
+        Self { lhs, rhs }
+    }
+}
+
+impl<T: ComplexField> LinearOperator<T> for ProductOperator<'_, T> {
+    fn nrows(&self) -> usize {
+        self.lhs.nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.rhs.ncols()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        temp_mat_scratch::<T>(self.lhs.ncols(), 1)
+            .and(self.lhs.scratch_req().or(self.rhs.scratch_req()))
+    }
+
+    fn par(&self) -> Par {
+        self.lhs.par()
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        let (mut tmp, stack) = temp_mat_zeroed::<T, _, _>(self.lhs.ncols(), 1, stack);
+        let mut tmp = tmp.as_mat_mut().col_mut(0);
+        self.rhs.apply(tmp.rb_mut(), rhs, stack);
+        self.lhs.apply(dst, tmp.rb(), stack);
+    }
+
+    /// `B^T A^T rhs`
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        let (mut tmp, stack) = temp_mat_zeroed::<T, _, _>(self.lhs.ncols(), 1, stack);
+        let mut tmp = tmp.as_mat_mut().col_mut(0);
+        self.lhs.apply_transpose(tmp.rb_mut(), rhs, stack);
+        self.rhs.apply_transpose(dst, tmp.rb(), stack);
+    }
+
+    fn apply_dot(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        v: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) -> T {
+        let (mut tmp, stack) = temp_mat_zeroed::<T, _, _>(self.lhs.ncols(), 1, stack);
+        let mut tmp = tmp.as_mat_mut().col_mut(0);
+        self.rhs.apply(tmp.rb_mut(), rhs, stack);
+        self.lhs.apply_dot(dst, tmp.rb(), v, stack)
+    }
+}
+
+/// `alpha A + sigma I`
+pub struct ScaledOperator<'a, T: ComplexField> {
+    op: &'a dyn LinearOperator<T>,
+    alpha: T,
+    sigma: T,
+    par: Par,
+}
+
+impl<'a, T: ComplexField> ScaledOperator<'a, T> {
+    /// `alpha op + sigma I` for a square `op`, with the vector update on `par`
+    pub fn new(op: &'a dyn LinearOperator<T>, alpha: T, sigma: T, par: Par) -> Self {
+        assert_eq!(op.nrows(), op.ncols());
+        Self {
+            op,
+            alpha,
+            sigma,
+            par,
+        }
+    }
+
+    /// `op + sigma I`
+    pub fn shifted(op: &'a dyn LinearOperator<T>, sigma: T, par: Par) -> Self {
+        Self::new(op, one(), sigma, par)
+    }
+
+    pub fn alpha(&self) -> &T {
+        &self.alpha
+    }
+
+    pub fn sigma(&self) -> &T {
+        &self.sigma
+    }
+
+    /// `dst = alpha dst + sigma x` once `dst` holds `A x` or `A^T x`, returning `<dst, v>` if `v`
+    /// is given
+    fn finish(&self, dst: ColMut<'_, T>, x: ColRef<'_, T>, v: Option<ColRef<'_, T>>) -> T {
+        let (alpha, sigma) = (&self.alpha, &self.sigma);
+        update(dst, v, self.par, |i, dst_i| {
+            alpha.mul_by_ref(dst_i).add_by_ref(&sigma.mul_by_ref(&x[i]))
+        })
+    }
+}
+
+impl<T: ComplexField> LinearOperator<T> for ScaledOperator<'_, T> {
+    fn nrows(&self) -> usize {
+        self.op.nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.op.ncols()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        self.op.scratch_req()
+    }
+
+    fn par(&self) -> Par {
+        self.par
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        let mut dst = dst;
+        self.op.apply(dst.rb_mut(), rhs, stack);
+        self.finish(dst, rhs, None);
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        let mut dst = dst;
+        self.op.apply_transpose(dst.rb_mut(), rhs, stack);
+        self.finish(dst, rhs, None);
+    }
+
+    fn apply_dot(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        v: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) -> T {
+        let mut dst = dst;
+        self.op.apply(dst.rb_mut(), rhs, stack);
+        self.finish(dst, rhs, Some(v))
+    }
+}
+
+/// `P^T A P`, the Galerkin coarse operator of multigrid, applied without forming the triple
+/// product
+pub struct GalerkinOperator<'a, T: ComplexField> {
+    a: &'a dyn LinearOperator<T>,
+    p: &'a dyn LinearOperator<T>,
+}
+
+impl<'a, T: ComplexField> GalerkinOperator<'a, T> {
+    /// `P^T A P` for a square `a` and a prolongation `p` from the coarse to the fine space
+    pub fn new(a: &'a dyn LinearOperator<T>, p: &'a dyn LinearOperator<T>) -> Self {
+        assert_eq!(a.nrows(), a.ncols());
+        assert_eq!(p.nrows(), a.ncols());
+        Self { a, p }
+    }
+
+    /// `dst = P^T op(P rhs)`, with `op` applying `A` or `A^T`
+    fn apply_with(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        stack: &mut MemStack,
+        op: impl FnOnce(ColMut<'_, T>, ColRef<'_, T>, &mut MemStack),
+    ) {
+        let n = self.p.nrows();
+        let (mut fine, stack) = temp_mat_zeroed::<T, _, _>(n, 2, stack);
+        let fine = fine.as_mat_mut();
+        let (p_rhs, a_p_rhs) = fine.split_at_col_mut(1);
+        let (mut p_rhs, mut a_p_rhs) = (p_rhs.col_mut(0), a_p_rhs.col_mut(0));
+        self.p.apply(p_rhs.rb_mut(), rhs, stack);
+        op(a_p_rhs.rb_mut(), p_rhs.rb(), stack);
+        self.p.apply_transpose(dst, a_p_rhs.rb(), stack);
+    }
+}
+
+impl<T: ComplexField> LinearOperator<T> for GalerkinOperator<'_, T> {
+    fn nrows(&self) -> usize {
+        self.p.ncols()
+    }
+
+    fn ncols(&self) -> usize {
+        self.p.ncols()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        temp_mat_scratch::<T>(self.p.nrows(), 2).and(self.a.scratch_req().or(self.p.scratch_req()))
+    }
+
+    fn par(&self) -> Par {
+        self.a.par()
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.apply_with(dst, rhs, stack, |dst, rhs, stack| {
+            self.a.apply(dst, rhs, stack)
+        });
+    }
+
+    /// `P^T A^T P rhs`
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.apply_with(dst, rhs, stack, |dst, rhs, stack| {
+            self.a.apply_transpose(dst, rhs, stack)
+        });
+    }
+}
+
+/// `dst_i = f(i, dst_i)` over the blocks of `par`, returning `<dst, v>` if `v` is given and zero
+/// otherwise
+fn update<T: ComplexField>(
+    dst: ColMut<'_, T>,
+    v: Option<ColRef<'_, T>>,
+    par: Par,
+    f: impl Fn(usize, &T) -> T + Sync,
+) -> T {
+    let dst = dst.rb();
+    Sum::total(for_each_block(
+        dst.nrows(),
+        par,
+        |rows, acc: &mut Sum<T>| {
+            // SAFETY: the blocks are disjoint
+            let mut dst = unsafe { dst.const_cast() };
+            for i in rows {
+                dst[i] = f(i, &dst[i]);
+                if let Some(v) = v {
+                    acc.0 = acc.0.add_by_ref(&conj(&dst[i]).mul_by_ref(&v[i]));
+                }
+            }
+        },
+    ))
+}
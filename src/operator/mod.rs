@@ -0,0 +1,238 @@
+//! Linear operators the solvers are generic over.
+//!
+//! An operator owns its plan, so callers only provide the vectors and a `MemStack` of
+//! `scratch_req` bytes, and code written against the trait runs on any of the kernels through
+//! `SparseOperator::with_backend`. `apply_dot` exists for the kernels that fuse the dot product of
+//! the output into their reduction sweep, which saves CG a pass over the vectors per iteration.
+use faer::{
+    Accum, ColMut, ColRef, Index, MatRef, Par,
+    dyn_stack::{MemStack, StackReq},
+    prelude::{Reborrow, ReborrowMut},
+    sparse::SparseColMatRef,
+    traits::{ComplexField, math_utils::one},
+};
+
+use crate::dense_sparse_impl::{dense_sparse_scratch, par_dense_sparse};
+use crate::reorder::permuted::PermutedOperator;
+use crate::smoothers::jacobi::JacobiSmoother;
+use crate::sparse_dense_impl::simple::par_sparse_dense_dot;
+use crate::spmv_drivers::{SpMvBackend, SpMvStrategy, dense_sparse_matmul, sparse_dense_matmul};
+use crate::vector_ops::dot;
+
+pub mod compose;
+
+pub trait LinearOperator<T: ComplexField> {
+    fn nrows(&self) -> usize;
+
+    fn ncols(&self) -> usize;
+
+    /// Scratch space needed by `apply`, `apply_transpose` and `apply_dot`
+    fn scratch_req(&self) -> StackReq;
+
+    /// Parallelism of the vector passes around the kernels, such as the dot product of the default
+    /// `apply_dot`
+    fn par(&self) -> Par {
+        Par::Seq
+    }
+
+    /// `dst = A rhs`
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack);
+
+    /// `dst = A^T rhs`, the transpose without conjugation
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack);
+
+    /// `dst = A rhs`, returning `<dst, v> = sum_i conj(dst_i) v_i`
+    fn apply_dot(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        v: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) -> T {
+        let mut dst = dst;
+        self.apply(dst.rb_mut(), rhs, stack);
+        dot(dst.rb(), v, self.par())
+    }
+}
+
+/// A sparse matrix with the plan of one of the `sparse_dense_impl` kernels. `apply_transpose` runs
+/// the race free `dense_sparse_impl` kernel on the same plan.
+pub struct SparseOperator<'a, I: Index, T: ComplexField> {
+    mat: SparseColMatRef<'a, I, T>,
+    par: Par,
+    backend: SpMvBackend,
+    strategy: SpMvStrategy,
+}
+
+impl<'a, I: Index, T: ComplexField> SparseOperator<'a, I, T> {
+    /// Operator running the `simple` kernel, which also fuses `apply_dot`
+    pub fn new(mat: SparseColMatRef<'a, I, T>, par: Par) -> Self {
+        Self::with_backend(mat, SpMvBackend::Simple, par)
+    }
+
+    pub fn with_backend(mat: SparseColMatRef<'a, I, T>, backend: SpMvBackend, par: Par) -> Self {
+        Self {
+            mat,
+            par,
+            backend,
+            strategy: SpMvStrategy::new(mat.symbolic(), par),
+        }
+    }
+
+    pub fn matrix(&self) -> SparseColMatRef<'a, I, T> {
+        self.mat
+    }
+
+    pub fn par(&self) -> Par {
+        self.par
+    }
+
+    pub fn backend(&self) -> SpMvBackend {
+        self.backend
+    }
+
+    pub fn strategy(&self) -> &SpMvStrategy {
+        &self.strategy
+    }
+}
+
+impl<I: Index, T: ComplexField> LinearOperator<T> for SparseOperator<'_, I, T> {
+    fn nrows(&self) -> usize {
+        self.mat.nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.mat.ncols()
+    }
+
+    fn par(&self) -> Par {
+        self.par
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        // the scratch functions only look at the number of vectors
+        let rhs = MatRef::<T>::from_column_major_slice(&[], 0, 1);
+        let lhs = MatRef::<T>::from_row_major_slice(&[], 1, 0);
+        self.backend
+            .scratch(self.mat, rhs, &self.strategy, self.par)
+            .or(dense_sparse_scratch(
+                lhs,
+                self.mat,
+                &self.strategy,
+                self.par,
+            ))
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        sparse_dense_matmul(
+            dst.as_mat_mut(),
+            Accum::Replace,
+            self.mat,
+            rhs.as_mat(),
+            one(),
+            self.par,
+            &self.strategy,
+            stack,
+            Some(self.backend.par_impl()),
+        );
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        dense_sparse_matmul(
+            dst.as_mat_mut().transpose_mut(),
+            Accum::Replace,
+            rhs.as_mat().transpose(),
+            self.mat,
+            one(),
+            self.par,
+            &self.strategy,
+            stack,
+            Some(par_dense_sparse),
+        );
+    }
+
+    fn apply_dot(
+        &self,
+        dst: ColMut<'_, T>,
+        rhs: ColRef<'_, T>,
+        v: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) -> T {
+        match (self.par, self.backend) {
+            (Par::Rayon(n_threads), SpMvBackend::Simple) => par_sparse_dense_dot(
+                dst,
+                Accum::Replace,
+                self.mat,
+                rhs,
+                &one(),
+                v,
+                n_threads.get(),
+                &self.strategy,
+                stack,
+            ),
+            _ => {
+                let mut dst = dst;
+                self.apply(dst.rb_mut(), rhs, stack);
+                dot(dst.rb(), v, self.par)
+            }
+        }
+    }
+}
+
+impl<I: Index, T: ComplexField> LinearOperator<T> for PermutedOperator<I, T> {
+    fn nrows(&self) -> usize {
+        self.nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.ncols()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        self.scratch()
+    }
+
+    fn par(&self) -> Par {
+        self.par()
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        PermutedOperator::apply(self, dst, Accum::Replace, rhs, &one(), stack);
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        PermutedOperator::apply_transpose(self, dst, Accum::Replace, rhs, &one(), stack);
+    }
+}
+
+/// The scaled inverse diagonal `omega D^-1`, i.e. one sweep from a zero initial guess, which makes
+/// the smoother a (weighted) Jacobi preconditioner
+impl<T: ComplexField> LinearOperator<T> for JacobiSmoother<T> {
+    fn nrows(&self) -> usize {
+        self.scaled_inv_diag().nrows()
+    }
+
+    fn ncols(&self) -> usize {
+        self.scaled_inv_diag().nrows()
+    }
+
+    fn scratch_req(&self) -> StackReq {
+        StackReq::empty()
+    }
+
+    fn par(&self) -> Par {
+        self.par()
+    }
+
+    fn apply(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        let _ = stack;
+        let mut dst = dst;
+        for (i, d_i) in self.scaled_inv_diag().iter().enumerate() {
+            dst[i] = d_i.mul_by_ref(&rhs[i]);
+        }
+    }
+
+    fn apply_transpose(&self, dst: ColMut<'_, T>, rhs: ColRef<'_, T>, stack: &mut MemStack) {
+        self.apply(dst, rhs, stack);
+    }
+}
@@ -0,0 +1,123 @@
+//! Reuse of SpMV plans across matrices sharing a sparsity pattern.
+//!
+//! Nonlinear and time-stepping solvers assemble many matrices with the same pattern, so the
+//! `SpMvStrategy` built for the first one is valid for all of them. `PlanCache` keys plans by a
+//! fingerprint of the pattern and hands out shared `Arc` plans.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use faer::{Index, Par, sparse::SymbolicSparseColMatRef};
+
+use crate::spmv_drivers::SpMvStrategy;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64 bit FNV-1a, deterministic across runs and platforms
+#[derive(Clone, Copy, Debug)]
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Identifies a sparsity pattern: the dimensions, the nnz and a hash of the column pointers and
+/// row indices
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PatternFingerprint {
+    pub nrows: usize,
+    pub ncols: usize,
+    pub nnz: usize,
+    pub hash: u64,
+}
+
+impl PatternFingerprint {
+    pub fn new<I: Index>(mat: SymbolicSparseColMatRef<'_, I>) -> Self {
+        let mut hasher = Fnv1a::new();
+        for j in 0..mat.ncols() {
+            let col_range = mat.col_range(j);
+            hasher.write_u64(col_range.len() as u64);
+            for i in mat.row_idx_of_col(j) {
+                hasher.write_u64(i as u64);
+            }
+        }
+        Self {
+            nrows: mat.nrows(),
+            ncols: mat.ncols(),
+            nnz: mat.compute_nnz(),
+            hash: hasher.0,
+        }
+    }
+}
+
+/// `Par` isn't hashable, `Seq` plans are empty and differ from single thread plans
+fn par_key(par: Par) -> usize {
+    match par {
+        Par::Seq => 0,
+        Par::Rayon(n_threads) => n_threads.get(),
+    }
+}
+
+/// Thread-safe cache of `SpMvStrategy` plans keyed by pattern and thread count
+#[derive(Default)]
+pub struct PlanCache {
+    plans: Mutex<HashMap<(PatternFingerprint, usize), Arc<SpMvStrategy>>>,
+}
+
+impl PlanCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The plan for `mat` and `par`, built on the first request for this pattern
+    pub fn get<I: Index>(
+        &self,
+        mat: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Arc<SpMvStrategy> {
+        self.get_with_fingerprint(PatternFingerprint::new(mat), mat, par)
+    }
+
+    /// `get` with a fingerprint computed by the caller, which must belong to `mat`
+    pub fn get_with_fingerprint<I: Index>(
+        &self,
+        fingerprint: PatternFingerprint,
+        mat: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Arc<SpMvStrategy> {
+        let key = (fingerprint, par_key(par));
+        if let Some(plan) = self.plans.lock().unwrap().get(&key) {
+            return plan.clone();
+        }
+        // plan outside the lock, concurrent requests for a new key may both build it but all of them
+        // get the plan inserted first
+        let plan = Arc::new(SpMvStrategy::new(mat, par));
+        self.plans
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(plan)
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.plans.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.plans.lock().unwrap().clear();
+    }
+}
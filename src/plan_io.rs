@@ -0,0 +1,322 @@
+//! Versioned binary files for SpMV plans.
+//!
+//! A plan file holds the `SpMvStrategy` for one thread count, optionally the `Reordering` applied
+//! before planning (the strategy then partitions the reordered matrix), and the
+//! `PatternFingerprint` of the matrix as given to the planner, before any reordering. Loading
+//! checks the fingerprint so a plan is never applied to a matrix whose pattern changed since it
+//! was saved, and checks the strategy against the (reordered) pattern, since the kernels trust
+//! it for their unchecked writes.
+//!
+//! Layout, all integers little-endian:
+//!
+//! | field                          | type                          |
+//! |--------------------------------|-------------------------------|
+//! | magic `b"PMVPLAN\0"`           | `[u8; 8]`                     |
+//! | format version                 | `u32`                         |
+//! | nrows, ncols, nnz, hash        | `u64` x 4                     |
+//! | thread count (0 for `Seq`)     | `u64`                         |
+//! | `thread_cols`                  | `u64` length + `u64` entries  |
+//! | `thread_indptrs`               | `u64` length + `u64` entries  |
+//! | `thread_rows`                  | `u64` length + `u64` entries  |
+//! | has reordering                 | `u8`                          |
+//! | row / col forward, part ptrs   | `u64` length + `u64` entries  |
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use faer::{Index, Par, perm::Perm, sparse::SymbolicSparseColMatRef};
+
+use crate::plan_cache::PatternFingerprint;
+use crate::reorder::{Reordering, permute_pattern};
+use crate::spmv_drivers::SpMvStrategy;
+
+pub const PLAN_MAGIC: [u8; 8] = *b"PMVPLAN\0";
+pub const PLAN_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum PlanIoError {
+    Io(io::Error),
+    /// Not a plan file
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The plan was saved for a different sparsity pattern
+    FingerprintMismatch {
+        expected: PatternFingerprint,
+        found: PatternFingerprint,
+    },
+    /// The plan was saved for a different thread count
+    ParMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// The file is well formed but its contents are inconsistent
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for PlanIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "plan io error: {err}"),
+            Self::BadMagic => write!(f, "not an SpMV plan file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported plan format version {version}, expected {PLAN_FORMAT_VERSION}"
+            ),
+            Self::FingerprintMismatch { expected, found } => write!(
+                f,
+                "plan was saved for a different matrix: expected {expected:?}, found {found:?}"
+            ),
+            Self::ParMismatch { expected, found } => {
+                write!(f, "plan was saved for {found} threads, expected {expected}")
+            }
+            Self::Corrupt(what) => write!(f, "corrupt plan file: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for PlanIoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlanIoError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Everything stored in a plan file
+pub struct SavedPlan<I: Index> {
+    pub fingerprint: PatternFingerprint,
+    /// `Par::Seq` is stored as 0 threads
+    pub par: Par,
+    pub strategy: SpMvStrategy,
+    pub reordering: Option<Reordering<I>>,
+}
+
+impl<I: Index> SavedPlan<I> {
+    /// Reject the plan if it doesn't belong to `mat` and `par`, or if its strategy does not fit
+    /// the (reordered) pattern
+    pub fn check(&self, mat: SymbolicSparseColMatRef<'_, I>, par: Par) -> Result<(), PlanIoError> {
+        let expected = PatternFingerprint::new(mat);
+        if expected != self.fingerprint {
+            return Err(PlanIoError::FingerprintMismatch {
+                expected,
+                found: self.fingerprint,
+            });
+        }
+        let (expected, found) = (n_threads(par), n_threads(self.par));
+        if expected != found {
+            return Err(PlanIoError::ParMismatch { expected, found });
+        }
+        let checked = match &self.reordering {
+            None => self.strategy.check_pattern(mat, par),
+            Some(reordering) => {
+                let (reordered, _) = permute_pattern(
+                    mat,
+                    reordering.row_perm.as_ref(),
+                    reordering.col_perm.as_ref(),
+                );
+                self.strategy.check_pattern(reordered.as_ref(), par)
+            }
+        };
+        checked.map_err(PlanIoError::Corrupt)
+    }
+}
+
+fn n_threads(par: Par) -> usize {
+    match par {
+        Par::Seq => 0,
+        Par::Rayon(n_threads) => n_threads.get(),
+    }
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: usize) -> io::Result<()> {
+    writer.write_all(&(value as u64).to_le_bytes())
+}
+
+fn write_usizes<W: Write>(
+    writer: &mut W,
+    values: impl ExactSizeIterator<Item = usize>,
+) -> io::Result<()> {
+    write_u64(writer, values.len())?;
+    for value in values {
+        write_u64(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, PlanIoError> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize, PlanIoError> {
+    usize::try_from(read_u64(reader)?).map_err(|_| PlanIoError::Corrupt("value overflows usize"))
+}
+
+fn read_usizes<R: Read>(reader: &mut R) -> Result<Vec<usize>, PlanIoError> {
+    let len = read_usize(reader)?;
+    // grow as entries arrive instead of trusting the length for the allocation
+    let mut values = Vec::with_capacity(len.min(1 << 16));
+    for _ in 0..len {
+        values.push(read_usize(reader)?);
+    }
+    Ok(values)
+}
+
+fn read_perm<I: Index, R: Read>(reader: &mut R, len: usize) -> Result<Perm<I>, PlanIoError> {
+    let forward = read_usizes(reader)?;
+    if forward.len() != len || I::truncate(len).zx() != len {
+        return Err(PlanIoError::Corrupt("permutation length"));
+    }
+    let mut inverse = vec![usize::MAX; len];
+    for (new, &old) in forward.iter().enumerate() {
+        if old >= len || inverse[old] != usize::MAX {
+            return Err(PlanIoError::Corrupt("permutation is not a bijection"));
+        }
+        inverse[old] = new;
+    }
+    let to_index = |values: Vec<usize>| values.into_iter().map(I::truncate).collect();
+    Ok(Perm::new_checked(to_index(forward), to_index(inverse), len))
+}
+
+/// Write a plan for the matrix with pattern `fingerprint`. With a `reordering`, `strategy` is the
+/// plan of the reordered matrix.
+pub fn write_plan<I: Index, W: Write>(
+    writer: &mut W,
+    fingerprint: PatternFingerprint,
+    par: Par,
+    strategy: &SpMvStrategy,
+    reordering: Option<&Reordering<I>>,
+) -> io::Result<()> {
+    writer.write_all(&PLAN_MAGIC)?;
+    writer.write_all(&PLAN_FORMAT_VERSION.to_le_bytes())?;
+    write_u64(writer, fingerprint.nrows)?;
+    write_u64(writer, fingerprint.ncols)?;
+    write_u64(writer, fingerprint.nnz)?;
+    writer.write_all(&fingerprint.hash.to_le_bytes())?;
+    write_u64(writer, n_threads(par))?;
+    write_usizes(writer, strategy.thread_cols.iter().copied())?;
+    write_usizes(writer, strategy.thread_indptrs.iter().copied())?;
+    write_usizes(writer, strategy.thread_rows.iter().copied())?;
+    match reordering {
+        None => writer.write_all(&[0]),
+        Some(reordering) => {
+            writer.write_all(&[1])?;
+            let (row_forward, _) = reordering.row_perm.as_ref().arrays();
+            let (col_forward, _) = reordering.col_perm.as_ref().arrays();
+            write_usizes(writer, row_forward.iter().map(|i| i.zx()))?;
+            write_usizes(writer, col_forward.iter().map(|j| j.zx()))?;
+            write_usizes(writer, reordering.row_part_ptr.iter().copied())?;
+            write_usizes(writer, reordering.col_part_ptr.iter().copied())
+        }
+    }
+}
+
+/// Read a plan without checking it against a matrix, see `SavedPlan::check`
+pub fn read_plan<I: Index, R: Read>(reader: &mut R) -> Result<SavedPlan<I>, PlanIoError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != PLAN_MAGIC {
+        return Err(PlanIoError::BadMagic);
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != PLAN_FORMAT_VERSION {
+        return Err(PlanIoError::UnsupportedVersion(version));
+    }
+
+    let fingerprint = PatternFingerprint {
+        nrows: read_usize(reader)?,
+        ncols: read_usize(reader)?,
+        nnz: read_usize(reader)?,
+        hash: read_u64(reader)?,
+    };
+    let par = match read_usize(reader)? {
+        0 => Par::Seq,
+        n_threads => Par::Rayon(NonZeroUsize::new(n_threads).unwrap()),
+    };
+    let strategy = SpMvStrategy {
+        thread_cols: read_usizes(reader)?,
+        thread_indptrs: read_usizes(reader)?,
+        thread_rows: read_usizes(reader)?,
+    };
+    strategy
+        .check_shape(fingerprint.nrows, fingerprint.ncols, fingerprint.nnz, par)
+        .map_err(PlanIoError::Corrupt)?;
+
+    let mut has_reordering = [0];
+    reader.read_exact(&mut has_reordering)?;
+    let reordering = match has_reordering[0] {
+        0 => None,
+        1 => {
+            let row_perm = read_perm(reader, fingerprint.nrows)?;
+            let col_perm = read_perm(reader, fingerprint.ncols)?;
+            let row_part_ptr = read_usizes(reader)?;
+            let col_part_ptr = read_usizes(reader)?;
+            let parts_fit = |part_ptr: &[usize], len| {
+                part_ptr.first() == Some(&0)
+                    && part_ptr.last() == Some(&len)
+                    && part_ptr.is_sorted()
+            };
+            if !parts_fit(&row_part_ptr, fingerprint.nrows)
+                || !parts_fit(&col_part_ptr, fingerprint.ncols)
+            {
+                return Err(PlanIoError::Corrupt("part pointers do not fit the matrix"));
+            }
+            Some(Reordering {
+                row_perm,
+                col_perm,
+                row_part_ptr,
+                col_part_ptr,
+            })
+        }
+        _ => return Err(PlanIoError::Corrupt("reordering flag")),
+    };
+
+    Ok(SavedPlan {
+        fingerprint,
+        par,
+        strategy,
+        reordering,
+    })
+}
+
+/// Save the plan for `mat` to `path`, `mat` is the matrix before `reordering`
+pub fn save_plan<I: Index>(
+    path: impl AsRef<Path>,
+    mat: SymbolicSparseColMatRef<'_, I>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    reordering: Option<&Reordering<I>>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_plan(
+        &mut writer,
+        PatternFingerprint::new(mat),
+        par,
+        strategy,
+        reordering,
+    )?;
+    writer.flush()
+}
+
+/// Load a plan from `path` and reject it unless it was saved for `mat` and `par`
+pub fn load_plan<I: Index>(
+    path: impl AsRef<Path>,
+    mat: SymbolicSparseColMatRef<'_, I>,
+    par: Par,
+) -> Result<SavedPlan<I>, PlanIoError> {
+    let plan = read_plan(&mut BufReader::new(File::open(path)?))?;
+    plan.check(mat, par)?;
+    Ok(plan)
+}
@@ -0,0 +1,177 @@
+//! Locality metrics of a matrix under a thread partition.
+use std::fmt;
+
+use faer::{Index, Par, sparse::SymbolicSparseColMatRef};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::sparse_dense_impl::buffer_foreign::{B_ROWS, assign_blocks};
+use crate::spmv_drivers::SpMvStrategy;
+
+/// Largest `|i - j|` over the nonzeros `(i, j)`
+pub fn bandwidth<I: Index>(mat: SymbolicSparseColMatRef<'_, I>) -> usize {
+    (0..mat.ncols())
+        .into_par_iter()
+        .map(|j| {
+            mat.row_idx_of_col(j)
+                .map(|i| i.abs_diff(j))
+                .max()
+                .unwrap_or(0)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Size of the lower envelope, `sum_i (i - f_i)` where `f_i` is the first column of row `i`
+/// (clipped to `i`)
+pub fn profile<I: Index>(mat: SymbolicSparseColMatRef<'_, I>) -> usize {
+    let m = mat.nrows();
+    let mut first_col: Vec<usize> = (0..m).collect();
+    for j in 0..mat.ncols() {
+        for i in mat.row_idx_of_col(j) {
+            first_col[i] = first_col[i].min(j);
+        }
+    }
+    first_col.iter().enumerate().map(|(i, f)| i - f).sum()
+}
+
+/// Number of rows between the first and last row written by each thread's nnz slice
+pub fn row_spreads<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+    strategy: &SpMvStrategy,
+) -> Vec<usize> {
+    let n_threads = strategy.thread_cols.len().saturating_sub(1);
+    let row_indices = mat.row_idx();
+    (0..n_threads)
+        .into_par_iter()
+        .map(|tid| {
+            let idx_range = strategy.thread_indptrs[tid]..strategy.thread_indptrs[tid + 1];
+            let rows = row_indices[idx_range].iter().map(|row| row.zx());
+            match (rows.clone().min(), rows.max()) {
+                (Some(first), Some(last)) => 1 + last - first,
+                _ => 0,
+            }
+        })
+        .collect()
+}
+
+/// Structural summary of a matrix under a thread partition
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatrixMetrics {
+    pub bandwidth: usize,
+    pub profile: usize,
+    /// Mean of `row_spreads`, `nrows` for an unpartitioned (sequential) strategy
+    pub avg_row_spread: f64,
+}
+
+impl MatrixMetrics {
+    pub fn new<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, strategy: &SpMvStrategy) -> Self {
+        let spreads = row_spreads(mat, strategy);
+        let avg_row_spread = if spreads.is_empty() {
+            mat.nrows() as f64
+        } else {
+            spreads.iter().sum::<usize>() as f64 / spreads.len() as f64
+        };
+        Self {
+            bandwidth: bandwidth(mat),
+            profile: profile(mat),
+            avg_row_spread,
+        }
+    }
+}
+
+impl fmt::Display for MatrixMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bandwidth {}, profile {}, avg row spread per thread {:.1}",
+            self.bandwidth, self.profile, self.avg_row_spread
+        )
+    }
+}
+
+/// Fraction of the nonzeros whose row is not owned by the thread that processes them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForeignWrites {
+    /// `merge` owns the `thread_rows` slices of the plan
+    pub merge: f64,
+    /// `buffer_foreign` owns the `thread_rows` slices rounded to `B_ROWS` row blocks
+    pub buffer_foreign: f64,
+}
+
+/// Foreign-write fractions of the `merge` and `buffer_foreign` kernels for `mat` under `strategy`
+pub fn foreign_writes<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+    strategy: &SpMvStrategy,
+) -> ForeignWrites {
+    let n_threads = strategy.thread_cols.len().saturating_sub(1);
+    let nnz = mat.compute_nnz();
+    if n_threads == 0 || nnz == 0 {
+        return ForeignWrites {
+            merge: 0.0,
+            buffer_foreign: 0.0,
+        };
+    }
+
+    let m = mat.nrows();
+    let (owner_of_block, _) = assign_blocks(m, B_ROWS, &strategy.thread_rows);
+    let row_indices = mat.row_idx();
+
+    let (merge, buffer_foreign) = (0..n_threads)
+        .into_par_iter()
+        .map(|tid| {
+            let mut merge = 0usize;
+            let mut buffer_foreign = 0usize;
+            let idx_range = strategy.thread_indptrs[tid]..strategy.thread_indptrs[tid + 1];
+            let owned_rows = strategy.thread_row_range(tid);
+            for row in &row_indices[idx_range] {
+                let row = row.zx();
+                merge += !owned_rows.contains(&row) as usize;
+                buffer_foreign += (owner_of_block[row / B_ROWS] != tid) as usize;
+            }
+            (merge, buffer_foreign)
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+    ForeignWrites {
+        merge: merge as f64 / nnz as f64,
+        buffer_foreign: buffer_foreign as f64 / nnz as f64,
+    }
+}
+
+/// Foreign-write fractions before and after a reordering
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForeignWriteReport {
+    pub before: ForeignWrites,
+    pub after: ForeignWrites,
+}
+
+impl ForeignWriteReport {
+    /// Compare `original` and `reordered` with the default nnz partition for `par`
+    pub fn new<I: Index>(
+        original: SymbolicSparseColMatRef<'_, I>,
+        reordered: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Self {
+        Self {
+            before: foreign_writes(original, &SpMvStrategy::new(original, par)),
+            after: foreign_writes(reordered, &SpMvStrategy::new(reordered, par)),
+        }
+    }
+}
+
+impl fmt::Display for ForeignWriteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "merge foreign writes:          {:6.2}% -> {:6.2}%",
+            100.0 * self.before.merge,
+            100.0 * self.after.merge
+        )?;
+        write!(
+            f,
+            "buffer_foreign foreign writes: {:6.2}% -> {:6.2}%",
+            100.0 * self.before.buffer_foreign,
+            100.0 * self.after.buffer_foreign
+        )
+    }
+}
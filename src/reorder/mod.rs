@@ -0,0 +1,132 @@
+//! Reorderings which improve the locality of the parallel SpMV kernels.
+//!
+//! The `merge` and `buffer_foreign` kernels give every thread ownership of a contiguous slice of
+//! the output rows, and every nonzero a thread meets outside its slice has to be merged or shipped
+//! to the owner. A reordering which groups the rows written by the columns of each thread's nnz
+//! slice into that thread's row slice turns those foreign writes into plain owned writes.
+//!
+//! Permutations follow the faer convention: `forward[new] = old`. The reordered matrix is
+//! `B = P A Q^T`, i.e. `B[i, j] = A[p[i], q[j]]`, so `y = A x` is computed as
+//! `P y = B (Q x)`.
+use faer::{
+    Col, ColRef, Index,
+    perm::{Perm, PermRef},
+    sparse::{SparseColMat, SparseColMatRef, SymbolicSparseColMat, SymbolicSparseColMatRef},
+    traits::ComplexField,
+};
+
+pub mod metrics;
+pub mod partition;
+pub mod permuted;
+pub mod rcm;
+
+/// Row and column permutations of a matrix together with the part (thread) boundaries in the
+/// permuted index space
+pub struct Reordering<I: Index> {
+    pub row_perm: Perm<I>,
+    pub col_perm: Perm<I>,
+    /// Permuted rows `row_part_ptr[t]..row_part_ptr[t + 1]` belong to part `t`
+    pub row_part_ptr: Vec<usize>,
+    /// Permuted columns `col_part_ptr[t]..col_part_ptr[t + 1]` belong to part `t`
+    pub col_part_ptr: Vec<usize>,
+}
+
+impl<I: Index> Reordering<I> {
+    /// Reordering with the same permutation for rows and columns
+    pub(crate) fn symmetric(perm: Perm<I>, part_ptr: Vec<usize>) -> Self {
+        let n = perm.len();
+        let (forward, inverse) = perm.into_arrays();
+        Self {
+            row_perm: Perm::new_checked(forward.clone(), inverse.clone(), n),
+            col_perm: Perm::new_checked(forward, inverse, n),
+            row_part_ptr: part_ptr.clone(),
+            col_part_ptr: part_ptr,
+        }
+    }
+
+    /// `P A Q^T`
+    pub fn permute_matrix<T: ComplexField>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+    ) -> SparseColMat<I, T> {
+        permute_matrix(mat, self.row_perm.as_ref(), self.col_perm.as_ref())
+    }
+
+    /// `Q x`, the right hand side of the reordered product
+    pub fn permute_rhs<T: ComplexField>(&self, x: ColRef<'_, T>) -> Col<T> {
+        permute_vec(x, self.col_perm.as_ref())
+    }
+
+    /// `P y`, the output (or any row-indexed vector) in the reordered row order
+    pub fn permute_dst<T: ComplexField>(&self, y: ColRef<'_, T>) -> Col<T> {
+        permute_vec(y, self.row_perm.as_ref())
+    }
+
+    /// `P^T y_perm`, the output of the reordered product back in the original row order
+    pub fn unpermute_dst<T: ComplexField>(&self, y_perm: ColRef<'_, T>) -> Col<T> {
+        permute_vec(y_perm, self.row_perm.as_ref().inverse())
+    }
+}
+
+/// `perm * x`, i.e. `out[i] = x[forward[i]]`
+pub fn permute_vec<I: Index, T: ComplexField>(x: ColRef<'_, T>, perm: PermRef<'_, I>) -> Col<T> {
+    assert_eq!(x.nrows(), perm.len());
+    let (forward, _) = perm.arrays();
+    Col::from_fn(x.nrows(), |i| x[forward[i].zx()].clone())
+}
+
+/// `B = P A Q^T` with sorted row indices in every column
+pub fn permute_matrix<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    row_perm: PermRef<'_, I>,
+    col_perm: PermRef<'_, I>,
+) -> SparseColMat<I, T> {
+    let (symbolic, value_map) = permute_pattern(mat.symbolic(), row_perm, col_perm);
+    let values = mat.val();
+    SparseColMat::new(
+        symbolic,
+        value_map.iter().map(|&idx| values[idx].clone()).collect(),
+    )
+}
+
+/// Pattern of `B = P A Q^T` with sorted row indices in every column, and for every entry of `B`
+/// the position of its value in the storage of `A`
+pub fn permute_pattern<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+    row_perm: PermRef<'_, I>,
+    col_perm: PermRef<'_, I>,
+) -> (SymbolicSparseColMat<I>, Vec<usize>) {
+    let m = mat.nrows();
+    let n = mat.ncols();
+    assert_eq!(row_perm.len(), m);
+    assert_eq!(col_perm.len(), n);
+    let (_, row_inverse) = row_perm.arrays();
+    let (col_forward, _) = col_perm.arrays();
+    let nnz = mat.compute_nnz();
+    let row_indices = mat.row_idx();
+
+    let mut col_ptr = Vec::with_capacity(n + 1);
+    let mut row_idx = Vec::with_capacity(nnz);
+    let mut value_map = Vec::with_capacity(nnz);
+    col_ptr.push(I::truncate(0));
+
+    let mut entries: Vec<(usize, usize)> = Vec::new();
+    for &old_col in col_forward {
+        entries.clear();
+        entries.extend(
+            mat.col_range(old_col.zx())
+                .map(|idx| (row_inverse[row_indices[idx].zx()].zx(), idx)),
+        );
+        entries.sort_unstable();
+        for &(i, idx) in &entries {
+            row_idx.push(I::truncate(i));
+            value_map.push(idx);
+        }
+        col_ptr.push(I::truncate(row_idx.len()));
+    }
+
+    // SAFETY: `row_perm` is a bijection on `0..m` so every column gets the same number of in
+    // bounds row indices as before, sorted above
+    let symbolic = unsafe { SymbolicSparseColMat::new_unchecked(m, n, col_ptr, None, row_idx) };
+    (symbolic, value_map)
+}
@@ -0,0 +1,203 @@
+//! METIS k-way partitioning with one part per thread.
+//!
+//! Both graphs carry two balance constraints per vertex: a row count, so that the parts line up
+//! with the even row slices the kernels hand out, and the column nnz, so that they line up with
+//! the even nnz slices of `SpMvStrategy`.
+use faer::{Index, perm::Perm, sparse::SymbolicSparseColMatRef};
+use metis::{Graph, Idx};
+
+use super::Reordering;
+
+/// Symmetric reordering `P A P^T` of a square matrix, partitioning the graph of `A + A^T`
+pub fn metis_symmetric<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+    n_threads: usize,
+) -> Result<Reordering<I>, metis::Error> {
+    let n = mat.ncols();
+    assert_eq!(
+        mat.nrows(),
+        n,
+        "symmetric reordering requires a square matrix"
+    );
+    // every off-diagonal entry becomes (at most) two adjacency entries
+    to_idx(2 * mat.compute_nnz())?;
+
+    let (xadj, adjncy) = symmetric_graph(mat);
+    let mut vwgt = Vec::with_capacity(2 * n);
+    for j in 0..n {
+        vwgt.extend([1, to_idx(mat.col_range(j).len())?]);
+    }
+    let part = partition(&xadj, &adjncy, &vwgt, n_threads)?;
+
+    let (perm, part_ptr) = perm_from_parts(&part, n_threads);
+    Ok(Reordering::symmetric(perm, part_ptr))
+}
+
+/// Independent row and column reordering `P A Q^T` of a (possibly rectangular) matrix,
+/// partitioning the bipartite row / column graph of `A`
+pub fn metis_bipartite<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+    n_threads: usize,
+) -> Result<Reordering<I>, metis::Error> {
+    let m = mat.nrows();
+    let n = mat.ncols();
+
+    let (xadj, adjncy) = bipartite_graph(mat)?;
+    let mut vwgt = Vec::with_capacity(2 * (m + n));
+    for _ in 0..m {
+        vwgt.extend([1, 0]);
+    }
+    for j in 0..n {
+        vwgt.extend([0, to_idx(mat.col_range(j).len())?]);
+    }
+    let part = partition(&xadj, &adjncy, &vwgt, n_threads)?;
+
+    let (row_perm, row_part_ptr) = perm_from_parts(&part[..m], n_threads);
+    let (col_perm, col_part_ptr) = perm_from_parts(&part[m..], n_threads);
+    Ok(Reordering {
+        row_perm,
+        col_perm,
+        row_part_ptr,
+        col_part_ptr,
+    })
+}
+
+fn to_idx(value: usize) -> Result<Idx, metis::Error> {
+    Idx::try_from(value).map_err(|_| metis::Error::Input)
+}
+
+/// Adjacency of `A + A^T` without self loops in METIS CSR format
+pub(super) fn symmetric_graph<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+) -> (Vec<Idx>, Vec<Idx>) {
+    let n = mat.ncols();
+
+    let mut degree = vec![0usize; n];
+    for j in 0..n {
+        for i in mat.row_idx_of_col(j).filter(|&i| i != j) {
+            degree[i] += 1;
+            degree[j] += 1;
+        }
+    }
+    let mut ptr = Vec::with_capacity(n + 1);
+    ptr.push(0);
+    for d in &degree {
+        ptr.push(ptr.last().unwrap() + d);
+    }
+
+    let mut cursor = ptr[..n].to_vec();
+    let mut adjncy = vec![0 as Idx; ptr[n]];
+    for j in 0..n {
+        for i in mat.row_idx_of_col(j).filter(|&i| i != j) {
+            adjncy[cursor[i]] = j as Idx;
+            cursor[i] += 1;
+            adjncy[cursor[j]] = i as Idx;
+            cursor[j] += 1;
+        }
+    }
+
+    // entries present in both `A` and `A^T` show up twice
+    let mut xadj = Vec::with_capacity(n + 1);
+    xadj.push(0);
+    let mut write = 0;
+    for v in 0..n {
+        let neighbours = &mut adjncy[ptr[v]..ptr[v + 1]];
+        neighbours.sort_unstable();
+        let mut last = None;
+        for read in ptr[v]..ptr[v + 1] {
+            let u = adjncy[read];
+            if last != Some(u) {
+                adjncy[write] = u;
+                write += 1;
+                last = Some(u);
+            }
+        }
+        xadj.push(write as Idx);
+    }
+    adjncy.truncate(write);
+    (xadj, adjncy)
+}
+
+/// Bipartite graph with row vertices `0..m` and column vertices `m..m + n`, one edge per nonzero
+fn bipartite_graph<I: Index>(
+    mat: SymbolicSparseColMatRef<'_, I>,
+) -> Result<(Vec<Idx>, Vec<Idx>), metis::Error> {
+    let m = mat.nrows();
+    let n = mat.ncols();
+    to_idx(m + n)?;
+
+    let mut ptr = vec![0usize; m + n + 1];
+    for j in 0..n {
+        for i in mat.row_idx_of_col(j) {
+            ptr[i + 1] += 1;
+        }
+        ptr[m + j + 1] = mat.col_range(j).len();
+    }
+    for v in 0..m + n {
+        ptr[v + 1] += ptr[v];
+    }
+
+    let mut cursor = ptr[..m + n].to_vec();
+    let mut adjncy = vec![0 as Idx; ptr[m + n]];
+    for j in 0..n {
+        for i in mat.row_idx_of_col(j) {
+            adjncy[cursor[i]] = (m + j) as Idx;
+            cursor[i] += 1;
+            adjncy[cursor[m + j]] = i as Idx;
+            cursor[m + j] += 1;
+        }
+    }
+
+    let xadj = ptr.into_iter().map(to_idx).collect::<Result<_, _>>()?;
+    Ok((xadj, adjncy))
+}
+
+/// k-way partition with two balance constraints, `vwgt` holds both weights of every vertex
+fn partition(
+    xadj: &[Idx],
+    adjncy: &[Idx],
+    vwgt: &[Idx],
+    n_parts: usize,
+) -> Result<Vec<Idx>, metis::Error> {
+    let mut part = vec![0; xadj.len() - 1];
+    // METIS rejects empty graphs, and can't do anything useful without edges anyway
+    if part.is_empty() || adjncy.is_empty() {
+        let n_vertices = part.len();
+        for (v, p) in part.iter_mut().enumerate() {
+            *p = (v * n_parts / n_vertices) as Idx;
+        }
+        return Ok(part);
+    }
+    Graph::new(2, to_idx(n_parts)?, xadj, adjncy)?
+        .set_vwgt(vwgt)
+        .part_kway(&mut part)?;
+    Ok(part)
+}
+
+/// Stable counting sort of the vertices by part, returning the permutation (`forward[new] = old`)
+/// and the part boundaries
+fn perm_from_parts<I: Index>(part: &[Idx], n_parts: usize) -> (Perm<I>, Vec<usize>) {
+    let n = part.len();
+    let mut part_ptr = vec![0usize; n_parts + 1];
+    for &p in part {
+        part_ptr[p as usize + 1] += 1;
+    }
+    for p in 0..n_parts {
+        part_ptr[p + 1] += part_ptr[p];
+    }
+
+    let mut cursor = part_ptr[..n_parts].to_vec();
+    let mut forward = vec![I::truncate(0); n];
+    let mut inverse = vec![I::truncate(0); n];
+    for (old, &p) in part.iter().enumerate() {
+        let new = cursor[p as usize];
+        cursor[p as usize] += 1;
+        forward[new] = I::truncate(old);
+        inverse[old] = I::truncate(new);
+    }
+
+    (
+        Perm::new_checked(forward.into_boxed_slice(), inverse.into_boxed_slice(), n),
+        part_ptr,
+    )
+}
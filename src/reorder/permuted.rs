@@ -0,0 +1,341 @@
+//! SpMV with a reordered matrix in the original indexing.
+//!
+//! `PermutedOperator` stores `B = P A Q^T` and computes `y = A x` without permuted copies of `x`
+//! and `y`: the scatter pass of the `simple` kernel reads `x[q[j]]` for column `j` of `B`, and the
+//! workspace reduction writes the sum of permuted row `i` straight to `y[p[i]]`. The transpose
+//! runs the `dense_sparse_impl` kernel the same way. With `Par::Seq` both go through permuted
+//! copies and the faer kernels.
+//!
+//! The operator owns its reordered copy, so a new matrix with the same pattern is loaded through
+//! `update_values`, which reuses the permutation, the pattern and the plan.
+use std::sync::Arc;
+use std::thread;
+
+use faer::{
+    Accum, ColMut, ColRef, Index, Par,
+    col::AsColMut,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    perm::Perm,
+    prelude::{Reborrow, ReborrowMut},
+    sparse::{
+        SparseColMat, SparseColMatRef, SymbolicSparseColMatRef,
+        linalg::matmul::{
+            dense_sparse_matmul as seq_dense_sparse, sparse_dense_matmul as seq_sparse_dense,
+        },
+    },
+    traits::{ComplexField, math_utils::zero},
+};
+use rayon::prelude::*;
+
+use super::{Reordering, permute_pattern};
+use crate::dense_sparse_impl::{stitch_boundaries_permuted, stitched_thread_range_permuted};
+use crate::plan_cache::PlanCache;
+use crate::sparse_dense_impl::simple::{reduce_workspaces_with, scatter_thread_range_gather};
+use crate::spmv_drivers::SpMvStrategy;
+
+/// A reordered matrix together with its permutations and SpMV plan
+pub struct PermutedOperator<I: Index, T: ComplexField> {
+    matrix: SparseColMat<I, T>,
+    row_perm: Perm<I>,
+    col_perm: Perm<I>,
+    /// Position in the values of the original matrix of every entry of `matrix`
+    value_map: Vec<usize>,
+    par: Par,
+    strategy: Arc<SpMvStrategy>,
+}
+
+impl<I: Index, T: ComplexField> PermutedOperator<I, T> {
+    /// Reorder `mat` with `reordering` and plan the SpMV of the result for `par`
+    pub fn new(mat: SparseColMatRef<'_, I, T>, reordering: Reordering<I>, par: Par) -> Self {
+        Self::with_plan(mat, reordering, par, |symbolic| {
+            Arc::new(SpMvStrategy::new(symbolic, par))
+        })
+    }
+
+    /// `new` with the plan taken from `cache`, shared with every operator whose reordered matrix
+    /// has the same pattern
+    pub fn new_cached(
+        mat: SparseColMatRef<'_, I, T>,
+        reordering: Reordering<I>,
+        par: Par,
+        cache: &PlanCache,
+    ) -> Self {
+        Self::with_plan(mat, reordering, par, |symbolic| cache.get(symbolic, par))
+    }
+
+    /// `new` with a plan built earlier for the reordered matrix, e.g. loaded with
+    /// `plan_io::load_plan`. Panics unless the plan fits the reordered pattern and `par`, see
+    /// `SpMvStrategy::check_pattern`.
+    pub fn with_strategy(
+        mat: SparseColMatRef<'_, I, T>,
+        reordering: Reordering<I>,
+        par: Par,
+        strategy: Arc<SpMvStrategy>,
+    ) -> Self {
+        Self::with_plan(mat, reordering, par, |symbolic| {
+            if let Err(what) = strategy.check_pattern(symbolic, par) {
+                panic!("strategy does not fit the reordered matrix: {what}");
+            }
+            strategy
+        })
+    }
+
+    fn with_plan(
+        mat: SparseColMatRef<'_, I, T>,
+        reordering: Reordering<I>,
+        par: Par,
+        plan: impl FnOnce(SymbolicSparseColMatRef<'_, I>) -> Arc<SpMvStrategy>,
+    ) -> Self {
+        let (symbolic, value_map) = permute_pattern(
+            mat.symbolic(),
+            reordering.row_perm.as_ref(),
+            reordering.col_perm.as_ref(),
+        );
+        let strategy = plan(symbolic.as_ref());
+        let values = mat.val();
+        let matrix = SparseColMat::new(
+            symbolic,
+            value_map.iter().map(|&idx| values[idx].clone()).collect(),
+        );
+        Self {
+            matrix,
+            row_perm: reordering.row_perm,
+            col_perm: reordering.col_perm,
+            value_map,
+            par,
+            strategy,
+        }
+    }
+
+    /// Load the values of a matrix with the same pattern as the one the operator was built from,
+    /// given in the storage order of that matrix
+    pub fn update_values(&mut self, values: &[T]) {
+        assert_eq!(values.len(), self.value_map.len());
+        self.matrix
+            .val_mut()
+            .par_iter_mut()
+            .zip(&self.value_map)
+            .for_each(|(dst, &idx)| *dst = values[idx].clone());
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.matrix.nrows()
+    }
+
+    pub fn ncols(&self) -> usize {
+        self.matrix.ncols()
+    }
+
+    /// The reordered matrix `P A Q^T`
+    pub fn matrix(&self) -> SparseColMatRef<'_, I, T> {
+        self.matrix.as_ref()
+    }
+
+    pub fn row_perm(&self) -> &Perm<I> {
+        &self.row_perm
+    }
+
+    pub fn col_perm(&self) -> &Perm<I> {
+        &self.col_perm
+    }
+
+    pub fn par(&self) -> Par {
+        self.par
+    }
+
+    pub fn strategy(&self) -> &SpMvStrategy {
+        &self.strategy
+    }
+
+    pub fn shared_strategy(&self) -> &Arc<SpMvStrategy> {
+        &self.strategy
+    }
+
+    /// Scratch space of `apply` and `apply_transpose`
+    pub fn scratch(&self) -> StackReq {
+        let permuted_copies =
+            temp_mat_scratch::<T>(self.ncols(), 1).and(temp_mat_scratch::<T>(self.nrows(), 1));
+        // the parallel `apply_transpose` permutes inside the kernel and needs no scratch
+        match self.par {
+            Par::Seq => permuted_copies,
+            Par::Rayon(n_threads) => temp_mat_scratch::<T>(self.nrows(), n_threads.get()),
+        }
+    }
+
+    /// `dst = beta * dst + alpha * A rhs` with `dst` and `rhs` in the original indexing of `A`
+    pub fn apply(
+        &self,
+        dst: ColMut<'_, T>,
+        beta: Accum,
+        rhs: ColRef<'_, T>,
+        alpha: &T,
+        stack: &mut MemStack,
+    ) {
+        assert_eq!(dst.nrows(), self.nrows());
+        assert_eq!(rhs.nrows(), self.ncols());
+        let (row_forward, _) = self.row_perm.as_ref().arrays();
+        let (col_forward, _) = self.col_perm.as_ref().arrays();
+
+        match self.par {
+            Par::Seq => {
+                let (mut rhs_perm, stack) = temp_mat_zeroed::<T, _, _>(self.ncols(), 1, stack);
+                let mut rhs_perm = rhs_perm.as_mat_mut().col_mut(0);
+                for (j, old) in col_forward.iter().enumerate() {
+                    rhs_perm[j] = rhs[old.zx()].clone();
+                }
+                let (mut dst_perm, _) = temp_mat_zeroed::<T, _, _>(self.nrows(), 1, stack);
+                let mut dst_perm = dst_perm.as_mat_mut();
+                seq_sparse_dense(
+                    dst_perm.rb_mut(),
+                    Accum::Replace,
+                    self.matrix.as_ref(),
+                    rhs_perm.rb().as_mat(),
+                    alpha.clone(),
+                    self.par,
+                );
+                let mut dst = dst;
+                for (i, old) in row_forward.iter().enumerate() {
+                    let old = old.zx();
+                    let sum = &dst_perm[(i, 0)];
+                    dst[old] = match beta {
+                        Accum::Replace => sum.clone(),
+                        Accum::Add => dst[old].add_by_ref(sum),
+                    };
+                }
+            }
+            Par::Rayon(n_threads) => {
+                let n_threads = n_threads.get();
+                let lhs = self.matrix.as_ref();
+                let strategy = &self.strategy;
+
+                let (mut work, _) = temp_mat_zeroed::<T, _, _>(self.nrows(), n_threads, stack);
+                let work = work.as_mat_mut();
+                let work = work.rb();
+
+                thread::scope(|s| {
+                    for tid in 0..n_threads {
+                        s.spawn(move || {
+                            // SAFETY each thread gets its own workspace vector to be summed when
+                            // all complete
+                            let mut work = unsafe {
+                                work.col(tid).const_cast().try_as_col_major_mut().unwrap()
+                            };
+                            scatter_thread_range_gather(
+                                tid,
+                                lhs,
+                                rhs,
+                                Some(col_forward),
+                                alpha,
+                                strategy,
+                                work.as_col_mut(),
+                            );
+                        });
+                    }
+                });
+
+                let dst = dst.rb();
+                reduce_workspaces_with(strategy, work, |row_start, sums, _: &mut ()| {
+                    // SAFETY: every permuted row is handed to exactly one call of the closure and
+                    // `row_forward` is a bijection, so every entry of `dst` has a single writer
+                    let mut dst = unsafe { dst.const_cast() };
+                    for (i, sum) in (row_start..).zip(sums) {
+                        let old = row_forward[i].zx();
+                        dst[old] = match beta {
+                            Accum::Replace => sum.clone(),
+                            Accum::Add => dst[old].add_by_ref(sum),
+                        };
+                    }
+                });
+            }
+        }
+    }
+
+    /// `dst = beta * dst + alpha * A^T rhs` with `dst` and `rhs` in the original indexing of `A`.
+    ///
+    /// `A^T = Q^T B^T P`, so the `dense_sparse_impl` kernel on `B` reads entry `i` of its input
+    /// from `rhs[p[i]]` and writes column `j` of its output to `dst[q[j]]`.
+    pub fn apply_transpose(
+        &self,
+        dst: ColMut<'_, T>,
+        beta: Accum,
+        rhs: ColRef<'_, T>,
+        alpha: &T,
+        stack: &mut MemStack,
+    ) {
+        assert_eq!(dst.nrows(), self.ncols());
+        assert_eq!(rhs.nrows(), self.nrows());
+        let (row_forward, _) = self.row_perm.as_ref().arrays();
+        let (col_forward, _) = self.col_perm.as_ref().arrays();
+
+        match self.par {
+            Par::Seq => {
+                let (mut rhs_perm, stack) = temp_mat_zeroed::<T, _, _>(self.nrows(), 1, stack);
+                let mut rhs_perm = rhs_perm.as_mat_mut().col_mut(0);
+                for (i, old) in row_forward.iter().enumerate() {
+                    rhs_perm[i] = rhs[old.zx()].clone();
+                }
+                let (mut dst_perm, _) = temp_mat_zeroed::<T, _, _>(self.ncols(), 1, stack);
+                let mut dst_perm = dst_perm.as_mat_mut();
+                seq_dense_sparse(
+                    dst_perm.rb_mut().transpose_mut(),
+                    Accum::Replace,
+                    rhs_perm.rb().as_mat().transpose(),
+                    self.matrix.as_ref(),
+                    alpha.clone(),
+                    self.par,
+                );
+                let mut dst = dst;
+                for (j, old) in col_forward.iter().enumerate() {
+                    let old = old.zx();
+                    let sum = &dst_perm[(j, 0)];
+                    dst[old] = match beta {
+                        Accum::Replace => sum.clone(),
+                        Accum::Add => dst[old].add_by_ref(sum),
+                    };
+                }
+            }
+            Par::Rayon(n_threads) => {
+                let n_threads = n_threads.get();
+                let rhs_mat = self.matrix.as_ref();
+                let strategy = &self.strategy;
+                let lhs = rhs.transpose();
+
+                let mut dst = dst.transpose_mut();
+                if let Accum::Replace = beta {
+                    dst.fill(zero());
+                }
+
+                let stitch: Vec<(T, T, T)> = thread::scope(|s| {
+                    let dst = dst.rb();
+                    let mut handles = Vec::with_capacity(n_threads);
+                    for tid in 0..n_threads {
+                        let handle = s.spawn(move || {
+                            // SAFETY: the ranges (col_start+1)..col_end are non-overlapping per
+                            // thread and `col_forward` is a bijection, so every owned entry of
+                            // `dst` has a single writer
+                            unsafe {
+                                stitched_thread_range_permuted(
+                                    tid,
+                                    dst,
+                                    Some(col_forward),
+                                    lhs,
+                                    Some(row_forward),
+                                    rhs_mat,
+                                    alpha,
+                                    None,
+                                    strategy,
+                                )
+                            }
+                        });
+                        handles.push(handle);
+                    }
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                });
+
+                stitch_boundaries_permuted(dst, Some(col_forward), &stitch, None, strategy);
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Reverse Cuthill-McKee ordering.
+//!
+//! RCM doesn't partition, it pulls the nonzeros towards the diagonal. That shrinks the range of
+//! rows every thread's contiguous nnz slice writes to, which is what the `simple` reduction and
+//! the owner-based kernels care about.
+use faer::{Index, perm::Perm, sparse::SymbolicSparseColMatRef};
+use metis::Idx;
+
+use super::{Reordering, partition::symmetric_graph};
+
+/// Adjacency of the graph of `A + A^T` as produced by `symmetric_graph`
+struct Graph {
+    xadj: Vec<Idx>,
+    adjncy: Vec<Idx>,
+}
+
+impl Graph {
+    fn neighbours(&self, v: usize) -> impl Iterator<Item = usize> + '_ {
+        self.adjncy[self.xadj[v] as usize..self.xadj[v + 1] as usize]
+            .iter()
+            .map(|&u| u as usize)
+    }
+
+    fn degree(&self, v: usize) -> usize {
+        (self.xadj[v + 1] - self.xadj[v]) as usize
+    }
+
+    /// Breadth first search from `root` over the unvisited vertices, appending them to `queue`
+    /// with their neighbours in increasing degree order. Returns the index in `queue` where the
+    /// last level starts and the number of levels.
+    fn bfs(&self, root: usize, visited: &mut [bool], queue: &mut Vec<usize>) -> (usize, usize) {
+        let mut head = queue.len();
+        let mut last_level = head;
+        let mut level_end = head + 1;
+        let mut depth = 1;
+        visited[root] = true;
+        queue.push(root);
+
+        let mut neighbours = Vec::new();
+        while head < queue.len() {
+            if head == level_end {
+                last_level = head;
+                level_end = queue.len();
+                depth += 1;
+            }
+            let v = queue[head];
+            head += 1;
+
+            neighbours.clear();
+            neighbours.extend(self.neighbours(v).filter(|&u| !visited[u]));
+            neighbours.sort_by_key(|&u| self.degree(u));
+            for &u in &neighbours {
+                visited[u] = true;
+                queue.push(u);
+            }
+        }
+        (last_level, depth)
+    }
+
+    /// George-Liu pseudo-peripheral vertex search in the component of `seed`
+    fn pseudo_peripheral(
+        &self,
+        seed: usize,
+        visited: &mut [bool],
+        scratch: &mut Vec<usize>,
+    ) -> usize {
+        let mut root = seed;
+        let mut eccentricity = 0;
+        loop {
+            scratch.clear();
+            let (last_level, depth) = self.bfs(root, visited, scratch);
+            for &v in scratch.iter() {
+                visited[v] = false;
+            }
+            let candidate = scratch[last_level..]
+                .iter()
+                .copied()
+                .min_by_key(|&v| self.degree(v))
+                .unwrap();
+            if depth <= eccentricity || candidate == root {
+                return root;
+            }
+            eccentricity = depth;
+            root = candidate;
+        }
+    }
+}
+
+/// Reverse Cuthill-McKee symmetric reordering `P A P^T` of a square matrix. There is a single
+/// part covering all rows and columns.
+pub fn rcm<I: Index>(mat: SymbolicSparseColMatRef<'_, I>) -> Reordering<I> {
+    let n = mat.ncols();
+    assert_eq!(mat.nrows(), n, "RCM requires a square matrix");
+
+    let (xadj, adjncy) = symmetric_graph(mat);
+    let graph = Graph { xadj, adjncy };
+
+    let mut seeds: Vec<usize> = (0..n).collect();
+    seeds.sort_by_key(|&v| graph.degree(v));
+
+    let mut visited = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+    let mut scratch = Vec::new();
+    for seed in seeds {
+        if visited[seed] {
+            continue;
+        }
+        let root = graph.pseudo_peripheral(seed, &mut visited, &mut scratch);
+        graph.bfs(root, &mut visited, &mut order);
+    }
+    order.reverse();
+
+    let mut forward = vec![I::truncate(0); n];
+    let mut inverse = vec![I::truncate(0); n];
+    for (new, old) in order.into_iter().enumerate() {
+        forward[new] = I::truncate(old);
+        inverse[old] = I::truncate(new);
+    }
+    let perm = Perm::new_checked(forward.into_boxed_slice(), inverse.into_boxed_slice(), n);
+    Reordering::symmetric(perm, vec![0, n])
+}
@@ -0,0 +1,230 @@
+//! Row oriented views and schedules for the kernels that update one row at a time.
+//!
+//! Triangular solves and Gauss–Seidel sweeps compute `x_i` from row `i` of a CSC matrix, so they
+//! need the transposed pattern. `RowPattern` stores it together with the position of every entry
+//! in the values of the CSC matrix, so a plan built from the symbolic structure works for all
+//! matrices sharing it. `RowSchedule` holds groups of mutually independent rows (levels or colors),
+//! each split into per-thread ranges of equal work like the column ranges of `SpMvStrategy`.
+//! A solve or sweep walks hundreds of groups, so the schedule keeps its own pool of `Par` workers
+//! and hands every group to all of them at once instead of spawning threads per group.
+use faer::{
+    Index, Par,
+    sparse::{SparseColMatRef, SymbolicSparseColMatRef},
+    traits::ComplexField,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::spmv_drivers::balanced_splits;
+
+/// Groups with less work than this are handled by the calling thread, where waking the workers
+/// costs more than it saves
+const PAR_GROUP_MIN_WORK: usize = 4096;
+
+/// The selected off-diagonal entries of every row of a square CSC matrix and its diagonal
+pub(crate) struct RowPattern {
+    n: usize,
+    nnz: usize,
+    /// Entries of row `i` are `row_ptr[i]..row_ptr[i + 1]` of `col_idx` and `val_pos`, with the
+    /// columns sorted
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    /// Position of every entry in the values of the matrix
+    val_pos: Vec<usize>,
+    diag_pos: Vec<Option<usize>>,
+}
+
+impl RowPattern {
+    /// Rows of the off-diagonal entries `(i, j)` of `mat` for which `keep(i, j)` holds
+    pub(crate) fn new<I: Index>(
+        mat: SymbolicSparseColMatRef<'_, I>,
+        keep: impl Fn(usize, usize) -> bool,
+    ) -> Self {
+        assert_eq!(mat.nrows(), mat.ncols());
+        let n = mat.nrows();
+        let row_indices = mat.row_idx();
+
+        let mut row_ptr = vec![0usize; n + 1];
+        let mut diag_pos = vec![None; n];
+        for (j, diag_pos) in diag_pos.iter_mut().enumerate() {
+            for idx in mat.col_range(j) {
+                let i = row_indices[idx].zx();
+                if i == j {
+                    *diag_pos = Some(idx);
+                } else if keep(i, j) {
+                    row_ptr[i + 1] += 1;
+                }
+            }
+        }
+        for i in 0..n {
+            row_ptr[i + 1] += row_ptr[i];
+        }
+        let mut col_idx = vec![0; row_ptr[n]];
+        let mut val_pos = vec![0; row_ptr[n]];
+        let mut next = row_ptr[..n].to_vec();
+        for j in 0..n {
+            for idx in mat.col_range(j) {
+                let i = row_indices[idx].zx();
+                if i != j && keep(i, j) {
+                    col_idx[next[i]] = j;
+                    val_pos[next[i]] = idx;
+                    next[i] += 1;
+                }
+            }
+        }
+
+        Self {
+            n,
+            nnz: mat.compute_nnz(),
+            row_ptr,
+            col_idx,
+            val_pos,
+            diag_pos,
+        }
+    }
+
+    pub(crate) fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Columns of the entries of row `i`
+    pub(crate) fn row(&self, i: usize) -> &[usize] {
+        &self.col_idx[self.row_ptr[i]..self.row_ptr[i + 1]]
+    }
+
+    pub(crate) fn row_len(&self, i: usize) -> usize {
+        self.row_ptr[i + 1] - self.row_ptr[i]
+    }
+
+    /// Panic unless `mat` has the dimensions and nnz of the pattern, and with `diagonal` unless
+    /// every diagonal entry is stored
+    pub(crate) fn check<I: Index, T: ComplexField>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+        diagonal: bool,
+    ) {
+        assert_eq!(mat.nrows(), self.n);
+        assert_eq!(mat.ncols(), self.n);
+        assert_eq!(mat.compute_nnz(), self.nnz);
+        if diagonal {
+            assert!(
+                self.diag_pos.iter().all(Option::is_some),
+                "the diagonal of the matrix must be stored"
+            );
+        }
+    }
+
+    /// `sum_j A_ij x(j)` over the entries of row `i`
+    #[inline]
+    pub(crate) fn row_dot<T: ComplexField>(
+        &self,
+        i: usize,
+        values: &[T],
+        x: impl Fn(usize) -> T,
+    ) -> T {
+        let mut acc = T::zero_impl();
+        for p in self.row_ptr[i]..self.row_ptr[i + 1] {
+            acc = acc.add_by_ref(&values[self.val_pos[p]].mul_by_ref(&x(self.col_idx[p])));
+        }
+        acc
+    }
+
+    /// The stored diagonal entry of row `i`
+    #[inline]
+    pub(crate) fn diag<'a, T>(&self, i: usize, values: &'a [T]) -> &'a T {
+        &values[self.diag_pos[i].unwrap()]
+    }
+}
+
+/// Groups of rows that can be processed in parallel, one group after the other
+pub(crate) struct RowSchedule {
+    n_threads: usize,
+    /// Rows of group `g` are `group_rows[group_ptr[g]..group_ptr[g + 1]]`
+    group_ptr: Vec<usize>,
+    group_rows: Vec<usize>,
+    /// Thread `t` handles the rows `group_threads[g * (n_threads + 1) + t]..` up to the next
+    /// entry of group `g`, as positions in `group_rows`
+    group_threads: Vec<usize>,
+    group_work: Vec<usize>,
+    /// `n_threads` workers, `None` with a single thread
+    pool: Option<ThreadPool>,
+}
+
+impl RowSchedule {
+    /// Schedule every row `i` in group `group_of_row[i]`, balancing `row_work` over the threads
+    pub(crate) fn new(
+        group_of_row: &[usize],
+        n_groups: usize,
+        row_work: impl Fn(usize) -> usize,
+        par: Par,
+    ) -> Self {
+        let n_threads = match par {
+            Par::Seq => 1,
+            Par::Rayon(n_threads) => n_threads.get(),
+        };
+
+        let mut group_ptr = vec![0usize; n_groups + 1];
+        for &g in group_of_row {
+            group_ptr[g + 1] += 1;
+        }
+        for g in 0..n_groups {
+            group_ptr[g + 1] += group_ptr[g];
+        }
+        let mut group_rows = vec![0; group_of_row.len()];
+        let mut next = group_ptr[..n_groups].to_vec();
+        for (i, &g) in group_of_row.iter().enumerate() {
+            group_rows[next[g]] = i;
+            next[g] += 1;
+        }
+
+        let weights: Vec<usize> = group_rows.iter().map(|&i| row_work(i)).collect();
+        let mut group_threads = Vec::with_capacity(n_groups * (n_threads + 1));
+        let mut group_work = Vec::with_capacity(n_groups);
+        for g in 0..n_groups {
+            let weights = &weights[group_ptr[g]..group_ptr[g + 1]];
+            group_work.push(weights.iter().sum());
+            let splits = balanced_splits(weights, n_threads);
+            group_threads.extend(splits.iter().map(|split| group_ptr[g] + split));
+        }
+
+        let pool = (n_threads > 1).then(|| {
+            ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build()
+                .expect("failed to start the row schedule workers")
+        });
+
+        Self {
+            n_threads,
+            group_ptr,
+            group_rows,
+            group_threads,
+            group_work,
+            pool,
+        }
+    }
+
+    pub(crate) fn n_groups(&self) -> usize {
+        self.group_ptr.len() - 1
+    }
+
+    pub(crate) fn group(&self, g: usize) -> &[usize] {
+        &self.group_rows[self.group_ptr[g]..self.group_ptr[g + 1]]
+    }
+
+    /// Call `f` on every row of group `g`, worker `t` of the schedule taking the `t`-th range
+    pub(crate) fn for_each_row(&self, g: usize, f: impl Fn(usize) + Sync) {
+        match &self.pool {
+            Some(pool) if self.group_work[g] >= PAR_GROUP_MIN_WORK => {
+                let stride = self.n_threads + 1;
+                let splits = &self.group_threads[g * stride..(g + 1) * stride];
+                pool.broadcast(|ctx| {
+                    let tid = ctx.index();
+                    self.group_rows[splits[tid]..splits[tid + 1]]
+                        .iter()
+                        .for_each(|&i| f(i));
+                });
+            }
+            _ => self.group(g).iter().for_each(|&i| f(i)),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Chebyshev polynomial smoother.
+//!
+//! Applies `x += p(D^-1 A) D^-1 (b - A x)`, where `p` is the Chebyshev polynomial of the given
+//! degree that is smallest on `[lambda_min, lambda_max]`, the part of the spectrum of `D^-1 A` to
+//! damp. Unlike Gauss–Seidel every step is a full SpMV, so it parallelizes like the kernels.
+//!
+//! Every step of the three-term recurrence
+//!
+//! ```text
+//! d_0 = D^-1 r_0 / theta,   d_k = rho_k rho_{k-1} d_{k-1} + 2 rho_k / delta D^-1 r_k,
+//! x_{k+1} = x_k + d_k,      r_k = b - A x_k
+//! ```
+//!
+//! is a single fused `residual_sweep`, so neither `r` nor `A x` is written out. With no bounds
+//! given, `lambda_max` is estimated with a few power iterations on `D^-1 A` using the parallel
+//! SpMV, and the smoother targets the upper `1 / EIG_RATIO` of the spectrum like the usual
+//! multigrid setup.
+use faer::{
+    Accum, Col, ColMut, ColRef, Index, MatRef, Par,
+    dyn_stack::{MemBuffer, MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::ReborrowMut,
+    sparse::SparseColMatRef,
+    traits::{
+        ComplexField,
+        math_utils::{from_f64, mul_real, one},
+    },
+};
+
+use super::jacobi::{JacobiDiagonal, JacobiSmoother};
+use super::{residual_sweep, residual_sweep_scratch};
+use crate::operator::LinearOperator;
+use crate::sparse_dense_impl::simple::{par_sparse_dense, sparse_dense_scratch};
+use crate::spmv_drivers::sparse_dense_matmul;
+
+/// Power iterations used to estimate `lambda_max`
+pub const POWER_ITERATIONS: usize = 10;
+/// Power iterations approach `lambda_max` from below, so the estimate is scaled up by this factor
+pub const LAMBDA_MAX_SAFETY: f64 = 1.1;
+/// Ratio `lambda_max / lambda_min` of the estimated bounds
+pub const EIG_RATIO: f64 = 30.0;
+
+/// Chebyshev smoother for a fixed matrix, holding its inverse diagonal, SpMV plan and spectrum
+/// bounds
+pub struct ChebyshevSmoother<T: ComplexField> {
+    /// Inverse diagonal and SpMV plan
+    jacobi: JacobiSmoother<T>,
+    degree: usize,
+    lambda_min: T::Real,
+    lambda_max: T::Real,
+}
+
+impl<T: ComplexField> ChebyshevSmoother<T> {
+    /// Smoother of degree `degree` for the square matrix `mat`, which must store its diagonal,
+    /// with the bounds estimated from the spectrum of `D^-1 A`
+    pub fn new<I: Index>(mat: SparseColMatRef<'_, I, T>, degree: usize, par: Par) -> Self {
+        let mut smoother = Self::with_bounds(mat, degree, one(), from_f64(2.0), par);
+        smoother.estimate_bounds(mat);
+        smoother
+    }
+
+    /// `new` with the bounds `lambda_min < lambda_max` given
+    pub fn with_bounds<I: Index>(
+        mat: SparseColMatRef<'_, I, T>,
+        degree: usize,
+        lambda_min: T::Real,
+        lambda_max: T::Real,
+        par: Par,
+    ) -> Self {
+        assert!(degree > 0);
+        let jacobi = JacobiSmoother::new(mat, JacobiDiagonal::Plain, one(), par);
+        let mut smoother = Self {
+            jacobi,
+            degree,
+            lambda_min: one(),
+            lambda_max: from_f64(2.0),
+        };
+        smoother.set_bounds(lambda_min, lambda_max);
+        smoother
+    }
+
+    /// Load the diagonal of a matrix with the same pattern as the one the smoother was built from
+    /// and estimate its bounds again
+    pub fn update_values<I: Index>(&mut self, mat: SparseColMatRef<'_, I, T>) {
+        self.jacobi.update_values(mat);
+        self.estimate_bounds(mat);
+    }
+
+    pub fn set_bounds(&mut self, lambda_min: T::Real, lambda_max: T::Real) {
+        assert!(lambda_min > from_f64(0.0) && lambda_min < lambda_max);
+        self.lambda_min = lambda_min;
+        self.lambda_max = lambda_max;
+    }
+
+    /// Set the bounds from a power iteration estimate of the largest eigenvalue of `D^-1 A`, with
+    /// the SpMV plan of the Jacobi diagonal
+    fn estimate_bounds<I: Index>(&mut self, mat: SparseColMatRef<'_, I, T>) {
+        let par = self.par();
+        let strategy = self.jacobi.strategy();
+        // the scratch functions only look at the number of vectors
+        let rhs = MatRef::<T>::from_column_major_slice(&[], 0, 1);
+        let scratch = sparse_dense_scratch(mat, rhs, strategy, par);
+        let spectral_radius = self.power_iteration(scratch, |dst, rhs, stack| {
+            sparse_dense_matmul(
+                dst.as_mat_mut(),
+                Accum::Replace,
+                mat,
+                rhs.as_mat(),
+                one(),
+                par,
+                strategy,
+                stack,
+                Some(par_sparse_dense),
+            );
+        });
+        self.set_estimated_bounds(spectral_radius);
+    }
+
+    /// `estimate_bounds` with `a` applying the matrix the diagonal was extracted from, e.g. with
+    /// another SpMV backend
+    pub fn estimate_bounds_with(&mut self, a: &dyn LinearOperator<T>) {
+        let n = self.jacobi.scaled_inv_diag().nrows();
+        assert_eq!((a.nrows(), a.ncols()), (n, n));
+        let spectral_radius =
+            self.power_iteration(a.scratch_req(), |dst, rhs, stack| a.apply(dst, rhs, stack));
+        self.set_estimated_bounds(spectral_radius);
+    }
+
+    fn set_estimated_bounds(&mut self, spectral_radius: T::Real) {
+        let lambda_max = spectral_radius * from_f64(LAMBDA_MAX_SAFETY);
+        let lambda_min = lambda_max.clone() / from_f64(EIG_RATIO);
+        self.set_bounds(lambda_min, lambda_max);
+    }
+
+    /// Estimate the spectral radius of `D^-1 A` with `POWER_ITERATIONS` SpMVs `apply(w, v, stack)`
+    /// computing `w = A v` in `scratch`
+    fn power_iteration(
+        &self,
+        scratch: StackReq,
+        apply: impl Fn(ColMut<'_, T>, ColRef<'_, T>, &mut MemStack),
+    ) -> T::Real {
+        let inv_diag = self.jacobi.scaled_inv_diag();
+        let n = inv_diag.nrows();
+
+        // a fixed pseudo random start, which is unlikely to miss the dominant eigenvector
+        let mut v = Col::<T>::from_fn(n, |i| {
+            let hash = (i as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40;
+            from_f64(hash as f64 / (1u64 << 24) as f64 - 0.5)
+        });
+        let mut w = Col::<T>::zeros(n);
+        let mut stack_buffer = MemBuffer::new(scratch);
+        let stack = MemStack::new(&mut stack_buffer);
+
+        let mut lambda = from_f64::<T::Real>(0.0);
+        let mut norm = v.norm_l2();
+        for _ in 0..POWER_ITERATIONS {
+            if norm == from_f64(0.0) {
+                break;
+            }
+            let inv_norm = from_f64::<T::Real>(1.0) / norm.clone();
+            v.iter_mut().for_each(|v_i| *v_i = mul_real(v_i, &inv_norm));
+            apply(w.as_mut(), v.as_ref(), stack);
+            for (w_i, d_i) in w.iter_mut().zip(inv_diag.iter()) {
+                *w_i = w_i.mul_by_ref(d_i);
+            }
+            norm = w.norm_l2();
+            lambda = norm.clone();
+            std::mem::swap(&mut v, &mut w);
+        }
+        lambda
+    }
+
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
+    pub fn lambda_min(&self) -> &T::Real {
+        &self.lambda_min
+    }
+
+    pub fn lambda_max(&self) -> &T::Real {
+        &self.lambda_max
+    }
+
+    pub fn par(&self) -> Par {
+        self.jacobi.par()
+    }
+
+    pub fn scratch(&self) -> StackReq {
+        let n = self.jacobi.scaled_inv_diag().nrows();
+        residual_sweep_scratch::<T>(n, self.par()).and(temp_mat_scratch::<T>(n, 1))
+    }
+
+    /// Apply the polynomial once to `A x = b`, updating `x` in place. `mat` must be the matrix the
+    /// diagonal was extracted from.
+    pub fn smooth<I: Index>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+        x: ColMut<'_, T>,
+        b: ColRef<'_, T>,
+        stack: &mut MemStack,
+    ) {
+        self.jacobi.check(mat);
+        let n = mat.nrows();
+        assert_eq!(x.nrows(), n);
+        assert_eq!(b.nrows(), n);
+        let inv_diag = self.jacobi.scaled_inv_diag();
+        let two = from_f64::<T::Real>(2.0);
+        let theta = (self.lambda_max.clone() + self.lambda_min.clone()) / two.clone();
+        let delta = (self.lambda_max.clone() - self.lambda_min.clone()) / two.clone();
+        let sigma = theta.clone() / delta.clone();
+
+        let (mut d, stack) = temp_mat_zeroed::<T, _, _>(n, 1, stack);
+        let d = d.as_mat_mut();
+        let d = d.col(0);
+        let mut x = x;
+        // `d_k = d_coeff d_{k-1} + r_coeff D^-1 r_k`
+        let mut d_coeff = from_f64::<T::Real>(0.0);
+        let mut r_coeff = from_f64::<T::Real>(1.0) / theta;
+        let mut rho = from_f64::<T::Real>(1.0) / sigma.clone();
+        for step in 0..self.degree {
+            if step > 0 {
+                let rho_next =
+                    from_f64::<T::Real>(1.0) / (two.clone() * sigma.clone() - rho.clone());
+                d_coeff = rho_next.clone() * rho;
+                r_coeff = two.clone() * rho_next.clone() / delta.clone();
+                rho = rho_next;
+            }
+            residual_sweep(
+                mat,
+                x.rb_mut(),
+                b,
+                self.par(),
+                self.jacobi.strategy(),
+                stack,
+                |i, r_i| {
+                    // SAFETY: `update` is called once per row
+                    let mut d = unsafe { d.const_cast() };
+                    d[i] = mul_real(&d[i], &d_coeff)
+                        .add_by_ref(&mul_real(&inv_diag[i].mul_by_ref(r_i), &r_coeff));
+                    d[i].clone()
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Multicolor Gauss–Seidel.
+//!
+//! A Gauss–Seidel sweep updates `x_i = (b_i - sum_{j != i} A_ij x_j) / A_ii` in place, one row
+//! after the other, which is sequential in general. Coloring the (symmetrized) graph of `A` so that
+//! no two rows of the same color are coupled makes the rows of a color independent: a sweep runs
+//! the colors one after the other and all rows of a color in parallel. The result differs from a
+//! sweep in natural order only by the ordering of the rows, and is the same for every thread
+//! count.
+use faer::{
+    ColMut, ColRef, Index, Par,
+    prelude::Reborrow,
+    sparse::{SparseColMatRef, SymbolicSparseColMatRef},
+    traits::{ComplexField, math_utils::recip},
+};
+
+use crate::schedule::{RowPattern, RowSchedule};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sweep {
+    /// Colors in increasing order
+    Forward,
+    /// Colors in decreasing order
+    Backward,
+    /// A forward sweep followed by a backward sweep
+    Symmetric,
+}
+
+/// Coloring and per-thread schedule of a multicolor Gauss–Seidel sweep
+pub struct GaussSeidelPlan {
+    /// Off-diagonal entries of every row
+    rows: RowPattern,
+    colors: RowSchedule,
+}
+
+impl GaussSeidelPlan {
+    /// Color the square matrix `mat` greedily in natural order and schedule the colors for `par`
+    pub fn new<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, par: Par) -> Self {
+        let rows = RowPattern::new(mat, |_, _| true);
+        let n = rows.n();
+        let row_indices = mat.row_idx();
+
+        let mut color = vec![usize::MAX; n];
+        // `forbidden[c] == i` marks color `c` as taken by a neighbour of row `i`
+        let mut forbidden: Vec<usize> = Vec::new();
+        let mut n_colors = 0;
+        for i in 0..n {
+            let column = mat.col_range(i).map(|idx| row_indices[idx].zx());
+            for j in rows.row(i).iter().copied().chain(column) {
+                let c = color[j];
+                if j != i && c != usize::MAX {
+                    forbidden[c] = i;
+                }
+            }
+            let c = (0..n_colors)
+                .find(|&c| forbidden[c] != i)
+                .unwrap_or(n_colors);
+            if c == n_colors {
+                n_colors += 1;
+                forbidden.push(usize::MAX);
+            }
+            color[i] = c;
+        }
+        let colors = RowSchedule::new(&color, n_colors, |i| 1 + rows.row_len(i), par);
+
+        Self { rows, colors }
+    }
+
+    pub fn n_colors(&self) -> usize {
+        self.colors.n_groups()
+    }
+
+    /// Rows of color `color`, none of them coupled to another
+    pub fn color_rows(&self, color: usize) -> &[usize] {
+        self.colors.group(color)
+    }
+
+    /// Run `n_sweeps` sweeps of `sweep` on `A x = b`, updating `x` in place. `mat` must have the
+    /// pattern the plan was built from and a stored diagonal.
+    pub fn smooth<I: Index, T: ComplexField>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+        x: ColMut<'_, T>,
+        b: ColRef<'_, T>,
+        sweep: Sweep,
+        n_sweeps: usize,
+    ) {
+        self.rows.check(mat, true);
+        assert_eq!(x.nrows(), self.rows.n());
+        assert_eq!(b.nrows(), self.rows.n());
+        let values = mat.val();
+        let x = x.rb();
+
+        let relax_color = |color: usize| {
+            self.colors.for_each_row(color, |i| {
+                // SAFETY: rows of a color are updated by a single thread each and never read
+                // each other
+                let mut x = unsafe { x.const_cast() };
+                let sum = self.rows.row_dot(i, values, |j| x[j].clone());
+                x[i] = b[i]
+                    .sub_by_ref(&sum)
+                    .mul_by_ref(&recip(self.rows.diag(i, values)));
+            });
+        };
+        for _ in 0..n_sweeps {
+            if sweep != Sweep::Backward {
+                (0..self.n_colors()).for_each(relax_color);
+            }
+            if sweep != Sweep::Forward {
+                (0..self.n_colors()).rev().for_each(relax_color);
+            }
+        }
+    }
+}
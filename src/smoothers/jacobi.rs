@@ -0,0 +1,233 @@
+//! Weighted Jacobi and l1-Jacobi.
+//!
+//! A sweep computes `x += omega D^-1 (b - A x)` in a single fused pass over the matrix, see
+//! `residual_sweep`.
+//!
+//! The l1 variant adds the absolute row sum of the off-diagonal entries to the diagonal, which
+//! makes the sweep convergent for every symmetric positive definite matrix without tuning `omega`.
+use std::ops::Range;
+use std::thread;
+
+use faer::{
+    Col, ColMut, ColRef, Index, Mat, Par,
+    dyn_stack::{MemStack, StackReq},
+    prelude::ReborrowMut,
+    sparse::SparseColMatRef,
+    traits::{
+        AddByRef, ComplexField,
+        math_utils::{abs, from_real, recip},
+    },
+};
+
+use super::{residual_sweep, residual_sweep_scratch};
+use crate::sparse_dense_impl::simple::reduce_workspaces_with;
+use crate::spmv_drivers::SpMvStrategy;
+
+/// The diagonal `D` a Jacobi sweep divides by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JacobiDiagonal {
+    /// `D_ii = A_ii`
+    Plain,
+    /// `D_ii = A_ii + sum_{j != i} |A_ij|`
+    L1,
+}
+
+/// Jacobi smoother for a fixed matrix, holding its scaled inverse diagonal and SpMV plan
+pub struct JacobiSmoother<T: ComplexField> {
+    diagonal: JacobiDiagonal,
+    omega: T::Real,
+    /// `omega / D_ii`
+    scaled_inv_diag: Col<T>,
+    nnz: usize,
+    par: Par,
+    strategy: SpMvStrategy,
+}
+
+/// Store the diagonal entries among the entries `entries` of the columns `cols` of `mat` in
+/// `diag`, and add the absolute values of the other ones to their row of `off_diag` if given.
+/// Returns the number of diagonal entries found.
+fn scan_entries<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    cols: Range<usize>,
+    entries: Range<usize>,
+    mut diag: ColMut<'_, T>,
+    mut off_diag: Option<ColMut<'_, T::Real>>,
+) -> usize {
+    let (symbolic, values) = mat.parts();
+    let row_indices = symbolic.row_idx();
+    let mut n_diag = 0;
+    for j in cols {
+        let col_range = symbolic.col_range(j);
+        let col_range = col_range.start.max(entries.start)..col_range.end.min(entries.end);
+        for idx in col_range {
+            let i = row_indices[idx].zx();
+            if i == j {
+                diag[i] = values[idx].clone();
+                n_diag += 1;
+            } else if let Some(off_diag) = off_diag.as_mut() {
+                off_diag[i] = off_diag[i].add_by_ref(&abs(&values[idx]));
+            }
+        }
+    }
+    n_diag
+}
+
+/// Panic unless every diagonal entry was found, since a missing one would divide by zero
+fn assert_diagonal_stored(n_diag: usize, n: usize) {
+    assert_eq!(n_diag, n, "the diagonal of the matrix must be stored");
+}
+
+impl<T: ComplexField> JacobiSmoother<T> {
+    /// Extract the diagonal of the square matrix `mat`, which must be stored (panics otherwise),
+    /// and plan its SpMV for `par`
+    pub fn new<I: Index>(
+        mat: SparseColMatRef<'_, I, T>,
+        diagonal: JacobiDiagonal,
+        omega: T::Real,
+        par: Par,
+    ) -> Self {
+        assert_eq!(mat.nrows(), mat.ncols());
+        let mut smoother = Self {
+            diagonal,
+            omega,
+            scaled_inv_diag: Col::zeros(mat.nrows()),
+            nnz: mat.compute_nnz(),
+            par,
+            strategy: SpMvStrategy::new(mat.symbolic(), par),
+        };
+        smoother.update_values(mat);
+        smoother
+    }
+
+    /// Extract the diagonal of a matrix with the same pattern as the one the smoother was built
+    /// from, keeping the plan
+    pub fn update_values<I: Index>(&mut self, mat: SparseColMatRef<'_, I, T>) {
+        self.check(mat);
+        let n = mat.nrows();
+        let l1 = self.diagonal == JacobiDiagonal::L1;
+        let mut diag = Col::<T>::zeros(n);
+
+        match self.par {
+            Par::Seq => {
+                let mut off_diag = Col::<T::Real>::zeros(if l1 { n } else { 0 });
+                let n_diag = scan_entries(
+                    mat,
+                    0..n,
+                    0..self.nnz,
+                    diag.as_mut(),
+                    l1.then_some(off_diag.as_mut()),
+                );
+                assert_diagonal_stored(n_diag, n);
+                for (i, dst) in self.scaled_inv_diag.iter_mut().enumerate() {
+                    let mut d_i = diag[i].clone();
+                    if l1 {
+                        d_i = d_i.add_by_ref(&from_real(&off_diag[i]));
+                    }
+                    *dst = recip(&d_i).mul_by_ref(&from_real(&self.omega));
+                }
+            }
+            Par::Rayon(n_threads) => {
+                let n_threads = n_threads.get();
+                // without l1 sums there are no workspaces and the reduction sums to zero
+                let work = Mat::<T::Real>::zeros(n, if l1 { n_threads } else { 0 });
+                let diag = diag.as_ref();
+                let strategy = &self.strategy;
+                let n_diag: usize = thread::scope(|s| {
+                    let mut handles = Vec::with_capacity(n_threads);
+                    for tid in 0..n_threads {
+                        let work = work.as_ref();
+                        let handle = s.spawn(move || {
+                            // SAFETY: every diagonal entry is stored once, so it is written by a
+                            // single thread, and each thread gets its own workspace vector
+                            let diag = unsafe { diag.const_cast() };
+                            let off_diag =
+                                (tid < work.ncols()).then(|| unsafe { work.col(tid).const_cast() });
+                            scan_entries(
+                                mat,
+                                strategy.thread_cols[tid]..strategy.thread_cols[tid + 1] + 1,
+                                strategy.thread_indptrs[tid]..strategy.thread_indptrs[tid + 1],
+                                diag,
+                                off_diag,
+                            )
+                        });
+                        handles.push(handle);
+                    }
+                    handles.into_iter().map(|h| h.join().unwrap()).sum()
+                });
+                assert_diagonal_stored(n_diag, n);
+
+                let scaled_inv_diag = self.scaled_inv_diag.as_ref();
+                let omega = from_real::<T>(&self.omega);
+                reduce_workspaces_with(strategy, work.as_ref(), |row_start, sums, _: &mut ()| {
+                    // SAFETY: every row is handed to exactly one call of the closure
+                    let mut scaled_inv_diag = unsafe { scaled_inv_diag.const_cast() };
+                    for (i, off_diag) in (row_start..).zip(sums) {
+                        let d_i = diag[i].add_by_ref(&from_real(off_diag));
+                        scaled_inv_diag[i] = recip(&d_i).mul_by_ref(&omega);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Panic unless `mat` has the dimensions and nnz of the matrix the smoother was built from
+    pub(crate) fn check<I: Index>(&self, mat: SparseColMatRef<'_, I, T>) {
+        assert_eq!(mat.nrows(), self.scaled_inv_diag.nrows());
+        assert_eq!(mat.ncols(), self.scaled_inv_diag.nrows());
+        assert_eq!(mat.compute_nnz(), self.nnz);
+    }
+
+    pub fn diagonal(&self) -> JacobiDiagonal {
+        self.diagonal
+    }
+
+    pub fn omega(&self) -> &T::Real {
+        &self.omega
+    }
+
+    /// `omega / D_ii` for every row
+    pub fn scaled_inv_diag(&self) -> ColRef<'_, T> {
+        self.scaled_inv_diag.as_ref()
+    }
+
+    pub fn par(&self) -> Par {
+        self.par
+    }
+
+    pub fn strategy(&self) -> &SpMvStrategy {
+        &self.strategy
+    }
+
+    pub fn scratch(&self) -> StackReq {
+        residual_sweep_scratch::<T>(self.scaled_inv_diag.nrows(), self.par)
+    }
+
+    /// Run `n_sweeps` sweeps on `A x = b`, updating `x` in place. `mat` must be the matrix the
+    /// diagonal was extracted from.
+    pub fn smooth<I: Index>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+        x: ColMut<'_, T>,
+        b: ColRef<'_, T>,
+        n_sweeps: usize,
+        stack: &mut MemStack,
+    ) {
+        self.check(mat);
+        let n = mat.nrows();
+        assert_eq!(x.nrows(), n);
+        assert_eq!(b.nrows(), n);
+        let scaled_inv_diag = self.scaled_inv_diag.as_ref();
+        let mut x = x;
+        for _ in 0..n_sweeps {
+            residual_sweep(
+                mat,
+                x.rb_mut(),
+                b,
+                self.par,
+                &self.strategy,
+                stack,
+                |i, r_i| scaled_inv_diag[i].mul_by_ref(r_i),
+            );
+        }
+    }
+}
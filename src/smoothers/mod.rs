@@ -0,0 +1,92 @@
+//! Parallel smoothers for multigrid and stationary iterations.
+use std::thread;
+
+use faer::{
+    Accum, ColMut, ColRef, Index, Par,
+    col::AsColMut,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::Reborrow,
+    sparse::{SparseColMatRef, linalg::matmul::sparse_dense_matmul as seq_sparse_dense},
+    traits::{
+        ComplexField,
+        math_utils::{neg, one},
+    },
+};
+
+use crate::sparse_dense_impl::simple::{reduce_workspaces_with, scatter_thread_range};
+use crate::spmv_drivers::SpMvStrategy;
+
+pub mod chebyshev;
+pub mod gauss_seidel;
+pub mod jacobi;
+
+pub(crate) fn residual_sweep_scratch<T: ComplexField>(n: usize, par: Par) -> StackReq {
+    match par {
+        Par::Seq => temp_mat_scratch::<T>(n, 1),
+        Par::Rayon(n_threads) => temp_mat_scratch::<T>(n, n_threads.get()),
+    }
+}
+
+/// `x_i += update(i, r_i)` for every row, with `r = b - A x` taken before the sweep.
+///
+/// In parallel this is the `simple` kernel with `alpha = -1`: the scatter pass accumulates `-A x`
+/// into the per-thread workspaces and the reduction sweep adds `b` and applies the update to the
+/// rows it owns, so the residual is never written out. The reduction only starts once every thread
+/// is done reading `x`, so the update is done in place. `update` is called once per row.
+pub(crate) fn residual_sweep<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+    update: impl Fn(usize, &T) -> T + Sync,
+) {
+    let n = mat.nrows();
+    let neg_one = neg(&one::<T>());
+
+    match par {
+        Par::Seq => {
+            let mut x = x;
+            let (mut r, _) = temp_mat_zeroed::<T, _, _>(n, 1, stack);
+            let mut r = r.as_mat_mut();
+            r.as_mut().col_mut(0).copy_from(b);
+            seq_sparse_dense(r.as_mut(), Accum::Add, mat, x.rb().as_mat(), neg_one, par);
+            for i in 0..n {
+                x[i] = x[i].add_by_ref(&update(i, &r[(i, 0)]));
+            }
+        }
+        Par::Rayon(n_threads) => {
+            let n_threads = n_threads.get();
+            let x = x.rb();
+            let (mut work, _) = temp_mat_zeroed::<T, _, _>(n, n_threads, stack);
+            let work = work.as_mat_mut();
+            let work = work.rb();
+
+            thread::scope(|s| {
+                for tid in 0..n_threads {
+                    let neg_one = &neg_one;
+                    s.spawn(move || {
+                        // SAFETY each thread gets its own workspace vector to be summed when all
+                        // complete
+                        let mut work =
+                            unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
+                        scatter_thread_range(tid, mat, x, neg_one, strategy, work.as_col_mut());
+                    });
+                }
+            });
+
+            reduce_workspaces_with(strategy, work, |row_start, sums, _: &mut ()| {
+                // SAFETY: every row is handed to exactly one call of the closure, and the scatter
+                // pass reading `x` is done
+                let mut x = unsafe { x.const_cast() };
+                for (i, neg_ax) in (row_start..).zip(sums) {
+                    let r_i = b[i].add_by_ref(neg_ax);
+                    x[i] = x[i].add_by_ref(&update(i, &r_i));
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! BiCGStab for general systems, optionally right preconditioned.
+//!
+//! An iteration costs two operator and two preconditioner applications. `<r_hat, v>` and
+//! `<t, s>` come from `apply_dot`, and the updates of `x` and `r` share a pass with `||r||^2` and
+//! the `<r_hat, r>` of the next iteration.
+use faer::{
+    ColMut, ColRef, Par,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{IntoConst, Reborrow, ReborrowMut},
+    traits::{
+        AddByRef, ComplexField,
+        math_utils::{abs2, conj, from_real, one, recip, sqrt, zero},
+    },
+};
+
+use super::{SolveInfo, StoppingCriteria};
+use crate::operator::LinearOperator;
+use crate::vector_ops::{Sum, dot, for_each_block};
+
+/// Scratch space of `bicgstab`
+pub fn bicgstab_scratch<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+) -> StackReq {
+    let (n_vectors, operators) = match m {
+        Some(m) => (8, a.scratch_req().or(m.scratch_req())),
+        None => (6, a.scratch_req()),
+    };
+    temp_mat_scratch::<T>(a.nrows(), n_vectors).and(operators)
+}
+
+/// Partials of the fused update at the end of an iteration
+struct UpdatePartials<T: ComplexField> {
+    r_norm_sq: T::Real,
+    rho: T,
+}
+
+impl<T: ComplexField> Default for UpdatePartials<T> {
+    fn default() -> Self {
+        Self {
+            r_norm_sq: zero(),
+            rho: zero(),
+        }
+    }
+}
+
+/// Solve `A x = b` with BiCGStab, starting from the given `x` and right preconditioned by `m` if
+/// given
+pub fn bicgstab<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    criteria: &StoppingCriteria<T::Real>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SolveInfo<T::Real> {
+    let n = a.nrows();
+    assert_eq!(a.ncols(), n);
+    assert_eq!(x.nrows(), n);
+    assert_eq!(b.nrows(), n);
+    if let Some(m) = m {
+        assert_eq!((m.nrows(), m.ncols()), (n, n));
+    }
+
+    let n_vectors = if m.is_some() { 8 } else { 6 };
+    let (mut vectors, stack) = temp_mat_zeroed::<T, _, _>(n, n_vectors, stack);
+    let mut vectors = vectors.as_mat_mut();
+    let mut vectors = vectors.rb_mut().col_iter_mut();
+    let mut next = || vectors.next().unwrap();
+    let (mut r, r_hat, p, mut v, s, mut t) = (next(), next(), next(), next(), next(), next());
+    // the preconditioned directions, which are `p` and `s` themselves without a preconditioner
+    let mut p_hat = m.map(|_| next());
+    let mut s_hat = m.map(|_| next());
+    let x = x.rb();
+
+    // r = b - A x, r_hat = r
+    a.apply(r.rb_mut(), x, stack);
+    let (r_view, r_hat_view) = (r.rb(), r_hat.rb());
+    let mut r_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+        // SAFETY: the blocks are disjoint
+        let mut r = unsafe { r_view.const_cast() };
+        let mut r_hat = unsafe { r_hat_view.const_cast() };
+        for i in rows {
+            r[i] = b[i].sub_by_ref(&r[i]);
+            r_hat[i] = r[i].clone();
+            acc.0 = acc.0.add_by_ref(&abs2(&r[i]));
+        }
+    }));
+
+    let threshold = criteria.threshold(&b.norm_l2());
+    let mut history = vec![sqrt(&r_norm_sq)];
+    let mut converged = history[0] <= threshold;
+
+    // rho = <r_hat, r>, with r_hat = r at the start
+    let mut rho = from_real::<T>(&r_norm_sq);
+    let (mut rho_prev, mut alpha, mut omega) = (one::<T>(), one::<T>(), one::<T>());
+    let mut iterations = 0;
+    while !converged && iterations < criteria.max_iters {
+        if rho == zero() {
+            break;
+        }
+
+        // p = r + beta (p - omega v), p_hat = M p
+        let beta = rho
+            .mul_by_ref(&recip(&rho_prev))
+            .mul_by_ref(&alpha.mul_by_ref(&recip(&omega)));
+        let (p_view, v_view, r_view) = (p.rb(), v.rb(), r.rb());
+        for_each_block(n, par, |rows, _: &mut ()| {
+            // SAFETY: the blocks are disjoint
+            let mut p = unsafe { p_view.const_cast() };
+            for i in rows {
+                let p_i = p[i].sub_by_ref(&omega.mul_by_ref(&v_view[i]));
+                p[i] = r_view[i].add_by_ref(&beta.mul_by_ref(&p_i));
+            }
+        });
+        let p_hat = preconditioned(m, p.rb(), p_hat.as_mut().map(|p| p.rb_mut()), stack);
+
+        // v = A p_hat, alpha = rho / <r_hat, v>
+        let r_hat_v = conj(&a.apply_dot(v.rb_mut(), p_hat, r_hat.rb(), stack));
+        if r_hat_v == zero() {
+            break;
+        }
+        alpha = rho.mul_by_ref(&recip(&r_hat_v));
+
+        // s = r - alpha v
+        let (s_view, v_view) = (s.rb(), v.rb());
+        let s_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+            // SAFETY: the blocks are disjoint
+            let mut s = unsafe { s_view.const_cast() };
+            for i in rows {
+                s[i] = r_view[i].sub_by_ref(&alpha.mul_by_ref(&v_view[i]));
+                acc.0 = acc.0.add_by_ref(&abs2(&s[i]));
+            }
+        }));
+        if sqrt(&s_norm_sq) <= threshold {
+            // x += alpha p_hat already solves the system
+            for_each_block(n, par, |rows, _: &mut ()| {
+                // SAFETY: the blocks are disjoint
+                let mut x = unsafe { x.const_cast() };
+                for i in rows {
+                    x[i] = x[i].add_by_ref(&alpha.mul_by_ref(&p_hat[i]));
+                }
+            });
+            iterations += 1;
+            history.push(sqrt(&s_norm_sq));
+            converged = true;
+            break;
+        }
+
+        // t = A s_hat, omega = <t, s> / <t, t>
+        let s_hat = preconditioned(m, s.rb(), s_hat.as_mut().map(|s| s.rb_mut()), stack);
+        let t_s = a.apply_dot(t.rb_mut(), s_hat, s.rb(), stack);
+        let t_t = dot(t.rb(), t.rb(), par);
+        if t_t == zero() {
+            break;
+        }
+        omega = t_s.mul_by_ref(&recip(&t_t));
+
+        // x += alpha p_hat + omega s_hat, r = s - omega t, rho = <r_hat, r>
+        let (t_view, r_hat_view) = (t.rb(), r_hat.rb());
+        let partials = for_each_block(n, par, |rows, acc: &mut UpdatePartials<T>| {
+            // SAFETY: the blocks are disjoint
+            let mut x = unsafe { x.const_cast() };
+            let mut r = unsafe { r_view.const_cast() };
+            for i in rows {
+                x[i] = x[i]
+                    .add_by_ref(&alpha.mul_by_ref(&p_hat[i]))
+                    .add_by_ref(&omega.mul_by_ref(&s_hat[i]));
+                r[i] = s_view[i].sub_by_ref(&omega.mul_by_ref(&t_view[i]));
+                acc.r_norm_sq = acc.r_norm_sq.add_by_ref(&abs2(&r[i]));
+                acc.rho = acc.rho.add_by_ref(&conj(&r_hat_view[i]).mul_by_ref(&r[i]));
+            }
+        });
+        rho_prev = rho;
+        (r_norm_sq, rho) = (zero(), zero());
+        for partial in partials {
+            r_norm_sq = r_norm_sq.add_by_ref(&partial.r_norm_sq);
+            rho = rho.add_by_ref(&partial.rho);
+        }
+
+        iterations += 1;
+        history.push(sqrt(&r_norm_sq));
+        converged = history[iterations] <= threshold;
+        if omega == zero() {
+            break;
+        }
+    }
+
+    SolveInfo {
+        converged,
+        iterations,
+        residual_norm: history.last().unwrap().clone(),
+        history,
+    }
+}
+
+/// `z = M v`, returning `z`, or `v` itself without a preconditioner
+fn preconditioned<'a, T: ComplexField>(
+    m: Option<&dyn LinearOperator<T>>,
+    v: ColRef<'a, T>,
+    z: Option<ColMut<'a, T>>,
+    stack: &mut MemStack,
+) -> ColRef<'a, T> {
+    match (m, z) {
+        (Some(m), Some(mut z)) => {
+            m.apply(z.rb_mut(), v, stack);
+            z.into_const()
+        }
+        _ => v,
+    }
+}
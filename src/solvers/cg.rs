@@ -0,0 +1,188 @@
+//! Conjugate gradients for Hermitian positive definite systems, optionally preconditioned.
+//!
+//! Every iteration costs one operator application, one preconditioner application and three passes
+//! over the vectors: `A p` comes with `<A p, p>` from `apply_dot`, the updates of `x` and `r`
+//! share a pass with `||r||^2`, and `p = z + beta p` follows `<r, z>`. Without a preconditioner
+//! `z = r`, so the `<r, z>` pass is skipped as well.
+use faer::{
+    ColMut, ColRef, Par,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    traits::{
+        AddByRef, ComplexField,
+        math_utils::{abs2, from_real, recip, sqrt, zero},
+    },
+};
+
+use super::{SolveInfo, StoppingCriteria};
+use crate::operator::LinearOperator;
+use crate::vector_ops::{Sum, dot, for_each_block};
+
+/// Scratch space of `cg` (`m == None`) and `pcg`
+pub fn cg_scratch<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+) -> StackReq {
+    let n_vectors = if m.is_some() { 4 } else { 3 };
+    let operators = match m {
+        Some(m) => a.scratch_req().or(m.scratch_req()),
+        None => a.scratch_req(),
+    };
+    temp_mat_scratch::<T>(a.nrows(), n_vectors).and(operators)
+}
+
+/// Solve `A x = b` with conjugate gradients, starting from the given `x`
+pub fn cg<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    criteria: &StoppingCriteria<T::Real>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SolveInfo<T::Real> {
+    solve(a, None, x, b, criteria, par, stack)
+}
+
+/// Solve `A x = b` with conjugate gradients preconditioned by `m`, which approximates `A^-1` and
+/// must be Hermitian positive definite as well
+pub fn pcg<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: &dyn LinearOperator<T>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    criteria: &StoppingCriteria<T::Real>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SolveInfo<T::Real> {
+    solve(a, Some(m), x, b, criteria, par, stack)
+}
+
+fn solve<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    criteria: &StoppingCriteria<T::Real>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SolveInfo<T::Real> {
+    let n = a.nrows();
+    assert_eq!(a.ncols(), n);
+    assert_eq!(x.nrows(), n);
+    assert_eq!(b.nrows(), n);
+    if let Some(m) = m {
+        assert_eq!((m.nrows(), m.ncols()), (n, n));
+    }
+
+    let n_vectors = if m.is_some() { 4 } else { 3 };
+    let (mut vectors, stack) = temp_mat_zeroed::<T, _, _>(n, n_vectors, stack);
+    let vectors = vectors.as_mat_mut();
+    let (r, vectors) = vectors.split_at_col_mut(1);
+    let (p, vectors) = vectors.split_at_col_mut(1);
+    let (q, z) = vectors.split_at_col_mut(1);
+    let (mut r, mut p, mut q) = (r.col_mut(0), p.col_mut(0), q.col_mut(0));
+    let mut z = (z.ncols() > 0).then(|| z.col_mut(0));
+    let x = x.rb();
+
+    // r = b - A x
+    a.apply(r.rb_mut(), x, stack);
+    let r_view = r.rb();
+    let mut r_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+        // SAFETY: the blocks are disjoint
+        let mut r = unsafe { r_view.const_cast() };
+        for i in rows {
+            r[i] = b[i].sub_by_ref(&r[i]);
+            acc.0 = acc.0.add_by_ref(&abs2(&r[i]));
+        }
+    }));
+
+    let threshold = criteria.threshold(&b.norm_l2());
+    let mut history = vec![sqrt(&r_norm_sq)];
+    let mut converged = history[0] <= threshold;
+
+    let mut rz = precondition_dot(
+        m,
+        r.rb(),
+        z.as_mut().map(|z| z.rb_mut()),
+        &r_norm_sq,
+        par,
+        stack,
+    );
+    p.copy_from(z.as_ref().map_or(r.rb(), |z| z.rb()));
+
+    let mut iterations = 0;
+    while !converged && iterations < criteria.max_iters {
+        let pq = a.apply_dot(q.rb_mut(), p.rb(), p.rb(), stack);
+        if pq == zero() {
+            break;
+        }
+        iterations += 1;
+        let alpha = rz.mul_by_ref(&recip(&pq));
+
+        let (p_view, q_view, r_view) = (p.rb(), q.rb(), r.rb());
+        r_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+            // SAFETY: the blocks are disjoint
+            let mut x = unsafe { x.const_cast() };
+            let mut r = unsafe { r_view.const_cast() };
+            for i in rows {
+                x[i] = x[i].add_by_ref(&alpha.mul_by_ref(&p_view[i]));
+                r[i] = r[i].sub_by_ref(&alpha.mul_by_ref(&q_view[i]));
+                acc.0 = acc.0.add_by_ref(&abs2(&r[i]));
+            }
+        }));
+        history.push(sqrt(&r_norm_sq));
+        if history[iterations] <= threshold {
+            converged = true;
+            break;
+        }
+
+        let rz_next = precondition_dot(
+            m,
+            r.rb(),
+            z.as_mut().map(|z| z.rb_mut()),
+            &r_norm_sq,
+            par,
+            stack,
+        );
+        let beta = rz_next.mul_by_ref(&recip(&rz));
+        rz = rz_next;
+
+        let z_view = z.as_ref().map_or(r.rb(), |z| z.rb());
+        let p_view = p.rb();
+        for_each_block(n, par, |rows, _: &mut ()| {
+            // SAFETY: the blocks are disjoint
+            let mut p = unsafe { p_view.const_cast() };
+            for i in rows {
+                p[i] = z_view[i].add_by_ref(&beta.mul_by_ref(&p[i]));
+            }
+        });
+    }
+
+    SolveInfo {
+        converged,
+        iterations,
+        residual_norm: history[iterations].clone(),
+        history,
+    }
+}
+
+/// `z = M r`, returning `<r, z>`. Without a preconditioner `z` is `r` itself and `<r, r>` is the
+/// already known `||r||^2`.
+fn precondition_dot<T: ComplexField>(
+    m: Option<&dyn LinearOperator<T>>,
+    r: ColRef<'_, T>,
+    z: Option<ColMut<'_, T>>,
+    r_norm_sq: &T::Real,
+    par: Par,
+    stack: &mut MemStack,
+) -> T {
+    match (m, z) {
+        (Some(m), Some(mut z)) => {
+            m.apply(z.rb_mut(), r, stack);
+            dot(r, z.rb(), par)
+        }
+        _ => from_real(r_norm_sq),
+    }
+}
@@ -0,0 +1,283 @@
+//! Restarted GMRES for general systems, optionally right preconditioned.
+//!
+//! Solves `A M y = b` with `x = x_0 + M y`, so the residual norm tracked by the Givens rotations of
+//! the Hessenberg matrix is the one of the unpreconditioned system. The new basis vector is
+//! orthogonalized with two passes of classical Gram–Schmidt: each pass computes the coefficients of
+//! all previous basis vectors in a single parallel sweep and subtracts them in a second one that
+//! also accumulates the norm, so a step costs four passes over the basis regardless of its size,
+//! with the loss of orthogonality of a single classical pass corrected by the second one.
+use faer::{
+    ColMut, ColRef, MatRef, Par,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    traits::{
+        AddByRef, ComplexField,
+        math_utils::{abs, abs2, conj, from_real, mul_real, neg, recip, sqrt, zero},
+    },
+};
+
+use super::{SolveInfo, StoppingCriteria};
+use crate::operator::LinearOperator;
+use crate::vector_ops::{Sum, for_each_block, scale};
+
+/// Scratch space of `gmres` with `restart` steps per cycle
+pub fn gmres_scratch<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+    restart: usize,
+) -> StackReq {
+    let n = a.nrows();
+    let (n_vectors, operators) = match m {
+        Some(m) => (2, a.scratch_req().or(m.scratch_req())),
+        None => (0, a.scratch_req()),
+    };
+    temp_mat_scratch::<T>(n, restart + 1)
+        .and(temp_mat_scratch::<T>(n, n_vectors))
+        .and(operators)
+}
+
+/// Solve `A x = b` with GMRES restarted every `restart` steps, starting from the given `x` and
+/// right preconditioned by `m` if given. Every step counts as one iteration.
+pub fn gmres<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    m: Option<&dyn LinearOperator<T>>,
+    x: ColMut<'_, T>,
+    b: ColRef<'_, T>,
+    restart: usize,
+    criteria: &StoppingCriteria<T::Real>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SolveInfo<T::Real> {
+    let n = a.nrows();
+    assert_eq!(a.ncols(), n);
+    assert_eq!(x.nrows(), n);
+    assert_eq!(b.nrows(), n);
+    if let Some(m) = m {
+        assert_eq!((m.nrows(), m.ncols()), (n, n));
+    }
+    assert!(restart > 0);
+
+    let (mut basis, stack) = temp_mat_zeroed::<T, _, _>(n, restart + 1, stack);
+    let mut basis = basis.as_mat_mut();
+    let n_vectors = if m.is_some() { 2 } else { 0 };
+    let (mut vectors, stack) = temp_mat_zeroed::<T, _, _>(n, n_vectors, stack);
+    let mut vectors = vectors.as_mat_mut();
+    let mut x = x;
+
+    // Hessenberg matrix, column major with `restart + 1` rows, reduced to upper triangular by the
+    // rotations as it is built
+    let mut h = vec![zero::<T>(); (restart + 1) * restart];
+    let mut rotations: Vec<(T::Real, T)> = Vec::with_capacity(restart);
+    let mut g = vec![zero::<T>(); restart + 1];
+
+    let threshold = criteria.threshold(&b.norm_l2());
+    let mut history = Vec::new();
+    let mut iterations = 0;
+    let mut converged = false;
+    loop {
+        // v_0 = r / ||r|| with r = b - A x
+        let mut v0 = basis.rb_mut().col_mut(0);
+        a.apply(v0.rb_mut(), x.rb(), stack);
+        let v0_view = v0.rb();
+        let r_norm = sqrt(&Sum::total(for_each_block(
+            n,
+            par,
+            |rows, acc: &mut Sum<T::Real>| {
+                // SAFETY: the blocks are disjoint
+                let mut v0 = unsafe { v0_view.const_cast() };
+                for i in rows {
+                    v0[i] = b[i].sub_by_ref(&v0[i]);
+                    acc.0 = acc.0.add_by_ref(&abs2(&v0[i]));
+                }
+            },
+        )));
+        if history.is_empty() {
+            history.push(r_norm.clone());
+        }
+        if r_norm <= threshold {
+            converged = true;
+            break;
+        }
+        if iterations >= criteria.max_iters {
+            break;
+        }
+        scale(v0, &recip(&r_norm), par);
+
+        h.iter_mut().for_each(|h| *h = zero());
+        g.iter_mut().for_each(|g| *g = zero());
+        g[0] = from_real(&r_norm);
+        rotations.clear();
+
+        let mut n_steps = 0;
+        while n_steps < restart && iterations < criteria.max_iters {
+            let j = n_steps;
+            let (done, mut next) = basis.rb_mut().split_at_col_mut(j + 1);
+            let mut w = next.rb_mut().col_mut(0);
+            match m {
+                Some(m) => {
+                    let mut z = vectors.rb_mut().col_mut(0);
+                    m.apply(z.rb_mut(), done.rb().col(j), stack);
+                    a.apply(w.rb_mut(), z.rb(), stack);
+                }
+                None => a.apply(w.rb_mut(), done.rb().col(j), stack),
+            }
+
+            let h_col = &mut h[j * (restart + 1)..(j + 1) * (restart + 1)];
+            let w_norm = orthogonalize(done.rb(), w.rb_mut(), &mut h_col[..j + 1], par);
+            h_col[j + 1] = from_real(&w_norm);
+            let breakdown = w_norm == zero();
+            if !breakdown {
+                scale(w, &recip(&w_norm), par);
+            }
+
+            for (k, (c, s)) in rotations.iter().enumerate() {
+                rotate(c, s, &mut h_col[k..k + 2]);
+            }
+            let (c, s) = givens(&h_col[j], &h_col[j + 1]);
+            rotate(&c, &s, &mut h_col[j..j + 2]);
+            rotate(&c, &s, &mut g[j..j + 2]);
+            rotations.push((c, s));
+
+            n_steps += 1;
+            iterations += 1;
+            let residual = abs(&g[j + 1]);
+            history.push(residual.clone());
+            if residual <= threshold || breakdown {
+                converged = residual <= threshold;
+                break;
+            }
+        }
+
+        // y = R^-1 g, x += M V y
+        let mut y = g[..n_steps].to_vec();
+        for k in (0..n_steps).rev() {
+            let h_col = &h[k * (restart + 1)..];
+            y[k] = y[k].mul_by_ref(&recip(&h_col[k]));
+            for i in 0..k {
+                y[i] = y[i].sub_by_ref(&h_col[i].mul_by_ref(&y[k]));
+            }
+        }
+        let basis_view = basis.rb().subcols(0, n_steps);
+        match m {
+            Some(m) => {
+                let (mut u, mut z) = vectors.rb_mut().split_at_col_mut(1);
+                let mut u = u.rb_mut().col_mut(0);
+                combine(basis_view, &y, u.rb_mut(), false, par);
+                let mut z = z.rb_mut().col_mut(0);
+                m.apply(z.rb_mut(), u.rb(), stack);
+                add(x.rb_mut(), z.rb(), par);
+            }
+            None => combine(basis_view, &y, x.rb_mut(), true, par),
+        }
+
+        if converged || iterations >= criteria.max_iters {
+            break;
+        }
+    }
+
+    SolveInfo {
+        converged,
+        iterations,
+        residual_norm: history.last().unwrap().clone(),
+        history,
+    }
+}
+
+/// Make `w` orthogonal to the orthonormal columns of `basis` with two passes of classical
+/// Gram–Schmidt, adding the coefficients to `h`, and return `||w||`
+fn orthogonalize<T: ComplexField>(
+    basis: MatRef<'_, T>,
+    w: ColMut<'_, T>,
+    h: &mut [T],
+    par: Par,
+) -> T::Real {
+    let n = basis.nrows();
+    let k = basis.ncols();
+    let w = w.rb();
+    let mut w_norm_sq = zero();
+    for _ in 0..2 {
+        let partials = for_each_block(n, par, |rows, acc: &mut Vec<T>| {
+            acc.resize(k, zero());
+            for (c, acc) in acc.iter_mut().enumerate() {
+                let v = basis.col(c);
+                for i in rows.clone() {
+                    *acc = acc.add_by_ref(&conj(&v[i]).mul_by_ref(&w[i]));
+                }
+            }
+        });
+        let mut coeffs = vec![zero::<T>(); k];
+        for partial in partials {
+            for (coeff, p) in coeffs.iter_mut().zip(partial) {
+                *coeff = coeff.add_by_ref(&p);
+            }
+        }
+
+        let coeffs = &coeffs;
+        w_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+            // SAFETY: the blocks are disjoint
+            let mut w = unsafe { w.const_cast() };
+            for i in rows {
+                let mut w_i = w[i].clone();
+                for (c, coeff) in coeffs.iter().enumerate() {
+                    w_i = w_i.sub_by_ref(&basis[(i, c)].mul_by_ref(coeff));
+                }
+                acc.0 = acc.0.add_by_ref(&abs2(&w_i));
+                w[i] = w_i;
+            }
+        }));
+        for (h, coeff) in h.iter_mut().zip(coeffs) {
+            *h = h.add_by_ref(coeff);
+        }
+    }
+    sqrt(&w_norm_sq)
+}
+
+/// `dst (+)= basis y`
+fn combine<T: ComplexField>(
+    basis: MatRef<'_, T>,
+    y: &[T],
+    dst: ColMut<'_, T>,
+    accumulate: bool,
+    par: Par,
+) {
+    let dst = dst.rb();
+    for_each_block(basis.nrows(), par, |rows, _: &mut ()| {
+        // SAFETY: the blocks are disjoint
+        let mut dst = unsafe { dst.const_cast() };
+        for i in rows {
+            let mut sum = if accumulate { dst[i].clone() } else { zero() };
+            for (c, y_c) in y.iter().enumerate() {
+                sum = sum.add_by_ref(&basis[(i, c)].mul_by_ref(y_c));
+            }
+            dst[i] = sum;
+        }
+    });
+}
+
+fn add<T: ComplexField>(dst: ColMut<'_, T>, rhs: ColRef<'_, T>, par: Par) {
+    combine(rhs.as_mat(), &[T::one_impl()], dst, true, par);
+}
+
+/// Rotation `(c, s)` with real `c` that maps `(a, b)` to `(r, 0)`
+fn givens<T: ComplexField>(a: &T, b: &T) -> (T::Real, T) {
+    let a_abs = abs(a);
+    if a_abs == zero() {
+        return (zero(), T::one_impl());
+    }
+    let r = sqrt(&(abs2(a) + abs2(b)));
+    let c = a_abs.clone() / r.clone();
+    let s = mul_real(
+        &mul_real(a, &recip(&a_abs)).mul_by_ref(&conj(b)),
+        &recip(&r),
+    );
+    (c, s)
+}
+
+/// `(x, y) = (c x + s y, -conj(s) x + c y)`
+fn rotate<T: ComplexField>(c: &T::Real, s: &T, xy: &mut [T]) {
+    let (x, y) = (xy[0].clone(), xy[1].clone());
+    xy[0] = mul_real(&x, c).add_by_ref(&s.mul_by_ref(&y));
+    xy[1] = neg(&conj(s)).mul_by_ref(&x).add_by_ref(&mul_real(&y, c));
+}
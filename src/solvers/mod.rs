@@ -0,0 +1,49 @@
+//! Krylov solvers over `LinearOperator`s.
+//!
+//! The operators bring their own parallel SpMV; the vector updates and reductions in between go
+//! through `vector_ops`, so they run on the rayon pool with `Par::Rayon` and give the same result
+//! for every thread count.
+use faer::traits::{
+    RealField,
+    math_utils::{from_f64, max, zero},
+};
+
+pub mod bicgstab;
+pub mod cg;
+pub mod gmres;
+
+/// Iterations stop once `||r|| <= max(rtol ||b||, atol)` or after `max_iters`
+#[derive(Clone, Debug)]
+pub struct StoppingCriteria<R> {
+    pub rtol: R,
+    pub atol: R,
+    pub max_iters: usize,
+}
+
+impl<R: RealField> Default for StoppingCriteria<R> {
+    fn default() -> Self {
+        Self {
+            rtol: from_f64(1e-8),
+            atol: zero(),
+            max_iters: 1000,
+        }
+    }
+}
+
+impl<R: RealField> StoppingCriteria<R> {
+    pub(crate) fn threshold(&self, b_norm: &R) -> R {
+        max(&(self.rtol.clone() * b_norm.clone()), &self.atol)
+    }
+}
+
+/// Outcome of a solve
+#[derive(Clone, Debug)]
+pub struct SolveInfo<R> {
+    pub converged: bool,
+    pub iterations: usize,
+    /// Norm of the residual as tracked by the iteration, which can drift from `||b - A x||` in
+    /// finite precision
+    pub residual_norm: R,
+    /// `residual_norm` before the first and after every iteration
+    pub history: Vec<R>,
+}
@@ -86,28 +86,41 @@ impl<'a, T: ComplexField> Chunk<'a, T> {
     }
 }
 
-/// Assign contiguous blocks to owners (threads) as evenly as possible.
+/// Assign contiguous blocks to owners (threads) following the row partition `thread_rows` of the
+/// plan: every block goes to the thread owning its middle row. An empty `thread_rows` (a sequential
+/// plan) gives every block to a single owner.
 /// Returns: owner_of_block[b] and for each owner a (row_start,row_end) pair to slice `y`.
-fn assign_blocks(
+pub(crate) fn assign_blocks(
     nrows: usize,
     block_rows: usize,
-    threads: usize,
+    thread_rows: &[usize],
 ) -> (Vec<usize>, Vec<(usize, usize)>) {
-    let num_blocks = (nrows + block_rows - 1) / block_rows;
-    let mut owner_of_block = vec![0usize; num_blocks];
+    let num_blocks = nrows.div_ceil(block_rows);
+    if thread_rows.is_empty() {
+        return (vec![0; num_blocks], vec![(0, nrows)]);
+    }
+    let threads = thread_rows.len() - 1;
+    debug_assert_eq!(thread_rows[threads], nrows);
+
+    let mut owner_of_block = Vec::with_capacity(num_blocks);
+    let mut owner = 0;
+    for b in 0..num_blocks {
+        let mid_row = (b * block_rows + min(nrows, (b + 1) * block_rows)) / 2;
+        while thread_rows[owner + 1] <= mid_row {
+            owner += 1;
+        }
+        owner_of_block.push(owner);
+    }
 
-    let blocks_per_owner = (num_blocks + threads - 1) / threads;
+    // owners are non-decreasing over the blocks, so every owner gets a contiguous run
     let mut row_ranges = Vec::with_capacity(threads);
-
+    let mut b0 = 0;
     for t in 0..threads {
-        let b0 = t * blocks_per_owner;
-        let b1 = min(num_blocks, (t + 1) * blocks_per_owner);
-        for b in b0..b1 {
-            owner_of_block[b] = t;
-        }
+        let b1 = b0 + owner_of_block[b0..].iter().take_while(|&&o| o == t).count();
         let row_start = min(nrows, b0 * block_rows);
         let row_end = min(nrows, b1 * block_rows);
         row_ranges.push((row_start, row_end));
+        b0 = b1;
     }
     (owner_of_block, row_ranges)
 }
@@ -165,7 +178,7 @@ impl<T: ComplexField> BlockScratch<T> {
 
 fn collect_chunks<'a, T: ComplexField>(
     chunk_recv: &Receiver<Vec<Box<Chunk<'a, T>>>>,
-    owner_of_block: &Vec<usize>,
+    owner_of_block: &[usize],
     scratch: &mut BlockScratch<T>,
     empty_chunks: &mut Vec<Chunk<'a, T>>,
     chunk_queue: &Arc<ArrayQueue<Chunk<'a, T>>>,
@@ -256,16 +269,16 @@ fn hot_loop<'a, I: Index, T: ComplexField>(
     row_indices: &[I],
     lhs_values: &[T],
     rhs_k: &T,
-    owner_of_block: &Vec<usize>,
+    owner_of_block: &[usize],
     tid: usize,
     row_start: usize,
     owned_rows: usize,
     mut dst_owned: ColMut<T>,
-    open: &mut Vec<Option<Box<Chunk<'a, T>>>>,
+    open: &mut [Option<Box<Chunk<'a, T>>>],
     chunk_queue: &Arc<ArrayQueue<Chunk<'a, T>>>,
-    full_chunks: &mut Vec<Vec<Box<Chunk<'a, T>>>>,
+    full_chunks: &mut [Vec<Box<Chunk<'a, T>>>],
     empty_chunks: &mut Vec<Chunk<'a, T>>,
-    txs_local: &Vec<Sender<Vec<Box<Chunk<'a, T>>>>>,
+    txs_local: &[Sender<Vec<Box<Chunk<'a, T>>>>],
 ) {
     for idx in col_range {
         let i = row_indices[idx].zx();
@@ -297,20 +310,20 @@ fn hot_loop<'a, I: Index, T: ComplexField>(
 fn buffer_foreign<'a, T: ComplexField>(
     block_id: usize,
     owner: usize,
-    open: &mut Vec<Option<Box<Chunk<'a, T>>>>,
+    open: &mut [Option<Box<Chunk<'a, T>>>],
     i: usize,
     contrib: T,
     chunk_queue: &Arc<ArrayQueue<Chunk<'a, T>>>,
-    full_chunks: &mut Vec<Vec<Box<Chunk<'a, T>>>>,
+    full_chunks: &mut [Vec<Box<Chunk<'a, T>>>],
     empty_chunks: &mut Vec<Chunk<'a, T>>,
-    txs_local: &Vec<Sender<Vec<Box<Chunk<'a, T>>>>>,
+    txs_local: &[Sender<Vec<Box<Chunk<'a, T>>>>],
 ) {
     match open[block_id] {
         Some(ref mut chunk) => {
             if chunk.push(i, contrib) {
                 // full -> send and remove
                 // NOTE: chunk consumed; replace with fresh
-                let mut fresh = get_fresh_chunk(empty_chunks, block_id, &chunk_queue);
+                let mut fresh = get_fresh_chunk(empty_chunks, block_id, chunk_queue);
 
                 std::mem::swap(&mut fresh, chunk);
                 full_chunks[owner].push(fresh);
@@ -322,7 +335,7 @@ fn buffer_foreign<'a, T: ComplexField>(
             }
         }
         None => {
-            let mut chunk = get_fresh_chunk(empty_chunks, block_id, &chunk_queue);
+            let mut chunk = get_fresh_chunk(empty_chunks, block_id, chunk_queue);
             let _cant_be_full = chunk.push(i, contrib);
             open[block_id] = Some(chunk);
         }
@@ -338,10 +351,42 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
     n_threads: usize,
     strategy: &SpMvStrategy,
     stack: &mut MemStack,
+) {
+    par_sparse_dense_impl(dst, beta, lhs, rhs, alpha, n_threads, strategy, stack, true);
+}
+
+/// `par_sparse_dense` without pinning the threads to cores, for thread counts above the core
+/// count or callers that manage affinity themselves
+pub fn par_sparse_dense_unpinned<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    par_sparse_dense_impl(
+        dst, beta, lhs, rhs, alpha, n_threads, strategy, stack, false,
+    );
+}
+
+/// Threads beyond the core count run unpinned even if `pin` is set
+fn par_sparse_dense_impl<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+    pin: bool,
 ) {
     let m = lhs.nrows();
-    let (owner_of_block, row_ranges) = assign_blocks(m, B_ROWS, n_threads);
-    let n_blocks = (m + B_ROWS - 1) / B_ROWS;
+    let (owner_of_block, row_ranges) = assign_blocks(m, B_ROWS, &strategy.thread_rows);
+    let n_blocks = m.div_ceil(B_ROWS);
 
     let (mut array, _) = stack.make_with(K_CAP * n_threads * WS_CHUNKS_PER_THREAD, |_| {
         (0usize, T::zero_impl())
@@ -368,14 +413,14 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
     thread::scope(|s| {
         let mut handles = Vec::with_capacity(n_threads);
         //let start_time = Instant::now();
-        let core_ids = core_affinity::get_core_ids().unwrap();
-        debug_assert!(core_ids.len() >= n_threads);
-        for (tid, core_id) in (0..n_threads).zip(core_ids.into_iter()) {
+        let core_ids = pin.then(|| core_affinity::get_core_ids().unwrap());
+        debug_assert!(core_ids.as_ref().is_none_or(|ids| ids.len() >= n_threads));
+        for (tid, &(row_start, row_end)) in row_ranges.iter().enumerate() {
+            let core_id = core_ids.as_ref().and_then(|ids| ids.get(tid).copied());
             //for tid in 0..n_threads {
-            let txs_local: Vec<Sender<Vec<Box<Chunk<T>>>>> = txs.iter().cloned().collect();
+            let txs_local: Vec<Sender<Vec<Box<Chunk<T>>>>> = txs.to_vec();
             let rx_owned = rxs.pop_front().unwrap();
 
-            let (row_start, row_end) = row_ranges[tid];
             let owned_rows = row_end - row_start;
 
             let owner_of_block = owner_of_block.clone();
@@ -384,8 +429,10 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
             let mut scratch = BlockScratch::new(B_ROWS);
 
             let handle = s.spawn(move || {
-                let res = core_affinity::set_for_current(core_id);
-                debug_assert!(res);
+                if let Some(core_id) = core_id {
+                    let res = core_affinity::set_for_current(core_id);
+                    debug_assert!(res);
+                }
                 // SAFETY: non-overlapping thread ownership of dst slice
                 let mut dst_owned = unsafe { dst.subrows(row_start, owned_rows).const_cast() };
                 if let Accum::Replace = beta {
@@ -436,7 +483,7 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
                         &txs_local,
                     );
 
-                    if (iter + 1) * n_threads % WS_CHUNKS_PER_THREAD == 0 {
+                    if ((iter + 1) * n_threads).is_multiple_of(WS_CHUNKS_PER_THREAD) {
                         collect_chunks(
                             &rx_owned,
                             &owner_of_block,
@@ -455,11 +502,11 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
 
                 // Flush partial (non-full) chunks
                 for (block_id, maybe_chunk) in open.into_iter().enumerate() {
-                    if let Some(chunk) = maybe_chunk {
-                        if chunk.len() > 0 {
-                            let owner = owner_of_block[block_id];
-                            full_chunks[owner].push(chunk);
-                        }
+                    if let Some(chunk) = maybe_chunk
+                        && chunk.len() > 0
+                    {
+                        let owner = owner_of_block[block_id];
+                        full_chunks[owner].push(chunk);
                     }
                 }
                 for (owner, chunks) in full_chunks.into_iter().enumerate() {
@@ -2,14 +2,15 @@
 //! sparse vec for each thread. Summed at end sequentially but could be parallel, doesn't
 //! matter since this one is much worse.
 use std::cmp::Ordering;
+use std::ops::Range;
 use std::thread;
 
 use faer::{
     Accum, ColMut, ColRef, Index, MatRef, Par,
     dyn_stack::{MemStack, StackReq},
-    prelude::{Reborrow, ReborrowMut},
+    prelude::Reborrow,
     sparse::SparseColMatRef,
-    traits::{AddByRef, ComplexField, math_utils::zero},
+    traits::{ComplexField, math_utils::zero},
 };
 
 use crate::spmv_drivers::SpMvStrategy;
@@ -35,8 +36,7 @@ pub fn sparse_dense_scratch<I: Index, T: ComplexField>(
                 for tid in 0..n_threads {
                     let col_start = strategy.thread_cols[tid];
                     let col_end = strategy.thread_cols[tid + 1];
-                    let k = 1 + col_end - col_start;
-                    let tree_size = k.next_power_of_two();
+                    let tree_size = merge_tree_size(1 + col_end - col_start);
 
                     total_base_size += tree_size;
                     total_losers_size += tree_size;
@@ -52,7 +52,7 @@ pub fn sparse_dense_scratch<I: Index, T: ComplexField>(
 }
 
 #[derive(Clone)]
-struct Contender<T: ComplexField> {
+pub(crate) struct Contender<T: ComplexField> {
     row: usize,
     val: T,
     local_col: usize,
@@ -69,7 +69,7 @@ impl<T: ComplexField> Ord for Contender<T> {
 impl<T: ComplexField> PartialOrd for Contender<T> {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(other.row.cmp(&self.row))
+        Some(self.cmp(other))
     }
 }
 
@@ -82,7 +82,7 @@ impl<T: ComplexField> PartialEq for Contender<T> {
 
 impl<T: ComplexField> Eq for Contender<T> {}
 
-struct LoserTree<'a, T: ComplexField> {
+pub(crate) struct LoserTree<'a, T: ComplexField> {
     base: &'a mut [Option<Contender<T>>],
     losers: &'a mut [usize],
     size: usize,
@@ -167,8 +167,7 @@ impl<'a, T: ComplexField> LoserTree<'a, T> {
     #[inline]
     fn winner(&mut self) -> Option<Contender<T>> {
         let winner_idx = self.losers[self.size - 1];
-        let result = self.base[winner_idx].take();
-        result
+        self.base[winner_idx].take()
     }
 
     #[inline]
@@ -179,40 +178,75 @@ impl<'a, T: ComplexField> LoserTree<'a, T> {
     }
 }
 
-/// Initialize base array with first elements from each column while skipping
-/// elements which this thread can write directly to dst
-fn initialize_leaves<I: Index, T: ComplexField>(
-    slices: &Vec<(&[I], &[T], T)>,
-    row_start: usize,
-    row_end: usize,
-    base_workspace: &mut [Option<Contender<T>>],
-    mut dst_owned: ColMut<T>,
-) -> Vec<usize> {
-    let k = slices.len();
-    let mut merge_ptrs: Vec<usize> = vec![0; k];
-    for (local_col, (indices, values, rhs_k)) in slices.iter().enumerate() {
-        for (i, (row, val)) in indices
-            .iter()
-            .map(|row| row.zx())
-            .zip(values.iter())
-            .enumerate()
-        {
-            merge_ptrs[local_col] = i + 1;
-            if row >= row_start && row < row_end {
-                let contrib = val.mul_by_ref(rhs_k);
-                let local_row = row - row_start;
-                dst_owned[local_row] = dst_owned[local_row].add_by_ref(&contrib);
-            } else {
-                base_workspace[local_col] = Some(Contender {
-                    row,
-                    val: val.clone(),
-                    local_col,
-                });
-                break;
+/// Leaves of the loser tree merging `n_columns` columns
+pub(crate) fn merge_tree_size(n_columns: usize) -> usize {
+    n_columns.next_power_of_two().max(2)
+}
+
+/// Scratch of `merge_columns` for `n_columns` columns split between `n_calls` calls, each getting
+/// `merge_tree_size` leaves and losers
+pub(crate) fn merge_columns_scratch<T: ComplexField>(n_columns: usize, n_calls: usize) -> StackReq {
+    let size = 2 * (n_columns + 2 * n_calls);
+    StackReq::new::<Option<Contender<T>>>(size).and(StackReq::new::<usize>(size))
+}
+
+/// Merge `columns` of `(row indices, values, scale)` with sorted row indices into `merged`, as
+/// `(row, sum of scale * value)` with increasing rows. `base` and `losers` must have
+/// `merge_tree_size(columns.len())` entries.
+///
+/// With `direct = Some((rows, dst))` the entries of a row in `rows` skip the tree and are added
+/// straight to `dst[row - rows.start]`.
+pub(crate) fn merge_columns<I: Index, T: ComplexField>(
+    columns: &[(&[I], &[T], T)],
+    base: &mut [Option<Contender<T>>],
+    losers: &mut [usize],
+    merged: &mut Vec<(usize, T)>,
+    direct: Option<(Range<usize>, ColMut<'_, T>)>,
+) {
+    assert_eq!(base.len(), merge_tree_size(columns.len()));
+    let mut direct = direct;
+    // Advance column `local_col` from `ptr` to its next entry that goes through the tree
+    let mut next_contender = |local_col: usize, ptr: &mut usize| {
+        let (indices, values, scale) = &columns[local_col];
+        while *ptr < indices.len() {
+            let row = indices[*ptr].zx();
+            let val = &values[*ptr];
+            *ptr += 1;
+            match &mut direct {
+                Some((rows, dst)) if rows.contains(&row) => {
+                    let local_row = row - rows.start;
+                    dst[local_row] = dst[local_row].add_by_ref(&val.mul_by_ref(scale));
+                }
+                _ => {
+                    return Some(Contender {
+                        row,
+                        val: val.clone(),
+                        local_col,
+                    });
+                }
             }
         }
+        None
+    };
+
+    let mut merge_ptrs = vec![0; columns.len()];
+    for (local_col, leaf) in base.iter_mut().enumerate() {
+        *leaf = match merge_ptrs.get_mut(local_col) {
+            Some(ptr) => next_contender(local_col, ptr),
+            None => None,
+        };
+    }
+
+    let mut loser_tree = LoserTree::new(base, losers);
+    while let Some(contender) = loser_tree.winner() {
+        let local_col = contender.local_col;
+        let val = contender.val.mul_by_ref(&columns[local_col].2);
+        match merged.last_mut() {
+            Some(last) if last.0 == contender.row => last.1 = last.1.add_by_ref(&val),
+            _ => merged.push((contender.row, val)),
+        }
+        loser_tree.push(next_contender(local_col, &mut merge_ptrs[local_col]));
     }
-    merge_ptrs
 }
 
 // NOTE: This merge based algorithm requires row indices to be in sorted order over columns, a soft
@@ -226,6 +260,38 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
     n_threads: usize,
     strategy: &SpMvStrategy,
     stack: &mut MemStack,
+) {
+    par_sparse_dense_impl(dst, beta, lhs, rhs, alpha, n_threads, strategy, stack, true);
+}
+
+/// `par_sparse_dense` without pinning the threads to cores, for thread counts above the core
+/// count or callers that manage affinity themselves
+pub fn par_sparse_dense_unpinned<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) {
+    par_sparse_dense_impl(
+        dst, beta, lhs, rhs, alpha, n_threads, strategy, stack, false,
+    );
+}
+
+/// Threads beyond the core count run unpinned even if `pin` is set
+fn par_sparse_dense_impl<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+    pin: bool,
 ) {
     let m = lhs.nrows();
 
@@ -240,8 +306,7 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
     for tid in 0..n_threads {
         let col_start = strategy.thread_cols[tid];
         let col_end = strategy.thread_cols[tid + 1];
-        let k = 1 + col_end - col_start;
-        let tree_size = k.next_power_of_two();
+        let tree_size = merge_tree_size(1 + col_end - col_start);
 
         thread_sizes.push(tree_size);
         total_base_size += tree_size;
@@ -257,14 +322,13 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
 
     let merged: Vec<Vec<(usize, T)>> = thread::scope(|s| {
         let mut handles = Vec::with_capacity(n_threads);
-        let core_ids = core_affinity::get_core_ids().unwrap();
-        debug_assert!(core_ids.len() >= n_threads);
+        let core_ids = pin.then(|| core_affinity::get_core_ids().unwrap());
+        debug_assert!(core_ids.as_ref().is_none_or(|ids| ids.len() >= n_threads));
 
-        let rows_per_thread = (m + (n_threads - 1)) / n_threads;
-        for (tid, core_id) in (0..n_threads).zip(core_ids.into_iter()) {
+        for (tid, &tree_size) in thread_sizes.iter().enumerate() {
+            let core_id = core_ids.as_ref().and_then(|ids| ids.get(tid).copied());
             let dst_rb = dst.rb();
 
-            let tree_size = thread_sizes[tid];
             let (base_workspace, remaining_base) = all_base_slice.split_at_mut(tree_size);
             let (losers_workspace, remaining_losers) = all_losers_slice.split_at_mut(tree_size);
 
@@ -272,17 +336,13 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
             all_losers_slice = remaining_losers;
 
             let handle = s.spawn(move || {
-                let res = core_affinity::set_for_current(core_id);
-                debug_assert!(res);
-
-                let row_start = tid * rows_per_thread;
-                let row_end = ((tid + 1) * rows_per_thread).min(m);
-                if row_end <= row_start {
-                    panic!(
-                        "Row end is {} and row start is {} for tid {}",
-                        row_end, row_start, tid
-                    );
+                if let Some(core_id) = core_id {
+                    let res = core_affinity::set_for_current(core_id);
+                    debug_assert!(res);
                 }
+
+                let row_start = strategy.thread_rows[tid];
+                let row_end = strategy.thread_rows[tid + 1];
                 let owned_rows = row_end - row_start;
 
                 // SAFETY: non-overlapping thread ownership of dst slice
@@ -315,72 +375,15 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
                     })
                     .collect();
 
-                let mut merge_ptrs = initialize_leaves(
+                // probably don't need this much capacity... but in pathological case we do
+                let mut merged: Vec<(usize, T)> = Vec::with_capacity(m);
+                merge_columns(
                     &slices,
-                    row_start,
-                    row_end,
                     base_workspace,
-                    dst_owned.rb_mut(),
+                    losers_workspace,
+                    &mut merged,
+                    Some((row_start..row_end, dst_owned)),
                 );
-
-                // probably don't need this much capacity... but in pathological case we do
-                let mut merged: Vec<(usize, T)> = Vec::with_capacity(m);
-                let mut loser_tree = LoserTree::new(base_workspace, losers_workspace);
-
-                loop {
-                    if let Some(contender) = loser_tree.winner() {
-                        let local_col = contender.local_col;
-                        let val = contender.val.mul_by_ref(&slices[local_col].2);
-                        if let Some(last) = merged.last_mut() {
-                            if last.0 == contender.row {
-                                last.1 = last.1.add_by_ref(&val);
-                            } else {
-                                merged.push((contender.row, val));
-                            }
-                        } else {
-                            merged.push((contender.row, val));
-                        }
-
-                        let current_idx = &mut merge_ptrs[local_col];
-                        let col_len = slices[local_col].0.len();
-                        // Fill local `dst_owned` with values that don't need to be merged.
-                        // This is a total mess. Might be better with iterator abstractions
-                        if *current_idx < col_len {
-                            let mut row = slices[local_col].0[*current_idx].zx();
-                            if row >= row_start && row < row_end {
-                                let rhs_k = &slices[local_col].2;
-                                while row < row_end {
-                                    let contrib =
-                                        slices[local_col].1[*current_idx].mul_by_ref(rhs_k);
-                                    let local_row = row - row_start;
-                                    dst_owned[local_row] =
-                                        dst_owned[local_row].add_by_ref(&contrib);
-                                    *current_idx += 1;
-                                    if *current_idx < col_len {
-                                        row = slices[local_col].0[*current_idx].zx();
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-
-                        // Add replacement contender to the tournament
-                        if *current_idx < col_len {
-                            let replacement = Contender {
-                                row: slices[local_col].0[*current_idx].zx(),
-                                val: slices[local_col].1[*current_idx].clone(),
-                                local_col,
-                            };
-                            loser_tree.push(Some(replacement));
-                            *current_idx += 1;
-                        } else {
-                            loser_tree.push(None);
-                        }
-                    } else {
-                        break;
-                    }
-                }
                 merged
             });
             handles.push(handle);
@@ -10,12 +10,16 @@ use faer::{
     mat::AsMatMut,
     prelude::{Reborrow, ReborrowMut},
     sparse::SparseColMatRef,
-    traits::{ComplexField, math_utils::zero},
+    traits::{
+        ComplexField,
+        math_utils::{conj, zero},
+    },
 };
 
-use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::spmv_drivers::SpMvStrategy;
+use crate::vector_ops::Sum;
 
 pub fn sparse_dense_scratch<I: Index, T: ComplexField>(
     lhs: SparseColMatRef<'_, I, T>,
@@ -23,7 +27,6 @@ pub fn sparse_dense_scratch<I: Index, T: ComplexField>(
     strategy: &SpMvStrategy,
     par: Par,
 ) -> StackReq {
-    let _ = lhs;
     let _ = strategy;
     match par {
         Par::Seq => StackReq::empty(),
@@ -33,14 +36,14 @@ pub fn sparse_dense_scratch<I: Index, T: ComplexField>(
             if dim >= n_threads * 4 {
                 StackReq::empty()
             } else {
-                temp_mat_scratch::<T>(rhs.nrows(), n_threads)
+                temp_mat_scratch::<T>(lhs.nrows(), n_threads)
             }
         }
     }
 }
 
 #[inline]
-fn hot_loop<I: Index, T: ComplexField>(
+pub(crate) fn hot_loop<I: Index, T: ComplexField>(
     col_range: std::ops::Range<usize>,
     row_indices: &[I],
     lhs_values: &[T],
@@ -54,6 +57,62 @@ fn hot_loop<I: Index, T: ComplexField>(
     }
 }
 
+/// Accumulate `alpha * A_t rhs` into the workspace `work` of thread `tid`, where `A_t` is the slice
+/// of `lhs` assigned to that thread by `strategy`
+#[inline]
+pub(crate) fn scatter_thread_range<I: Index, T: ComplexField>(
+    tid: usize,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    strategy: &SpMvStrategy,
+    work: ColMut<'_, T>,
+) {
+    scatter_thread_range_gather(tid, lhs, rhs, None, alpha, strategy, work);
+}
+
+/// `scatter_thread_range` where column `j` of `lhs` multiplies `rhs[rhs_idx[j]]` instead of
+/// `rhs[j]`, which fuses a column permutation into the gather
+#[inline]
+pub(crate) fn scatter_thread_range_gather<I: Index, T: ComplexField>(
+    tid: usize,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    rhs_idx: Option<&[I]>,
+    alpha: &T,
+    strategy: &SpMvStrategy,
+    mut work: ColMut<'_, T>,
+) {
+    let (lhs_symbolic, lhs_values) = lhs.parts();
+    let row_indices = lhs_symbolic.row_idx();
+
+    let col_start = strategy.thread_cols[tid];
+    let col_end = strategy.thread_cols[tid + 1];
+    let idx_start = strategy.thread_indptrs[tid];
+    let idx_end = strategy.thread_indptrs[tid + 1];
+
+    for depth in col_start..=col_end {
+        let rhs_k = match rhs_idx {
+            Some(rhs_idx) => rhs[rhs_idx[depth].zx()].mul_by_ref(alpha),
+            None => rhs[depth].mul_by_ref(alpha),
+        };
+        let mut col_range = lhs_symbolic.col_range(depth);
+        if depth == col_start {
+            col_range.start = idx_start;
+        }
+        if depth == col_end {
+            col_range.end = idx_end;
+        }
+        hot_loop(
+            col_range,
+            row_indices,
+            lhs_values,
+            &rhs_k,
+            work.as_col_mut(),
+        );
+    }
+}
+
 pub fn par_sparse_dense<I: Index, T: ComplexField>(
     dst: ColMut<'_, T>,
     beta: Accum,
@@ -69,8 +128,6 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
     let (mut work, _) = temp_mat_zeroed::<T, _, _>(m, n_threads, stack);
     let work = work.as_mat_mut();
     let work = work.rb();
-    let (lhs_symbolic, lhs_values) = lhs.parts();
-    let row_indices = lhs_symbolic.row_idx();
     let mut dst = dst;
 
     thread::scope(|s| {
@@ -80,28 +137,7 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
                 let mut work =
                     unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
 
-                let col_start = strategy.thread_cols[tid];
-                let col_end = strategy.thread_cols[tid + 1];
-                let idx_start = strategy.thread_indptrs[tid];
-                let idx_end = strategy.thread_indptrs[tid + 1];
-
-                for depth in col_start..=col_end {
-                    let rhs_k = rhs[depth].mul_by_ref(alpha);
-                    let mut col_range = lhs_symbolic.col_range(depth);
-                    if depth == col_start {
-                        col_range.start = idx_start;
-                    }
-                    if depth == col_end {
-                        col_range.end = idx_end;
-                    }
-                    hot_loop(
-                        col_range,
-                        row_indices,
-                        lhs_values,
-                        &rhs_k,
-                        work.as_col_mut(),
-                    );
-                }
+                scatter_thread_range(tid, lhs, rhs, alpha, strategy, work.as_col_mut());
             });
         }
 
@@ -111,30 +147,75 @@ pub fn par_sparse_dense<I: Index, T: ComplexField>(
         }
     });
 
-    //reduce_workspaces_threaded(n_threads, work, dst);
-    reduce_workspaces_rayon(n_threads, work, dst);
+    //reduce_workspaces_threaded(strategy, work, dst);
+    reduce_workspaces_rayon(strategy, work, dst);
+}
+
+/// `dst = beta * dst + alpha * lhs * rhs`, returning `<dst, v> = sum_i conj(dst_i) v_i`.
+///
+/// The dot product is accumulated in the workspace reduction while each block of `dst` is still in
+/// cache.
+pub fn par_sparse_dense_dot<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: ColRef<'_, T>,
+    alpha: &T,
+    v: ColRef<'_, T>,
+    n_threads: usize,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> T {
+    let m = lhs.nrows();
+
+    let (mut work, _) = temp_mat_zeroed::<T, _, _>(m, n_threads, stack);
+    let work = work.as_mat_mut();
+    let work = work.rb();
+
+    thread::scope(|s| {
+        for tid in 0..n_threads {
+            s.spawn(move || {
+                // SAFETY each thread gets its own workspace vector to be summed when all complete
+                let mut work =
+                    unsafe { work.col(tid).const_cast().try_as_col_major_mut().unwrap() };
+                scatter_thread_range(tid, lhs, rhs, alpha, strategy, work.as_col_mut());
+            });
+        }
+    });
+
+    let dst = dst.rb();
+    let partials = reduce_workspaces_with(strategy, work, |row_start, sums, acc: &mut Sum<T>| {
+        // SAFETY: every row is handed to exactly one call of the closure
+        let mut dst = unsafe { dst.const_cast() };
+        for (i, sum) in (row_start..).zip(sums) {
+            let dst_i = match beta {
+                Accum::Replace => sum.clone(),
+                Accum::Add => dst[i].add_by_ref(sum),
+            };
+            acc.0 = acc.0.add_by_ref(&conj(&dst_i).mul_by_ref(&v[i]));
+            dst[i] = dst_i;
+        }
+    });
+    Sum::total(partials)
 }
 
 /// somehow this is slower than `reduce_workspaces_rayon` variant
 #[allow(dead_code)]
-fn reduce_workspaces_threaded<T: ComplexField>(n_threads: usize, work: MatRef<T>, dst: ColMut<T>) {
-    let rows_per_thread = (work.nrows() + (n_threads - 1)) / n_threads;
+fn reduce_workspaces_threaded<T: ComplexField>(
+    strategy: &SpMvStrategy,
+    work: MatRef<T>,
+    dst: ColMut<T>,
+) {
     thread::scope(|s| {
-        for tid in 0..n_threads {
+        for tid in 0..work.ncols() {
             let dst = dst.rb();
             let work = work.rb();
             let _handle = s.spawn(move || {
-                let mut rows_per_thread = rows_per_thread;
-                let start_row = tid * rows_per_thread;
-                let mut end_row = (tid + 1) * rows_per_thread;
-                if tid == n_threads - 1 {
-                    end_row = work.nrows();
-                    rows_per_thread = end_row - start_row;
-                }
-
-                let mut dst = unsafe { dst.subrows(start_row, rows_per_thread).const_cast() };
+                let rows = strategy.thread_row_range(tid);
+                // SAFETY: the `thread_rows` ranges are disjoint
+                let mut dst = unsafe { dst.subrows(rows.start, rows.len()).const_cast() };
                 for col in work.col_iter() {
-                    for (local_row, i) in (start_row..end_row).enumerate() {
+                    for (local_row, i) in rows.clone().enumerate() {
                         dst[local_row] = dst[local_row].add_by_ref(&col[i]);
                     }
                 }
@@ -143,19 +224,61 @@ fn reduce_workspaces_threaded<T: ComplexField>(n_threads: usize, work: MatRef<T>
     });
 }
 
+/// Add the row sums of the per-thread workspaces `work` to `dst`, every thread reducing the rows it
+/// owns in `strategy`
+///
 /// somehow this is faster than `reduce_workspaces_threaded` variant
-#[allow(dead_code)]
-fn reduce_workspaces_rayon<T: ComplexField>(n_threads: usize, work: MatRef<T>, dst: ColMut<T>) {
-    let rows_per_thread = (work.nrows() + (n_threads - 1)) / n_threads;
-    // This seems janky could probably improve lots
-    dst.as_mat_mut()
-        .par_row_chunks_mut(rows_per_thread)
-        .zip(work.par_row_chunks(rows_per_thread))
-        .for_each(|(dst_chunk, work_chunk)| {
-            let dst_chunk = dst_chunk.col_mut(0);
-            for (i, dst) in dst_chunk.iter_mut().enumerate() {
-                let work_row = work_chunk.row(i);
-                *dst = dst.add_by_ref(&work_row.sum());
+pub(crate) fn reduce_workspaces_rayon<T: ComplexField>(
+    strategy: &SpMvStrategy,
+    work: MatRef<T>,
+    dst: ColMut<T>,
+) {
+    assert_eq!(strategy.thread_rows.last(), Some(&work.nrows()));
+    let dst = dst.rb();
+    (0..strategy.thread_rows.len() - 1)
+        .into_par_iter()
+        .for_each(|tid| {
+            let rows = strategy.thread_row_range(tid);
+            // SAFETY: the `thread_rows` ranges are disjoint
+            let mut dst = unsafe { dst.subrows(rows.start, rows.len()).const_cast() };
+            for (local_row, i) in rows.enumerate() {
+                dst[local_row] = dst[local_row].add_by_ref(&work.row(i).sum());
             }
         });
 }
+
+/// Rows summed per call of the closure in `reduce_workspaces_with`, small enough that the sums are
+/// still in cache when the closure reads them
+const REDUCE_BLOCK_ROWS: usize = 256;
+
+/// Sum the workspaces like `reduce_workspaces_rayon`, but hand every block of row sums to `f`
+/// instead of adding them to `dst`, so callers can fuse their per-row work into the same sweep.
+/// `f` gets the first row of the block, the row sums and an accumulator private to the thread's
+/// rows in `strategy`. Returns the accumulators of all threads.
+pub(crate) fn reduce_workspaces_with<T, R, F>(
+    strategy: &SpMvStrategy,
+    work: MatRef<T>,
+    f: F,
+) -> Vec<R>
+where
+    T: ComplexField,
+    R: Default + Send,
+    F: Fn(usize, &[T], &mut R) + Sync,
+{
+    assert_eq!(strategy.thread_rows.last(), Some(&work.nrows()));
+    (0..strategy.thread_rows.len() - 1)
+        .into_par_iter()
+        .map(|tid| {
+            let rows = strategy.thread_row_range(tid);
+            let mut acc = R::default();
+            let mut sums = Vec::with_capacity(REDUCE_BLOCK_ROWS);
+            for block_start in rows.clone().step_by(REDUCE_BLOCK_ROWS) {
+                let block_end = (block_start + REDUCE_BLOCK_ROWS).min(rows.end);
+                sums.clear();
+                sums.extend((block_start..block_end).map(|i| work.row(i).sum()));
+                f(block_start, &sums, &mut acc);
+            }
+            acc
+        })
+        .collect()
+}
@@ -0,0 +1,213 @@
+//! Extremal eigenvalue estimates over `LinearOperator`s.
+//!
+//! Both routines spend one operator application per iteration, taking the Rayleigh quotient from
+//! `apply_dot`, and do their vector updates with the fixed blocks of the solvers, so they
+//! parallelize like the SpMV of the operator. Every estimate comes with the residual norm
+//! `||A v - lambda v||` of its unit eigenvector estimate `v`, which for a Hermitian operator bounds
+//! the distance from `lambda` to the spectrum.
+//!
+//! Lanczos does not reorthogonalize its basis, so repeated copies of converged Ritz values may show
+//! up after many steps. They do not affect the extremal estimates, and the residuals are the usual
+//! `|beta_k y_k|` of the Ritz vectors, which stay accurate in finite precision.
+use faer::{
+    ColMut, Mat, Par, Side,
+    dyn_stack::{MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::{Reborrow, ReborrowMut},
+    traits::{
+        AddByRef, ComplexField, RealField,
+        math_utils::{abs, abs2, conj, eps, from_f64, mul_real, real, recip, sqrt, zero},
+    },
+};
+
+use crate::operator::LinearOperator;
+use crate::vector_ops::{Sum, for_each_block, scale};
+
+/// Lanczos stops once `beta_j` is within this many ulps of `|alpha_j| + beta_{j-1}`
+const BREAKDOWN_FACTOR: f64 = 64.0;
+
+/// An eigenvalue estimate with the residual norm of its unit eigenvector estimate
+#[derive(Clone, Debug)]
+pub struct EigenEstimate<T: ComplexField> {
+    pub value: T,
+    pub residual: T::Real,
+}
+
+/// Extremal Ritz values of a Lanczos run
+#[derive(Clone, Debug)]
+pub struct LanczosEstimate<R: RealField> {
+    pub min: EigenEstimate<R>,
+    pub max: EigenEstimate<R>,
+    /// Steps taken, fewer than requested if the Krylov space became invariant
+    pub steps: usize,
+}
+
+impl<R: RealField> LanczosEstimate<R> {
+    /// `lambda_max / lambda_min`, the condition number of a positive definite operator
+    pub fn condition_number(&self) -> R {
+        self.max.value.clone() / self.min.value.clone()
+    }
+}
+
+/// Scratch space of `power_iteration`
+pub fn power_iteration_scratch<T: ComplexField>(a: &dyn LinearOperator<T>) -> StackReq {
+    temp_mat_scratch::<T>(a.nrows(), 2).and(a.scratch_req())
+}
+
+/// Scratch space of `lanczos`
+pub fn lanczos_scratch<T: ComplexField>(a: &dyn LinearOperator<T>) -> StackReq {
+    temp_mat_scratch::<T>(a.nrows(), 3).and(a.scratch_req())
+}
+
+/// Estimate the eigenvalue of largest magnitude of the square operator `a` with `n_iters` power
+/// iterations, returning the Rayleigh quotient of the last iterate
+pub fn power_iteration<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    n_iters: usize,
+    par: Par,
+    stack: &mut MemStack,
+) -> EigenEstimate<T> {
+    let n = a.nrows();
+    assert_eq!(a.ncols(), n);
+
+    let (mut vectors, stack) = temp_mat_zeroed::<T, _, _>(n, 2, stack);
+    let mut vectors = vectors.as_mat_mut();
+    let (v, w) = vectors.rb_mut().split_at_col_mut(1);
+    let (mut v, mut w) = (v.col_mut(0), w.col_mut(0));
+    start_vector(v.rb_mut(), par);
+
+    let mut estimate = EigenEstimate {
+        value: zero(),
+        residual: zero(),
+    };
+    for _ in 0..n_iters {
+        // w = A v, lambda = <v, A v>
+        let lambda = conj(&a.apply_dot(w.rb_mut(), v.rb(), v.rb(), stack));
+        let (v_view, w_view) = (v.rb(), w.rb());
+        let partials = for_each_block(n, par, |rows, acc: &mut [Sum<T::Real>; 2]| {
+            for i in rows {
+                let r_i = w_view[i].sub_by_ref(&lambda.mul_by_ref(&v_view[i]));
+                acc[0].0 = acc[0].0.add_by_ref(&abs2(&w_view[i]));
+                acc[1].0 = acc[1].0.add_by_ref(&abs2(&r_i));
+            }
+        });
+        let (w_norm_sq, r_norm_sq) = partials.into_iter().fold(
+            (zero::<T::Real>(), zero::<T::Real>()),
+            |(w, r), [w_p, r_p]| (w.add_by_ref(&w_p.0), r.add_by_ref(&r_p.0)),
+        );
+        estimate = EigenEstimate {
+            value: lambda,
+            residual: sqrt(&r_norm_sq),
+        };
+        if w_norm_sq == zero() {
+            break;
+        }
+
+        // v = w / ||w||
+        std::mem::swap(&mut v, &mut w);
+        scale(v.rb_mut(), &recip(&sqrt(&w_norm_sq)), par);
+    }
+    estimate
+}
+
+/// Estimate the extremal eigenvalues of the Hermitian operator `a` from the Ritz values of an
+/// `n_steps` Lanczos run
+pub fn lanczos<T: ComplexField>(
+    a: &dyn LinearOperator<T>,
+    n_steps: usize,
+    par: Par,
+    stack: &mut MemStack,
+) -> LanczosEstimate<T::Real> {
+    let n = a.nrows();
+    assert_eq!(a.ncols(), n);
+    assert!(n > 0 && n_steps > 0);
+
+    let (mut vectors, stack) = temp_mat_zeroed::<T, _, _>(n, 3, stack);
+    let mut vectors = vectors.as_mat_mut();
+    let mut vectors = vectors.rb_mut().col_iter_mut();
+    let (mut v_prev, mut v, mut w) = (
+        vectors.next().unwrap(),
+        vectors.next().unwrap(),
+        vectors.next().unwrap(),
+    );
+    start_vector(v.rb_mut(), par);
+
+    // the tridiagonal matrix, `beta[j]` coupling steps `j` and `j + 1`
+    let mut alpha = Vec::with_capacity(n_steps);
+    let mut beta: Vec<T::Real> = Vec::with_capacity(n_steps);
+    while alpha.len() < n_steps.min(n) {
+        // w = A v - alpha v - beta v_prev, alpha = <v, A v>
+        let alpha_j = real(&a.apply_dot(w.rb_mut(), v.rb(), v.rb(), stack));
+        let beta_prev = beta.last().cloned().unwrap_or_else(zero);
+        let (v_view, v_prev_view, w_view) = (v.rb(), v_prev.rb(), w.rb());
+        let w_norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+            // SAFETY: the blocks are disjoint
+            let mut w = unsafe { w_view.const_cast() };
+            for i in rows {
+                w[i] = w[i]
+                    .sub_by_ref(&mul_real(&v_view[i], &alpha_j))
+                    .sub_by_ref(&mul_real(&v_prev_view[i], &beta_prev));
+                acc.0 = acc.0.add_by_ref(&abs2(&w[i]));
+            }
+        }));
+        let beta_j = sqrt(&w_norm_sq);
+        // `w` is rounding error of the size of the entries of the tridiagonal matrix once the
+        // Krylov space is invariant
+        let invariant =
+            beta_j <= eps::<T::Real>() * from_f64(BREAKDOWN_FACTOR) * (abs(&alpha_j) + beta_prev);
+        alpha.push(alpha_j);
+        beta.push(beta_j.clone());
+        if invariant {
+            break;
+        }
+
+        // v_prev = v, v = w / beta
+        std::mem::swap(&mut v_prev, &mut v);
+        std::mem::swap(&mut v, &mut w);
+        scale(v.rb_mut(), &recip(&beta_j), par);
+    }
+
+    let steps = alpha.len();
+    let tridiagonal = Mat::<T::Real>::from_fn(steps, steps, |i, j| {
+        if i == j {
+            alpha[i].clone()
+        } else if i == j + 1 {
+            beta[j].clone()
+        } else {
+            zero()
+        }
+    });
+    let eigen = tridiagonal
+        .self_adjoint_eigen(Side::Lower)
+        .expect("eigendecomposition of the Lanczos tridiagonal failed");
+    let beta_last = beta[steps - 1].clone();
+    // eigenvalues are sorted in nondecreasing order
+    let ritz = |k: usize| EigenEstimate {
+        value: eigen.S()[k].clone(),
+        residual: abs(&beta_last) * abs(&eigen.U()[(steps - 1, k)]),
+    };
+    LanczosEstimate {
+        min: ritz(0),
+        max: ritz(steps - 1),
+        steps,
+    }
+}
+
+/// Fill `v` with a fixed pseudo random unit vector, which is unlikely to miss an eigenvector
+fn start_vector<T: ComplexField>(v: ColMut<'_, T>, par: Par) {
+    let n = v.nrows();
+    let v_view = v.rb();
+    let norm_sq = Sum::total(for_each_block(n, par, |rows, acc: &mut Sum<T::Real>| {
+        // SAFETY: the blocks are disjoint
+        let mut v = unsafe { v_view.const_cast() };
+        for i in rows {
+            let hash = (i as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40;
+            v[i] = from_f64(hash as f64 / (1u64 << 24) as f64 - 0.5);
+            acc.0 = acc.0.add_by_ref(&abs2(&v[i]));
+        }
+    }));
+    if norm_sq != zero() {
+        scale(v, &recip(&sqrt(&norm_sq)), par);
+    }
+}
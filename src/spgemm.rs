@@ -0,0 +1,391 @@
+//! Sparse-sparse products `C = A B` and `C = R A P` for Galerkin coarse operators.
+//!
+//! The products are column by column (Gustavson): column `j` of `A B` scatters the columns of `A`
+//! selected by column `j` of `B` into a dense accumulator, and the triple product scatters that
+//! accumulator once more through the columns of `R`, so `A P` is never stored. Every column is
+//! owned by one thread, and the threads get contiguous column ranges with about the same number
+//! of multiply-adds, counted like the nnz of `SpMvStrategy` but on the columns of `A` each
+//! column of the product touches.
+//!
+//! `SpGemmPlan::new` and `SpGemmPlan::new_triple` are the symbolic phase: they compute the sorted
+//! pattern of the product and the partition. The numeric phase fills the values of a product with
+//! that pattern and can run again whenever the values of the factors change, which is the common
+//! case when an AMG hierarchy is rebuilt for a new matrix on the same mesh. Columns are computed
+//! the same way for every partition, so the values do not depend on the thread count.
+use faer::{
+    Index, Par,
+    dyn_stack::{MemBuffer, MemStack, StackReq},
+    linalg::{temp_mat_scratch, temp_mat_zeroed},
+    mat::AsMatMut,
+    prelude::ReborrowMut,
+    sparse::{SparseColMat, SparseColMatRef, SymbolicSparseColMat, SymbolicSparseColMatRef},
+    traits::ComplexField,
+};
+
+use crate::plan_cache::PatternFingerprint;
+use crate::spmv_drivers::{balanced_splits, run_threads};
+
+/// Pattern and column partition of `A B` or `R A P`, for the factors whose patterns it was built
+/// from
+pub struct SpGemmPlan<I: Index> {
+    pattern: SymbolicSparseColMat<I>,
+    /// Columns `thread_cols[t]..thread_cols[t + 1]` of the product are computed by thread `t`
+    thread_cols: Vec<usize>,
+    /// Patterns of `R` (triple products only), `A` and `B` or `P`
+    factors: Vec<PatternFingerprint>,
+    par: Par,
+}
+
+impl<I: Index> SpGemmPlan<I> {
+    /// Symbolic phase of `lhs * rhs`
+    pub fn new(
+        lhs: SymbolicSparseColMatRef<'_, I>,
+        rhs: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Self {
+        assert_eq!(lhs.ncols(), rhs.nrows());
+        Self::build(None, lhs, rhs, par)
+    }
+
+    /// Symbolic phase of `r * a * p`
+    pub fn new_triple(
+        r: SymbolicSparseColMatRef<'_, I>,
+        a: SymbolicSparseColMatRef<'_, I>,
+        p: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Self {
+        assert_eq!(r.ncols(), a.nrows());
+        assert_eq!(a.ncols(), p.nrows());
+        Self::build(Some(r), a, p, par)
+    }
+
+    fn build(
+        outer: Option<SymbolicSparseColMatRef<'_, I>>,
+        a: SymbolicSparseColMatRef<'_, I>,
+        b: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Self {
+        let n_threads = match par {
+            Par::Seq => 1,
+            Par::Rayon(n_threads) => n_threads.get(),
+        };
+        // multiply-adds of column `j` of `A B`, plus one for the column itself
+        let work: Vec<usize> = (0..b.ncols())
+            .map(|j| {
+                1 + b
+                    .row_idx_of_col(j)
+                    .map(|k| a.col_range(k).len())
+                    .sum::<usize>()
+            })
+            .collect();
+        let thread_cols = balanced_splits(&work, n_threads);
+        let m = outer.map_or(a.nrows(), |r| r.nrows());
+
+        // every thread collects the sorted patterns of its columns, which are then concatenated
+        let thread_patterns = run_threads(
+            thread_cols
+                .windows(2)
+                .map(|cols| cols[0]..cols[1])
+                .collect(),
+            |cols| {
+                let mut accumulator = SymbolicAccumulator::new(a.nrows(), outer.map(|r| r.nrows()));
+                let mut col_nnz = Vec::with_capacity(cols.len());
+                let mut row_idx = Vec::new();
+                for j in cols {
+                    let pattern = accumulator.column(outer, a, b, j);
+                    pattern.sort_unstable();
+                    col_nnz.push(pattern.len());
+                    row_idx.extend(pattern.iter().map(|&i| I::truncate(i)));
+                }
+                (col_nnz, row_idx)
+            },
+        );
+
+        let nnz = thread_patterns.iter().map(|(_, rows)| rows.len()).sum();
+        let mut col_ptr = Vec::with_capacity(b.ncols() + 1);
+        let mut row_idx = Vec::with_capacity(nnz);
+        col_ptr.push(I::truncate(0));
+        for (col_nnz, rows) in thread_patterns {
+            for len in col_nnz {
+                col_ptr.push(I::truncate(col_ptr.last().unwrap().zx() + len));
+            }
+            row_idx.extend(rows);
+        }
+        // SAFETY: the row indices of every column are distinct, sorted and below `m`
+        let pattern =
+            unsafe { SymbolicSparseColMat::new_unchecked(m, b.ncols(), col_ptr, None, row_idx) };
+
+        let factors = outer
+            .into_iter()
+            .chain([a, b])
+            .map(PatternFingerprint::new)
+            .collect();
+        Self {
+            pattern,
+            thread_cols,
+            factors,
+            par,
+        }
+    }
+
+    /// Pattern of the product, with sorted row indices in every column
+    pub fn pattern(&self) -> SymbolicSparseColMatRef<'_, I> {
+        self.pattern.as_ref()
+    }
+
+    pub fn thread_cols(&self) -> &[usize] {
+        &self.thread_cols
+    }
+
+    pub fn par(&self) -> Par {
+        self.par
+    }
+
+    fn n_threads(&self) -> usize {
+        self.thread_cols.len() - 1
+    }
+
+    fn is_triple(&self) -> bool {
+        self.factors.len() == 3
+    }
+
+    /// Scratch space of the numeric phase: the dense accumulators and markers of every thread
+    pub fn scratch<T: ComplexField>(&self) -> StackReq {
+        let n_threads = self.n_threads();
+        let (n_mid, n_outer) = if self.is_triple() {
+            (self.factors[1].nrows, self.pattern.nrows())
+        } else {
+            (self.pattern.nrows(), 0)
+        };
+        temp_mat_scratch::<T>(n_mid + n_outer, n_threads)
+            .and(StackReq::new::<usize>((2 * n_mid + n_outer) * n_threads))
+    }
+
+    /// Numeric phase of `lhs * rhs`
+    pub fn product<T: ComplexField>(
+        &self,
+        lhs: SparseColMatRef<'_, I, T>,
+        rhs: SparseColMatRef<'_, I, T>,
+        stack: &mut MemStack,
+    ) -> SparseColMat<I, T> {
+        let mut values = vec![T::zero_impl(); self.pattern.compute_nnz()];
+        self.product_into(&mut values, lhs, rhs, stack);
+        SparseColMat::new(self.pattern.clone(), values)
+    }
+
+    /// Numeric phase of `lhs * rhs`, writing the values in the storage order of `pattern`
+    pub fn product_into<T: ComplexField>(
+        &self,
+        values: &mut [T],
+        lhs: SparseColMatRef<'_, I, T>,
+        rhs: SparseColMatRef<'_, I, T>,
+        stack: &mut MemStack,
+    ) {
+        assert!(!self.is_triple());
+        self.check(&[lhs.symbolic(), rhs.symbolic()]);
+        self.numeric(values, None, lhs, rhs, stack);
+    }
+
+    /// Numeric phase of `r * a * p`
+    pub fn triple_product<T: ComplexField>(
+        &self,
+        r: SparseColMatRef<'_, I, T>,
+        a: SparseColMatRef<'_, I, T>,
+        p: SparseColMatRef<'_, I, T>,
+        stack: &mut MemStack,
+    ) -> SparseColMat<I, T> {
+        let mut values = vec![T::zero_impl(); self.pattern.compute_nnz()];
+        self.triple_product_into(&mut values, r, a, p, stack);
+        SparseColMat::new(self.pattern.clone(), values)
+    }
+
+    /// Numeric phase of `r * a * p`, writing the values in the storage order of `pattern`
+    pub fn triple_product_into<T: ComplexField>(
+        &self,
+        values: &mut [T],
+        r: SparseColMatRef<'_, I, T>,
+        a: SparseColMatRef<'_, I, T>,
+        p: SparseColMatRef<'_, I, T>,
+        stack: &mut MemStack,
+    ) {
+        assert!(self.is_triple());
+        self.check(&[r.symbolic(), a.symbolic(), p.symbolic()]);
+        self.numeric(values, Some(r), a, p, stack);
+    }
+
+    fn check(&self, factors: &[SymbolicSparseColMatRef<'_, I>]) {
+        for (factor, fingerprint) in factors.iter().zip(&self.factors) {
+            assert_eq!(
+                PatternFingerprint::new(*factor),
+                *fingerprint,
+                "factor pattern differs from the one the plan was built for"
+            );
+        }
+    }
+
+    fn numeric<T: ComplexField>(
+        &self,
+        values: &mut [T],
+        outer: Option<SparseColMatRef<'_, I, T>>,
+        a: SparseColMatRef<'_, I, T>,
+        b: SparseColMatRef<'_, I, T>,
+        stack: &mut MemStack,
+    ) {
+        let col_ptr = self.pattern.col_ptr();
+        assert_eq!(values.len(), col_ptr[self.pattern.ncols()].zx());
+        let n_threads = self.n_threads();
+        let n_mid = a.nrows();
+        let n_outer = outer.map_or(0, |r| r.nrows());
+
+        let (mut dense, stack) = temp_mat_zeroed::<T, _, _>(n_mid + n_outer, n_threads, stack);
+        let mut dense = dense.as_mat_mut();
+        let (mut markers, _) = stack.make_with((2 * n_mid + n_outer) * n_threads, |_| 0usize);
+
+        // hand every thread its accumulators and the values of its columns
+        let mut inputs = Vec::with_capacity(n_threads);
+        let mut values = values;
+        let mut markers: &mut [usize] = &mut markers;
+        for (tid, dense) in (0..n_threads).zip(dense.rb_mut().col_iter_mut()) {
+            let cols = self.thread_cols[tid]..self.thread_cols[tid + 1];
+            let (thread_values, rest) =
+                values.split_at_mut(col_ptr[cols.end].zx() - col_ptr[cols.start].zx());
+            values = rest;
+            let (thread_markers, rest) = markers.split_at_mut(2 * n_mid + n_outer);
+            markers = rest;
+            let dense = dense.try_as_col_major_mut().unwrap().as_slice_mut();
+            inputs.push((cols, thread_values, dense, thread_markers));
+        }
+
+        let pattern = self.pattern.as_ref();
+        run_threads(inputs, |(cols, thread_values, dense, markers)| {
+            let (acc_mid, acc_outer) = dense.split_at_mut(n_mid);
+            let (markers, touched_mid) = markers.split_at_mut(n_mid + n_outer);
+            let (mark_mid, mark_outer) = markers.split_at_mut(n_mid);
+            mark_mid.fill(usize::MAX);
+            mark_outer.fill(usize::MAX);
+            let offset = col_ptr[cols.start].zx();
+            let (a_rows, a_values) = (a.symbolic().row_idx(), a.val());
+            let (b_rows, b_values) = (b.symbolic().row_idx(), b.val());
+
+            for j in cols {
+                // column `j` of `A B` in `acc_mid`, its rows in `touched_mid[..n_touched]`
+                let mut n_touched = 0;
+                for b_idx in b.col_range(j) {
+                    let k = b_rows[b_idx].zx();
+                    let b_kj = &b_values[b_idx];
+                    for a_idx in a.col_range(k) {
+                        let i = a_rows[a_idx].zx();
+                        let product = a_values[a_idx].mul_by_ref(b_kj);
+                        if mark_mid[i] != j {
+                            mark_mid[i] = j;
+                            touched_mid[n_touched] = i;
+                            n_touched += 1;
+                            acc_mid[i] = product;
+                        } else {
+                            acc_mid[i] = acc_mid[i].add_by_ref(&product);
+                        }
+                    }
+                }
+
+                let acc = match outer {
+                    None => &*acc_mid,
+                    Some(r) => {
+                        let (r_rows, r_values) = (r.symbolic().row_idx(), r.val());
+                        for &i in &touched_mid[..n_touched] {
+                            let ap_ij = &acc_mid[i];
+                            for r_idx in r.col_range(i) {
+                                let row = r_rows[r_idx].zx();
+                                let product = r_values[r_idx].mul_by_ref(ap_ij);
+                                if mark_outer[row] != j {
+                                    mark_outer[row] = j;
+                                    acc_outer[row] = product;
+                                } else {
+                                    acc_outer[row] = acc_outer[row].add_by_ref(&product);
+                                }
+                            }
+                        }
+                        &*acc_outer
+                    }
+                };
+                for (idx, i) in pattern.col_range(j).zip(pattern.row_idx_of_col(j)) {
+                    thread_values[idx - offset] = acc[i].clone();
+                }
+            }
+        });
+    }
+}
+
+/// `lhs * rhs`, planned for this product only
+pub fn spgemm<I: Index, T: ComplexField>(
+    lhs: SparseColMatRef<'_, I, T>,
+    rhs: SparseColMatRef<'_, I, T>,
+    par: Par,
+) -> SparseColMat<I, T> {
+    let plan = SpGemmPlan::new(lhs.symbolic(), rhs.symbolic(), par);
+    let mut stack_buffer = MemBuffer::new(plan.scratch::<T>());
+    plan.product(lhs, rhs, MemStack::new(&mut stack_buffer))
+}
+
+/// `r * a * p`, planned for this product only
+pub fn rap<I: Index, T: ComplexField>(
+    r: SparseColMatRef<'_, I, T>,
+    a: SparseColMatRef<'_, I, T>,
+    p: SparseColMatRef<'_, I, T>,
+    par: Par,
+) -> SparseColMat<I, T> {
+    let plan = SpGemmPlan::new_triple(r.symbolic(), a.symbolic(), p.symbolic(), par);
+    let mut stack_buffer = MemBuffer::new(plan.scratch::<T>());
+    plan.triple_product(r, a, p, MemStack::new(&mut stack_buffer))
+}
+
+/// Markers and touched rows of the symbolic phase
+struct SymbolicAccumulator {
+    mark_mid: Vec<usize>,
+    touched_mid: Vec<usize>,
+    mark_outer: Vec<usize>,
+    touched_outer: Vec<usize>,
+}
+
+impl SymbolicAccumulator {
+    fn new(n_mid: usize, n_outer: Option<usize>) -> Self {
+        Self {
+            mark_mid: vec![usize::MAX; n_mid],
+            touched_mid: Vec::new(),
+            mark_outer: vec![usize::MAX; n_outer.unwrap_or(0)],
+            touched_outer: Vec::new(),
+        }
+    }
+
+    /// Rows of column `j` of `A B`, or of `R A B` with `outer`, in the order they are reached
+    fn column<I: Index>(
+        &mut self,
+        outer: Option<SymbolicSparseColMatRef<'_, I>>,
+        a: SymbolicSparseColMatRef<'_, I>,
+        b: SymbolicSparseColMatRef<'_, I>,
+        j: usize,
+    ) -> &mut Vec<usize> {
+        self.touched_mid.clear();
+        for k in b.row_idx_of_col(j) {
+            for i in a.row_idx_of_col(k) {
+                if self.mark_mid[i] != j {
+                    self.mark_mid[i] = j;
+                    self.touched_mid.push(i);
+                }
+            }
+        }
+        let Some(r) = outer else {
+            return &mut self.touched_mid;
+        };
+
+        self.touched_outer.clear();
+        for &i in &self.touched_mid {
+            for row in r.row_idx_of_col(i) {
+                if self.mark_outer[row] != j {
+                    self.mark_outer[row] = j;
+                    self.touched_outer.push(row);
+                }
+            }
+        }
+        &mut self.touched_outer
+    }
+}
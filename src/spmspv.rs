@@ -0,0 +1,243 @@
+//! Sparse matrix–sparse vector product `y = A x` (SpMSpV), e.g. for frontier expansion in graph
+//! traversals or for applying an operator to a local correction.
+//!
+//! CSC fits it naturally: only the columns of `A` selected by the nonzeros of `x` are touched, so
+//! the cost is the nnz of those columns instead of the nnz of `A`. The nonzeros of `x` are split
+//! into contiguous ranges with about the same number of multiply-adds, and every thread merges its
+//! scaled columns with the loser tree of the `merge` kernel into a sorted sparse partial. The
+//! partials are then combined in parallel over disjoint row ranges, like the `buffer_foreign`
+//! reduction, into either a sorted `SparseVec` or a dense column. Rows that cancel to zero are
+//! kept in the sparse output, so its pattern only depends on the patterns of `A` and `x`.
+use faer::{
+    Accum, Col, ColMut, ColRef, Index, Par,
+    dyn_stack::{MemStack, StackReq},
+    prelude::Reborrow,
+    sparse::SparseColMatRef,
+    traits::{ComplexField, math_utils::zero},
+};
+
+use crate::sparse_dense_impl::merge::{
+    Contender, merge_columns, merge_columns_scratch, merge_tree_size,
+};
+use crate::spmv_drivers::{balanced_splits, run_threads};
+
+/// Sparse vector of length `len` with strictly increasing `indices`
+#[derive(Clone, Debug)]
+pub struct SparseVec<I: Index, T: ComplexField> {
+    len: usize,
+    indices: Vec<I>,
+    values: Vec<T>,
+}
+
+impl<I: Index, T: ComplexField> SparseVec<I, T> {
+    pub fn new(len: usize, indices: Vec<I>, values: Vec<T>) -> Self {
+        assert_eq!(indices.len(), values.len());
+        assert!(indices.windows(2).all(|w| w[0] < w[1]));
+        assert!(indices.last().is_none_or(|i| i.zx() < len));
+        Self {
+            len,
+            indices,
+            values,
+        }
+    }
+
+    /// The nonzero entries of `x`
+    pub fn from_dense(x: ColRef<'_, T>) -> Self {
+        let (indices, values) = x
+            .iter()
+            .enumerate()
+            .filter(|(_, x_i)| **x_i != zero::<T>())
+            .map(|(i, x_i)| (I::truncate(i), x_i.clone()))
+            .unzip();
+        Self::new(x.nrows(), indices, values)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn indices(&self) -> &[I] {
+        &self.indices
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn to_dense(&self) -> Col<T> {
+        let mut dense = Col::zeros(self.len);
+        for (i, x_i) in self.indices.iter().zip(&self.values) {
+            dense[i.zx()] = x_i.clone();
+        }
+        dense
+    }
+}
+
+pub fn spmspv_scratch<T: ComplexField>(x_nnz: usize, par: Par) -> StackReq {
+    merge_columns_scratch::<T>(x_nnz, n_threads(x_nnz, par))
+}
+
+/// `A x` as a sparse vector
+pub fn spmspv<I: Index, T: ComplexField>(
+    lhs: SparseColMatRef<'_, I, T>,
+    x: &SparseVec<I, T>,
+    par: Par,
+    stack: &mut MemStack,
+) -> SparseVec<I, T> {
+    let partials = merge_partials(lhs, x, par, stack);
+    let merged: Vec<(usize, T)> = if partials.len() == 1 {
+        partials.into_iter().next().unwrap()
+    } else {
+        let ranges = row_ranges(&partials, lhs.nrows());
+        run_threads(ranges, |(row_start, row_end)| {
+            let mut heads: Vec<_> = partials
+                .iter()
+                .map(|partial| row_slice(partial, row_start, row_end))
+                .collect();
+            let mut merged = Vec::new();
+            // The partials are few, so the smallest head is found by scanning
+            while let Some(row) = heads
+                .iter()
+                .filter_map(|head| head.first().map(|(row, _)| *row))
+                .min()
+            {
+                let mut sum = zero::<T>();
+                for head in &mut heads {
+                    if let Some(((head_row, val), rest)) = head.split_first()
+                        && *head_row == row
+                    {
+                        sum = sum.add_by_ref(val);
+                        *head = rest;
+                    }
+                }
+                merged.push((row, sum));
+            }
+            merged
+        })
+        .concat()
+    };
+    let (indices, values) = merged
+        .into_iter()
+        .map(|(row, val)| (I::truncate(row), val))
+        .unzip();
+    SparseVec {
+        len: lhs.nrows(),
+        indices,
+        values,
+    }
+}
+
+/// `dst = A x` or `dst += A x` for a dense `dst`
+pub fn spmspv_dense<I: Index, T: ComplexField>(
+    dst: ColMut<'_, T>,
+    beta: Accum,
+    lhs: SparseColMatRef<'_, I, T>,
+    x: &SparseVec<I, T>,
+    par: Par,
+    stack: &mut MemStack,
+) {
+    assert_eq!(dst.nrows(), lhs.nrows());
+    let partials = merge_partials(lhs, x, par, stack);
+    let ranges = row_ranges(&partials, lhs.nrows());
+    let dst = dst.rb();
+    run_threads(ranges, |(row_start, row_end)| {
+        // SAFETY: the row ranges are disjoint
+        let mut dst = unsafe { dst.subrows(row_start, row_end - row_start).const_cast() };
+        if let Accum::Replace = beta {
+            dst.fill(zero());
+        }
+        for partial in &partials {
+            for (row, val) in row_slice(partial, row_start, row_end) {
+                let dst_i = &mut dst[row - row_start];
+                *dst_i = dst_i.add_by_ref(val);
+            }
+        }
+    });
+}
+
+fn n_threads(x_nnz: usize, par: Par) -> usize {
+    match par {
+        Par::Seq => 1,
+        Par::Rayon(n_threads) => n_threads.get().min(x_nnz).max(1),
+    }
+}
+
+/// Sorted `(row, value)` partials of `A x`, one per thread, each over a contiguous range of the
+/// nonzeros of `x`
+fn merge_partials<I: Index, T: ComplexField>(
+    lhs: SparseColMatRef<'_, I, T>,
+    x: &SparseVec<I, T>,
+    par: Par,
+    stack: &mut MemStack,
+) -> Vec<Vec<(usize, T)>> {
+    assert_eq!(x.len(), lhs.ncols());
+    let n_threads = n_threads(x.nnz(), par);
+    let work: Vec<usize> = x
+        .indices()
+        .iter()
+        .map(|j| lhs.symbolic().col_range(j.zx()).len())
+        .collect();
+    let thread_nnz = balanced_splits(&work, n_threads);
+
+    let tree_sizes: Vec<usize> = thread_nnz
+        .windows(2)
+        .map(|w| merge_tree_size(w[1] - w[0]))
+        .collect();
+    let total_size = tree_sizes.iter().sum();
+    let (mut all_base, stack) = stack.make_with::<Option<Contender<T>>>(total_size, |_| None);
+    let (mut all_losers, _) = stack.make_with::<usize>(total_size, |_| 0);
+    let mut base_rest: &mut [Option<Contender<T>>] = &mut all_base;
+    let mut losers_rest: &mut [usize] = &mut all_losers;
+    let mut inputs = Vec::with_capacity(n_threads);
+    for (w, &tree_size) in thread_nnz.windows(2).zip(&tree_sizes) {
+        let (base, rest) = base_rest.split_at_mut(tree_size);
+        base_rest = rest;
+        let (losers, rest) = losers_rest.split_at_mut(tree_size);
+        losers_rest = rest;
+        inputs.push((w[0]..w[1], base, losers));
+    }
+
+    run_threads(inputs, |(nnz_range, base, losers)| {
+        let columns: Vec<_> = x.indices()[nnz_range.clone()]
+            .iter()
+            .zip(&x.values()[nnz_range])
+            .map(|(j, x_j)| {
+                let j = j.zx();
+                (
+                    lhs.symbolic().row_idx_of_col_raw(j),
+                    lhs.val_of_col(j),
+                    x_j.clone(),
+                )
+            })
+            .collect();
+        let mut merged = Vec::new();
+        merge_columns(&columns, base, losers, &mut merged, None);
+        merged
+    })
+}
+
+/// Split the rows into one range per partial, cut at evenly spaced rows of the longest partial
+fn row_ranges<T>(partials: &[Vec<(usize, T)>], nrows: usize) -> Vec<(usize, usize)> {
+    let n = partials.len();
+    let longest = partials.iter().max_by_key(|p| p.len()).unwrap();
+    let mut bounds = Vec::with_capacity(n + 1);
+    bounds.push(0);
+    bounds.extend((1..n).map(|t| longest.get(t * longest.len() / n).map_or(nrows, |e| e.0)));
+    bounds.push(nrows);
+    bounds.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+/// Entries of a sorted partial in rows `row_start..row_end`
+fn row_slice<T>(partial: &[(usize, T)], row_start: usize, row_end: usize) -> &[(usize, T)] {
+    let start = partial.partition_point(|(row, _)| *row < row_start);
+    let end = partial.partition_point(|(row, _)| *row < row_end);
+    &partial[start..end]
+}
@@ -1,8 +1,10 @@
+use std::thread;
+
 use faer::{
     Accum, ColMut, ColRef, Index, MatMut, MatRef, Par, RowMut, RowRef,
-    dyn_stack::MemStack,
+    dyn_stack::{MemStack, StackReq},
     sparse::{
-        SparseColMatRef, SymbolicSparseColMatRef,
+        SparseColMatRef, SparseRowMatRef, SymbolicSparseColMatRef, SymbolicSparseRowMatRef,
         linalg::matmul::{
             dense_sparse_matmul as seq_dense_sparse, sparse_dense_matmul as seq_sparse_dense,
         },
@@ -10,15 +12,67 @@ use faer::{
     traits::ComplexField,
 };
 
+use crate::cost_model::CostModel;
+use crate::sparse_dense_impl::{buffer_foreign, merge, simple};
+
 pub struct SpMvStrategy {
     pub thread_cols: Vec<usize>,
     pub thread_indptrs: Vec<usize>,
+    /// Rows `thread_rows[t]..thread_rows[t + 1]` of the output are owned by thread `t`, in the
+    /// workspace reductions as well as in the kernels writing to their own rows directly
+    pub thread_rows: Vec<usize>,
+}
+
+/// Split `0..weights.len()` into `n_threads` contiguous ranges with about the same total weight,
+/// returning the `n_threads + 1` boundaries
+pub(crate) fn balanced_splits(weights: &[usize], n_threads: usize) -> Vec<usize> {
+    let n = weights.len();
+    let total: usize = weights.iter().sum();
+    let mut splits = Vec::with_capacity(n_threads + 1);
+    splits.push(0);
+    let mut work_counter = 0;
+    for (i, work) in weights.iter().enumerate() {
+        work_counter += work;
+        while splits.len() < n_threads && work_counter * n_threads >= splits.len() * total {
+            splits.push(i + 1);
+        }
+    }
+    splits.resize(n_threads, n);
+    splits.push(n);
+    splits
+}
+
+/// Call `f` on every input, each on its own scoped thread unless there is only one
+pub(crate) fn run_threads<A: Send, R: Send>(inputs: Vec<A>, f: impl Fn(A) -> R + Sync) -> Vec<R> {
+    if inputs.len() == 1 {
+        return inputs.into_iter().map(f).collect();
+    }
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = inputs
+            .into_iter()
+            .map(|input| s.spawn(move || f(input)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Split the rows into `n_threads` contiguous ranges with about the same output work, counting
+/// one unit per row (the workspace reduction) plus one per nonzero in it (the owner writes)
+fn row_partition<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, n_threads: usize) -> Vec<usize> {
+    let mut row_work = vec![1usize; mat.nrows()];
+    for j in 0..mat.ncols() {
+        for i in mat.row_idx_of_col(j) {
+            row_work[i] += 1;
+        }
+    }
+    balanced_splits(&row_work, n_threads)
 }
 
 impl SpMvStrategy {
     pub fn new<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, par: Par) -> Self {
-        let (thread_cols, thread_indptrs) = match par {
-            Par::Seq => (Vec::new(), Vec::new()),
+        let (thread_cols, thread_indptrs, thread_rows) = match par {
+            Par::Seq => (Vec::new(), Vec::new(), Vec::new()),
             Par::Rayon(n_threads) => {
                 let n_threads = n_threads.get();
 
@@ -59,15 +113,181 @@ impl SpMvStrategy {
                         unimplemented!();
                     }
                 }
-                (thread_cols, thread_indptrs)
+                (thread_cols, thread_indptrs, row_partition(mat, n_threads))
+            }
+        };
+
+        Self {
+            thread_cols,
+            thread_indptrs,
+            thread_rows,
+        }
+    }
+
+    /// Output rows owned by thread `tid`
+    pub fn thread_row_range(&self, tid: usize) -> std::ops::Range<usize> {
+        self.thread_rows[tid]..self.thread_rows[tid + 1]
+    }
+
+    /// Check the lengths, order and bounds of a plan for `par` on an `nrows x ncols` matrix with
+    /// `nnz` nonzeros, see `check_pattern`
+    pub fn check_shape(
+        &self,
+        nrows: usize,
+        ncols: usize,
+        nnz: usize,
+        par: Par,
+    ) -> Result<(), &'static str> {
+        let expected_len = match par {
+            Par::Seq => 0,
+            Par::Rayon(n_threads) => n_threads.get() + 1,
+        };
+        if self.thread_cols.len() != expected_len
+            || self.thread_indptrs.len() != expected_len
+            || self.thread_rows.len() != expected_len
+        {
+            return Err("thread count of the strategy");
+        }
+        if expected_len == 0 {
+            return Ok(());
+        }
+        let spans = |ptr: &[usize], end: usize| {
+            ptr.first() == Some(&0) && ptr.last() == Some(&end) && ptr.is_sorted()
+        };
+        if !spans(&self.thread_rows, nrows) {
+            return Err("thread rows do not fit the matrix");
+        }
+        if !spans(&self.thread_indptrs, nnz) {
+            return Err("thread nonzeros do not fit the matrix");
+        }
+        if self.thread_cols[0] != 0
+            || !self.thread_cols.is_sorted()
+            || self.thread_cols.last().is_none_or(|&col| col >= ncols)
+        {
+            return Err("thread columns do not fit the matrix");
+        }
+        Ok(())
+    }
+
+    /// Check that the plan fits `mat` and `par`. The kernels index `mat` and write their output
+    /// through the plan without bounds checks, so a plan that does not come from the planner for
+    /// this pattern, e.g. one loaded from a file, must pass this before it is used.
+    pub fn check_pattern<I: Index>(
+        &self,
+        mat: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+    ) -> Result<(), &'static str> {
+        let col_ptr = mat.col_ptr();
+        if mat.col_nnz().is_some() {
+            return Err("matrix is not compressed");
+        }
+        self.check_shape(mat.nrows(), mat.ncols(), col_ptr[mat.ncols()].zx(), par)?;
+        // every thread starts and ends inside its boundary columns
+        for (&col, &idx) in self.thread_cols.iter().zip(&self.thread_indptrs) {
+            if idx < col_ptr[col].zx() || idx > col_ptr[col + 1].zx() {
+                return Err("thread nonzeros are outside their columns");
+            }
+        }
+        Ok(())
+    }
+
+    /// Plan that balances the cost of `cost` instead of the nnz, with thread `t` getting a share
+    /// of the total cost proportional to `thread_weights[t]` (all equal if `None`), e.g. the
+    /// relative speed of big and little cores. Threads still start and end in the middle of
+    /// columns, at the first nonzero that takes them past their share. The columns need not be
+    /// compressed, `thread_indptrs` then index the storage of `mat`.
+    pub fn new_weighted<I: Index>(
+        mat: SymbolicSparseColMatRef<'_, I>,
+        par: Par,
+        cost: &CostModel,
+        thread_weights: Option<&[f64]>,
+    ) -> Self {
+        let n_threads = match par {
+            Par::Seq => return Self::new(mat, par),
+            Par::Rayon(n_threads) => n_threads.get(),
+        };
+        let weights = match thread_weights {
+            Some(weights) => {
+                assert_eq!(weights.len(), n_threads);
+                assert!(
+                    weights.iter().all(|w| w.is_finite() && *w > 0.0),
+                    "thread weights must be positive"
+                );
+                weights.to_vec()
             }
+            None => vec![1.0; n_threads],
         };
 
+        let nnz = mat.compute_nnz();
+        // TODO probably don't assert here
+        assert!(nnz > n_threads);
+        let ncols = mat.ncols();
+        // one past the last stored index, which is past `nnz` if the columns are not compressed
+        let end = mat.col_range(ncols - 1).end;
+
+        // cost at which every thread after the first starts
+        let total_cost = cost.nnz_cost * nnz as f64 + cost.col_cost * ncols as f64;
+        let total_weight: f64 = weights.iter().sum();
+        let targets: Vec<f64> = weights[..n_threads - 1]
+            .iter()
+            .scan(0.0, |acc, w| {
+                *acc += w;
+                Some(*acc / total_weight * total_cost)
+            })
+            .collect();
+
+        let mut thread_indptrs = Vec::with_capacity(n_threads + 1);
+        thread_indptrs.push(0);
+        let mut cost_counter = 0.0;
+        for col in 0..ncols {
+            let col_range = mat.col_range(col);
+            let (start, col_nnz) = (col_range.start, col_range.len());
+            // the column switch is paid before the first nonzero
+            let first_nnz_cost = cost_counter + cost.col_cost;
+            cost_counter = first_nnz_cost + cost.nnz_cost * col_nnz as f64;
+            while thread_indptrs.len() < n_threads
+                && targets[thread_indptrs.len() - 1] < cost_counter
+            {
+                let target = targets[thread_indptrs.len() - 1];
+                let offset = if cost.nnz_cost > 0.0 {
+                    ((target - first_nnz_cost) / cost.nnz_cost).ceil().max(0.0) as usize
+                } else {
+                    0
+                };
+                thread_indptrs.push(start + offset.min(col_nnz));
+            }
+        }
+        // rounding can leave the last targets unreached
+        thread_indptrs.resize(n_threads, end);
+        thread_indptrs.push(end);
+
+        // every thread starts in the first column with a nonzero at or after its first index,
+        // skipping the unused storage between the columns
+        let mut thread_cols = Vec::with_capacity(n_threads + 1);
+        thread_cols.push(0);
+        let mut col = 0;
+        for idx in &mut thread_indptrs[1..n_threads] {
+            while col + 1 < ncols && mat.col_range(col).end <= *idx {
+                col += 1;
+            }
+            *idx = (*idx).max(mat.col_range(col).start);
+            thread_cols.push(col);
+        }
+        thread_cols.push(ncols - 1);
+
         Self {
             thread_cols,
             thread_indptrs,
+            thread_rows: row_partition(mat, n_threads),
         }
     }
+
+    /// Plan for a CSR matrix. The row pointers of `mat` are the column pointers of its CSC
+    /// transpose, so threads get even slices of the stored rows and `thread_cols` holds the
+    /// starting / ending *row* of each slice. Use this plan with the `SparseRowMatRef` drivers.
+    pub fn new_csr<I: Index>(mat: SymbolicSparseRowMatRef<'_, I>, par: Par) -> Self {
+        Self::new(mat.transpose(), par)
+    }
 }
 
 pub type SparseDenseImplFn<I, T> = fn(
@@ -92,6 +312,54 @@ pub type DenseSparseImplFn<I, T> = fn(
     stack: &mut MemStack,
 );
 
+/// The parallel `sparse_dense_impl` kernels, which share the plan of `SpMvStrategy::new`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpMvBackend {
+    /// Per thread workspaces summed by row range
+    Simple,
+    /// Per thread k-way merge of the columns, which needs sorted row indices. Pins its threads
+    /// to the first `n_threads` cores.
+    Merge,
+    /// Foreign row blocks exchanged in chunks between the threads. Pins its threads to the first
+    /// `n_threads` cores.
+    BufferForeign,
+}
+
+impl SpMvBackend {
+    pub const ALL: [Self; 3] = [Self::Simple, Self::Merge, Self::BufferForeign];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Simple => "simple",
+            Self::Merge => "merge",
+            Self::BufferForeign => "buffer_foreign",
+        }
+    }
+
+    pub fn par_impl<I: Index, T: ComplexField>(self) -> SparseDenseImplFn<I, T> {
+        match self {
+            Self::Simple => simple::par_sparse_dense,
+            Self::Merge => merge::par_sparse_dense,
+            Self::BufferForeign => buffer_foreign::par_sparse_dense,
+        }
+    }
+
+    /// Scratch space of `par_impl` for `lhs * rhs`
+    pub fn scratch<I: Index, T: ComplexField>(
+        self,
+        lhs: SparseColMatRef<'_, I, T>,
+        rhs: MatRef<'_, T>,
+        strategy: &SpMvStrategy,
+        par: Par,
+    ) -> StackReq {
+        match self {
+            Self::Simple => simple::sparse_dense_scratch(lhs, rhs, strategy, par),
+            Self::Merge => merge::sparse_dense_scratch(lhs, rhs, strategy, par),
+            Self::BufferForeign => buffer_foreign::sparse_dense_scratch(lhs, rhs, strategy, par),
+        }
+    }
+}
+
 pub fn sparse_dense_matmul<I: Index, T: ComplexField>(
     dst: MatMut<'_, T>,
     beta: Accum,
@@ -149,3 +417,62 @@ pub fn dense_sparse_matmul<I: Index, T: ComplexField>(
         }
     }
 }
+
+/// `dst = beta * dst + alpha * lhs * rhs` for a CSR `lhs`.
+///
+/// Every entry of `dst` is a dot product with a stored row of `lhs`, so this is `rhs^T lhs^T` with
+/// `lhs^T` in CSC format and runs on the race-free `dense_sparse_impl` algorithm. `strategy` must
+/// come from `SpMvStrategy::new_csr` and the scratch from
+/// `dense_sparse_impl::sparse_row_dense_scratch`.
+pub fn sparse_row_dense_matmul<I: Index, T: ComplexField>(
+    dst: MatMut<'_, T>,
+    beta: Accum,
+    lhs: SparseRowMatRef<'_, I, T>,
+    rhs: MatRef<'_, T>,
+    alpha: T,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+    par_impl: Option<DenseSparseImplFn<I, T>>,
+) {
+    dense_sparse_matmul(
+        dst.transpose_mut(),
+        beta,
+        rhs.transpose(),
+        lhs.transpose(),
+        alpha,
+        par,
+        strategy,
+        stack,
+        par_impl,
+    );
+}
+
+/// `dst = beta * dst + alpha * lhs * rhs` for a CSR `rhs`.
+///
+/// This is `rhs^T lhs^T` with `rhs^T` in CSC format, so the output is scattered and one of the
+/// `sparse_dense_impl` kernels is required. `strategy` must come from `SpMvStrategy::new_csr` and
+/// the scratch from the kernel's `sparse_dense_scratch` called with the transposed operands.
+pub fn dense_sparse_row_matmul<I: Index, T: ComplexField>(
+    dst: MatMut<'_, T>,
+    beta: Accum,
+    lhs: MatRef<'_, T>,
+    rhs: SparseRowMatRef<'_, I, T>,
+    alpha: T,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+    par_impl: Option<SparseDenseImplFn<I, T>>,
+) {
+    sparse_dense_matmul(
+        dst.transpose_mut(),
+        beta,
+        rhs.transpose(),
+        lhs.transpose(),
+        alpha,
+        par,
+        strategy,
+        stack,
+        par_impl,
+    );
+}
@@ -100,7 +100,7 @@ fn get_matrix_nnz(path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
     }
 
     // Parse the header line: rows cols nnz
-    let parts: Vec<&str> = line.trim().split_whitespace().collect();
+    let parts: Vec<&str> = line.split_whitespace().collect();
     if parts.len() < 3 {
         return Err(format!("Invalid Matrix Market header: {}", line.trim()).into());
     }
@@ -368,3 +368,37 @@ impl FaerLoader {
         }
     }
 }
+
+/// 5-point Laplacian on an `nx` by `ny` grid with Dirichlet boundaries, the usual symmetric
+/// positive definite model problem for smoothers and solvers
+pub fn laplacian_2d(nx: usize, ny: usize) -> SparseColMat<usize, f64> {
+    convection_diffusion_2d(nx, ny, 0.0, 0.0)
+}
+
+/// `laplacian_2d` plus the first order upwind discretization of a convection with velocity
+/// `(cx, cy) >= 0` in units of the grid spacing. Nonsymmetric, and convection dominated once the
+/// velocity is well above 2.
+pub fn convection_diffusion_2d(nx: usize, ny: usize, cx: f64, cy: f64) -> SparseColMat<usize, f64> {
+    assert!(cx >= 0.0 && cy >= 0.0);
+    let n = nx * ny;
+    let mut triplets = Vec::with_capacity(5 * n);
+    for y in 0..ny {
+        for x in 0..nx {
+            let i = y * nx + x;
+            triplets.push(Triplet::new(i, i, 4.0 + cx + cy));
+            if x > 0 {
+                triplets.push(Triplet::new(i, i - 1, -1.0 - cx));
+            }
+            if x + 1 < nx {
+                triplets.push(Triplet::new(i, i + 1, -1.0));
+            }
+            if y > 0 {
+                triplets.push(Triplet::new(i, i - nx, -1.0 - cy));
+            }
+            if y + 1 < ny {
+                triplets.push(Triplet::new(i, i + nx, -1.0));
+            }
+        }
+    }
+    SparseColMat::try_new_from_triplets(n, n, &triplets).unwrap()
+}
@@ -0,0 +1,235 @@
+//! Parallel transpose, equivalently CSC <-> CSR format conversion.
+//!
+//! The race-free `dense_sparse_impl` kernel needs the matrix stored along the output dimension,
+//! so it often pays to convert once and run that kernel thereafter. This is a counting sort over
+//! the row indices: every thread counts the rows in its slice of the `SpMvStrategy` nnz
+//! partition, a parallel prefix sum over `(row, thread)` gives every thread a private write
+//! cursor per output column, and then the threads scatter their entries without synchronization.
+//! Threads own increasing column ranges, so the row indices of the result come out sorted.
+use std::thread;
+
+use faer::{
+    ColMut, Index, MatMut, Par,
+    dyn_stack::{MemStack, StackReq},
+    prelude::{Reborrow, ReborrowMut},
+    sparse::{
+        SparseColMat, SparseColMatRef, SparseRowMat, SparseRowMatRef, SymbolicSparseColMat,
+        SymbolicSparseColMatRef,
+    },
+    traits::ComplexField,
+};
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+use crate::spmv_drivers::SpMvStrategy;
+
+pub fn transpose_scratch<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, par: Par) -> StackReq {
+    let n_threads = match par {
+        Par::Seq => 1,
+        Par::Rayon(n_threads) => n_threads.get(),
+    };
+    StackReq::new::<usize>(mat.nrows() * n_threads)
+}
+
+/// Count the occurrences of each row in `row_indices[idx_start..idx_end]`
+fn count_rows<I: Index>(row_indices: &[I], idx_start: usize, idx_end: usize, counts: &mut [usize]) {
+    for row in &row_indices[idx_start..idx_end] {
+        counts[row.zx()] += 1;
+    }
+}
+
+/// Turn the per-thread row counts into per-thread write cursors and fill in `col_ptr` of the
+/// transpose. `counts` is `nrows` by `n_threads` in column-major order.
+fn counts_to_offsets<I: Index>(counts: &mut [usize], col_ptr: &mut [I], n_threads: usize) {
+    let m = col_ptr.len() - 1;
+    col_ptr[0] = I::truncate(0);
+    if m == 0 {
+        return;
+    }
+    let mut counts = MatMut::from_column_major_slice_mut(counts, m, n_threads);
+    let rows_per_chunk = m.div_ceil(n_threads);
+
+    // exclusive prefix over threads for each row, storing the row totals in `col_ptr[1..]`
+    let chunk_totals: Vec<usize> = counts
+        .rb_mut()
+        .par_row_chunks_mut(rows_per_chunk)
+        .zip(col_ptr[1..].par_chunks_mut(rows_per_chunk))
+        .map(|(mut counts, totals)| {
+            let mut chunk_total = 0;
+            for (i, total) in totals.iter_mut().enumerate() {
+                let mut running = 0;
+                for tid in 0..n_threads {
+                    let count = counts[(i, tid)];
+                    counts[(i, tid)] = running;
+                    running += count;
+                }
+                *total = I::truncate(running);
+                chunk_total += running;
+            }
+            chunk_total
+        })
+        .collect();
+
+    let mut chunk_bases = Vec::with_capacity(chunk_totals.len());
+    let mut base = 0;
+    for total in chunk_totals {
+        chunk_bases.push(base);
+        base += total;
+    }
+
+    counts
+        .par_row_chunks_mut(rows_per_chunk)
+        .zip(col_ptr[1..].par_chunks_mut(rows_per_chunk))
+        .zip(chunk_bases.into_par_iter())
+        .for_each(|((mut counts, totals), base)| {
+            let mut running = base;
+            for (i, total) in totals.iter_mut().enumerate() {
+                for tid in 0..n_threads {
+                    counts[(i, tid)] += running;
+                }
+                running += total.zx();
+                *total = I::truncate(running);
+            }
+        });
+}
+
+/// Move the entries of columns `col_start..=col_end`, clipped to `idx_start..idx_end`, to their
+/// positions in the transpose
+fn scatter_range<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    col_start: usize,
+    col_end: usize,
+    idx_start: usize,
+    idx_end: usize,
+    cursors: &mut [usize],
+    mut dst_row_idx: ColMut<'_, I>,
+    mut dst_values: ColMut<'_, T>,
+) {
+    let (symbolic, values) = mat.parts();
+    let row_indices = symbolic.row_idx();
+    for col in col_start..=col_end {
+        let mut col_range = symbolic.col_range(col);
+        if col == col_start {
+            col_range.start = idx_start;
+        }
+        if col == col_end {
+            col_range.end = idx_end;
+        }
+        for idx in col_range {
+            let cursor = &mut cursors[row_indices[idx].zx()];
+            dst_row_idx[*cursor] = I::truncate(col);
+            dst_values[*cursor] = values[idx].clone();
+            *cursor += 1;
+        }
+    }
+}
+
+/// Compute `mat^T` in CSC format, which is `mat` in CSR format. `strategy` must be built from
+/// `mat.symbolic()` with the same `par`.
+pub fn par_transpose<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> SparseColMat<I, T> {
+    let (symbolic, _) = mat.parts();
+    assert!(
+        symbolic.col_nnz().is_none(),
+        "transpose requires a compressed matrix"
+    );
+    let m = mat.nrows();
+    let n = mat.ncols();
+    let nnz = symbolic.compute_nnz();
+    let row_indices = symbolic.row_idx();
+
+    let mut col_ptr = vec![I::truncate(0); m + 1];
+    let mut dst_row_idx = vec![I::truncate(0); nnz];
+    let mut dst_values = vec![T::zero_impl(); nnz];
+
+    match par {
+        Par::Seq => {
+            let (mut counts, _) = stack.make_with::<usize>(m, |_| 0);
+            count_rows(row_indices, 0, nnz, &mut counts);
+            counts_to_offsets(&mut counts, &mut col_ptr, 1);
+            if n > 0 {
+                scatter_range(
+                    mat,
+                    0,
+                    n - 1,
+                    0,
+                    nnz,
+                    &mut counts,
+                    ColMut::from_slice_mut(&mut dst_row_idx),
+                    ColMut::from_slice_mut(&mut dst_values),
+                );
+            }
+        }
+        Par::Rayon(n_threads) => {
+            let n_threads = n_threads.get();
+            let (mut counts, _) = stack.make_with::<usize>(m * n_threads, |_| 0);
+
+            thread::scope(|s| {
+                for (tid, counts) in counts.chunks_mut(m).enumerate() {
+                    let idx_start = strategy.thread_indptrs[tid];
+                    let idx_end = strategy.thread_indptrs[tid + 1];
+                    s.spawn(move || count_rows(row_indices, idx_start, idx_end, counts));
+                }
+            });
+
+            counts_to_offsets(&mut counts, &mut col_ptr, n_threads);
+
+            let dst_row_idx = ColMut::from_slice_mut(&mut dst_row_idx);
+            let dst_values = ColMut::from_slice_mut(&mut dst_values);
+            thread::scope(|s| {
+                let dst_row_idx = dst_row_idx.rb();
+                let dst_values = dst_values.rb();
+                for (tid, cursors) in counts.chunks_mut(m).enumerate() {
+                    s.spawn(move || {
+                        // SAFETY: the prefix sum gives every (row, thread) pair a disjoint range
+                        // of output positions, so no two threads write the same entry
+                        let dst_row_idx = unsafe { dst_row_idx.const_cast() };
+                        let dst_values = unsafe { dst_values.const_cast() };
+                        scatter_range(
+                            mat,
+                            strategy.thread_cols[tid],
+                            strategy.thread_cols[tid + 1],
+                            strategy.thread_indptrs[tid],
+                            strategy.thread_indptrs[tid + 1],
+                            cursors,
+                            dst_row_idx,
+                            dst_values,
+                        );
+                    });
+                }
+            });
+        }
+    }
+
+    // SAFETY: the column pointers come from a prefix sum over the row counts and every
+    // column's row indices are in bounds and sorted by construction
+    let symbolic = unsafe { SymbolicSparseColMat::new_unchecked(n, m, col_ptr, None, dst_row_idx) };
+    SparseColMat::new(symbolic, dst_values)
+}
+
+/// Convert a CSC matrix to CSR. `strategy` must come from `SpMvStrategy::new(mat.symbolic(), par)`.
+pub fn par_to_row_major<I: Index, T: ComplexField>(
+    mat: SparseColMatRef<'_, I, T>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> SparseRowMat<I, T> {
+    par_transpose(mat, par, strategy, stack).into_transpose()
+}
+
+/// Convert a CSR matrix to CSC. `strategy` must come from
+/// `SpMvStrategy::new_csr(mat.symbolic(), par)`.
+pub fn par_to_col_major<I: Index, T: ComplexField>(
+    mat: SparseRowMatRef<'_, I, T>,
+    par: Par,
+    strategy: &SpMvStrategy,
+    stack: &mut MemStack,
+) -> SparseColMat<I, T> {
+    par_transpose(mat.transpose(), par, strategy, stack)
+}
@@ -0,0 +1,101 @@
+//! Level-scheduled parallel sparse triangular solves.
+//!
+//! Solving `L x = b` row by row, `x_i = (b_i - sum_k L_ik x_k) / L_ii` only needs the `x_k` of the
+//! rows `i` depends on, so every row whose dependencies are solved can go at the same time.
+//! `TriangularPlan` sorts the rows into such levels from the symbolic structure and, like
+//! `SpMvStrategy`, splits every level into per-thread ranges with the same amount of work. The
+//! gather form needs the rows of the CSC matrix, so the plan also stores the transposed pattern of
+//! the strict triangle together with the position of every entry in the values of the matrix; the
+//! solve then reads the values of whatever matrix with that pattern it is handed.
+//!
+//! Entries outside the triangle selected by `Side` are ignored.
+use faer::{
+    ColMut, Index, Par, Side,
+    prelude::Reborrow,
+    sparse::{SparseColMatRef, SymbolicSparseColMatRef},
+    traits::{ComplexField, math_utils::recip},
+};
+
+use crate::schedule::{RowPattern, RowSchedule};
+
+/// Whether the diagonal is read from the matrix or assumed to be all ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Diag {
+    Unit,
+    NonUnit,
+}
+
+pub struct TriangularPlan {
+    side: Side,
+    /// Strict triangle of every row
+    rows: RowPattern,
+    levels: RowSchedule,
+}
+
+impl TriangularPlan {
+    /// Plan the solve with the `side` triangle of the square matrix `mat` for `par`
+    pub fn new<I: Index>(mat: SymbolicSparseColMatRef<'_, I>, side: Side, par: Par) -> Self {
+        let rows = RowPattern::new(mat, |i, j| match side {
+            Side::Lower => i > j,
+            Side::Upper => i < j,
+        });
+        let n = rows.n();
+
+        // a row goes one level after the deepest row it depends on, which is solved earlier in
+        // substitution order
+        let mut level = vec![0usize; n];
+        let mut n_levels = 0;
+        let mut set_level = |i: usize| {
+            level[i] = rows.row(i).iter().map(|&k| level[k] + 1).max().unwrap_or(0);
+            n_levels = n_levels.max(level[i] + 1);
+        };
+        match side {
+            Side::Lower => (0..n).for_each(&mut set_level),
+            Side::Upper => (0..n).rev().for_each(&mut set_level),
+        }
+        let levels = RowSchedule::new(&level, n_levels, |i| 1 + rows.row_len(i), par);
+
+        Self { side, rows, levels }
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn n_levels(&self) -> usize {
+        self.levels.n_groups()
+    }
+
+    /// Rows solved in level `level`, all of them only depend on rows of earlier levels
+    pub fn level_rows(&self, level: usize) -> &[usize] {
+        self.levels.group(level)
+    }
+
+    /// Solve `A x = rhs` in place with the planned triangle of `mat`, which must have the pattern
+    /// the plan was built from
+    pub fn solve_in_place<I: Index, T: ComplexField>(
+        &self,
+        mat: SparseColMatRef<'_, I, T>,
+        diag: Diag,
+        rhs: ColMut<'_, T>,
+    ) {
+        self.rows.check(mat, diag == Diag::NonUnit);
+        assert_eq!(rhs.nrows(), self.rows.n());
+        let values = mat.val();
+        let x = rhs.rb();
+
+        for level in 0..self.n_levels() {
+            self.levels.for_each_row(level, |i| {
+                // SAFETY: every row of a level is solved by a single thread and only reads rows
+                // of earlier levels, which are final
+                let mut x = unsafe { x.const_cast() };
+                let sum = self.rows.row_dot(i, values, |k| x[k].clone());
+                let mut x_i = x[i].sub_by_ref(&sum);
+                if diag == Diag::NonUnit {
+                    x_i = x_i.mul_by_ref(&recip(self.rows.diag(i, values)));
+                }
+                x[i] = x_i;
+            });
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Blocked vector operations shared by the kernels returning reductions and the iterative methods.
+//!
+//! The vectors are split into fixed blocks of rows, so the reductions run on the rayon pool with
+//! `Par::Rayon` and give the same result for every thread count.
+use std::ops::Range;
+
+use faer::{
+    ColMut, ColRef, Par,
+    prelude::Reborrow,
+    traits::{
+        ComplexField,
+        math_utils::{conj, mul_real, zero},
+    },
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Rows per task of the vector operations
+const VECTOR_BLOCK_ROWS: usize = 4096;
+
+/// Per block partial of a sum
+pub(crate) struct Sum<T: ComplexField>(pub T);
+
+impl<T: ComplexField> Default for Sum<T> {
+    fn default() -> Self {
+        Self(zero())
+    }
+}
+
+impl<T: ComplexField> Sum<T> {
+    pub(crate) fn total(partials: Vec<Self>) -> T {
+        let mut total = zero::<T>();
+        for partial in partials {
+            total = total.add_by_ref(&partial.0);
+        }
+        total
+    }
+}
+
+/// Call `f` on every block of `VECTOR_BLOCK_ROWS` rows of `0..n`, on the rayon pool unless `par` is
+/// `Par::Seq`, and return the accumulators of the blocks in order
+pub(crate) fn for_each_block<R, F>(n: usize, par: Par, f: F) -> Vec<R>
+where
+    R: Default + Send,
+    F: Fn(Range<usize>, &mut R) + Sync,
+{
+    let block = |block: usize| {
+        let start = block * VECTOR_BLOCK_ROWS;
+        let mut acc = R::default();
+        f(start..(start + VECTOR_BLOCK_ROWS).min(n), &mut acc);
+        acc
+    };
+    let n_blocks = n.div_ceil(VECTOR_BLOCK_ROWS);
+    match par {
+        Par::Seq => (0..n_blocks).map(block).collect(),
+        Par::Rayon(_) => (0..n_blocks).into_par_iter().map(block).collect(),
+    }
+}
+
+/// `<lhs, rhs> = sum_i conj(lhs_i) rhs_i`
+pub(crate) fn dot<T: ComplexField>(lhs: ColRef<'_, T>, rhs: ColRef<'_, T>, par: Par) -> T {
+    assert_eq!(lhs.nrows(), rhs.nrows());
+    Sum::total(for_each_block(
+        lhs.nrows(),
+        par,
+        |rows, acc: &mut Sum<T>| {
+            for i in rows {
+                acc.0 = acc.0.add_by_ref(&conj(&lhs[i]).mul_by_ref(&rhs[i]));
+            }
+        },
+    ))
+}
+
+/// `v = factor * v`
+pub(crate) fn scale<T: ComplexField>(v: ColMut<'_, T>, factor: &T::Real, par: Par) {
+    let v = v.rb();
+    for_each_block(v.nrows(), par, |rows, _: &mut ()| {
+        // SAFETY: the blocks are disjoint
+        let mut v = unsafe { v.const_cast() };
+        for i in rows {
+            v[i] = mul_real(&v[i], factor);
+        }
+    });
+}
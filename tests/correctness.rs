@@ -1,19 +1,69 @@
 use std::num::NonZero;
+use std::sync::Arc;
 
-use faer::{Mat, Par};
+use faer::{
+    Accum, Mat, Par, Side,
+    sparse::{SparseColMat, SymbolicSparseColMat, Triplet},
+};
 use nalgebra::DVector;
 
 use par_matvec::{
-    dense_sparse_impl::{dense_sparse_scratch, par_dense_sparse},
+    analysis::PartitionReport,
+    cost_model::CostModel,
+    dense_sparse_impl::{
+        dense_sparse_scratch, par_dense_sparse, par_dense_sparse_dot, sparse_row_dense_scratch,
+    },
+    fused::{
+        normal::{aat_matmul, aat_scratch, ata_matmul, ata_scratch},
+        residual::{residual, residual_scratch},
+    },
+    numa::{NumaTopology, first_touch_copy, parse_cpulist, pinned_first_touch_copy},
+    operator::{
+        LinearOperator, SparseOperator,
+        compose::{
+            GalerkinOperator, ProductOperator, ScaledOperator, SumOperator, TransposedOperator,
+        },
+    },
+    plan_cache::{PatternFingerprint, PlanCache},
+    plan_io::{PLAN_MAGIC, PlanIoError, load_plan, read_plan, save_plan, write_plan},
+    reorder::{
+        metrics::{ForeignWriteReport, MatrixMetrics, bandwidth, profile},
+        partition::{metis_bipartite, metis_symmetric},
+        permuted::PermutedOperator,
+        rcm::rcm,
+    },
+    smoothers::{
+        chebyshev::{ChebyshevSmoother, LAMBDA_MAX_SAFETY},
+        gauss_seidel::{GaussSeidelPlan, Sweep},
+        jacobi::{JacobiDiagonal, JacobiSmoother},
+    },
+    solvers::{
+        StoppingCriteria,
+        bicgstab::{bicgstab, bicgstab_scratch},
+        cg::{cg, cg_scratch, pcg},
+        gmres::{gmres, gmres_scratch},
+    },
     sparse_dense_impl::{buffer_foreign, merge, simple},
-    spmv_drivers::{SpMvStrategy, dense_sparse_matmul, sparse_dense_matmul},
-    test_utils::{TestMatrices, small_matrix_paths},
+    spectral::{lanczos, lanczos_scratch, power_iteration, power_iteration_scratch},
+    spgemm::{SpGemmPlan, rap, spgemm},
+    spmspv::{SparseVec, spmspv, spmspv_dense, spmspv_scratch},
+    spmv_drivers::{
+        SpMvBackend, SpMvStrategy, dense_sparse_matmul, dense_sparse_row_matmul,
+        sparse_dense_matmul, sparse_row_dense_matmul,
+    },
+    test_utils::{TestMatrices, convection_diffusion_2d, laplacian_2d, small_matrix_paths},
+    transpose::{par_to_col_major, par_to_row_major, transpose_scratch},
+    triangular::{Diag, TriangularPlan},
 };
 
 /// Tolerance for floating point comparisons
 const RELATIVE_TOLERANCE: f64 = 1e-12;
 const ABSOLUTE_TOLERANCE: f64 = 1e-8;
 
+/// Thread counts for kernels which don't pin threads to cores, so these run even when
+/// oversubscribed on small CI machines
+const FIXED_THREAD_COUNTS: [usize; 3] = [2, 3, 4];
+
 /// Convert different vector types to a common Vec<f64> for comparison
 trait ToVecF64 {
     fn to_vec_f64(&self) -> Vec<f64>;
@@ -25,6 +75,12 @@ impl ToVecF64 for Mat<f64> {
     }
 }
 
+impl ToVecF64 for faer::Col<f64> {
+    fn to_vec_f64(&self) -> Vec<f64> {
+        self.iter().copied().collect()
+    }
+}
+
 impl ToVecF64 for nalgebra::DVector<f64> {
     fn to_vec_f64(&self) -> Vec<f64> {
         self.as_slice().to_vec()
@@ -208,7 +264,7 @@ fn test_parallel_implementations(
                     par,
                 );
                 let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req)?;
-                let mut stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+                let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
 
                 sparse_dense_matmul(
                     parallel_output.as_mut(),
@@ -218,7 +274,7 @@ fn test_parallel_implementations(
                     1.0,
                     par,
                     &strategy,
-                    &mut stack,
+                    stack,
                     Some(*par_impl),
                 );
 
@@ -238,7 +294,7 @@ fn test_parallel_implementations(
             let stack_req =
                 dense_sparse_scratch(lhs_vector, matrices.faer_csc.as_ref(), &strategy, par);
             let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req)?;
-            let mut stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
 
             let mut parallel_output = Mat::zeros(1, matrices.nrows);
             dense_sparse_matmul(
@@ -249,7 +305,7 @@ fn test_parallel_implementations(
                 1.0,
                 par,
                 &strategy,
-                &mut stack,
+                stack,
                 Some(par_dense_sparse),
             );
 
@@ -276,6 +332,2097 @@ fn test_parallel_implementations(
     Ok(())
 }
 
+/// Test the CSR entry points against the sequential CSC reference
+fn test_csr_implementations(matrices: &TestMatrices) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Testing CSR implementations on matrix '{}' ({}x{}, {} nnz)",
+        matrices.matrix_name, matrices.nrows, matrices.ncols, matrices.nnz
+    );
+
+    let csr = matrices.faer_csc.to_row_major()?;
+
+    let mut sparse_dense_reference_output = Mat::zeros(matrices.nrows, 1);
+    faer::sparse::linalg::matmul::sparse_dense_matmul(
+        sparse_dense_reference_output.as_mut(),
+        faer::Accum::Replace,
+        matrices.faer_csc.as_ref(),
+        matrices.rhs_vector.as_ref(),
+        1.0,
+        Par::Seq,
+    );
+
+    let lhs_vector = Mat::from_fn(1, matrices.nrows, |_, i| (i as f64 * 0.3 + 1.0) % 7.0);
+    let mut dense_sparse_reference_output = Mat::zeros(1, matrices.ncols);
+    faer::sparse::linalg::matmul::dense_sparse_matmul(
+        dense_sparse_reference_output.as_mut(),
+        faer::Accum::Replace,
+        lhs_vector.as_ref(),
+        matrices.faer_csc.as_ref(),
+        1.0,
+        Par::Seq,
+    );
+
+    for num_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(num_threads).unwrap());
+        let strategy = SpMvStrategy::new_csr(csr.symbolic(), par);
+
+        let stack_req =
+            sparse_row_dense_scratch(csr.as_ref(), matrices.rhs_vector.as_ref(), &strategy, par);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req)?;
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let mut parallel_output = Mat::zeros(matrices.nrows, 1);
+        sparse_row_dense_matmul(
+            parallel_output.as_mut(),
+            faer::Accum::Replace,
+            csr.as_ref(),
+            matrices.rhs_vector.as_ref(),
+            1.0,
+            par,
+            &strategy,
+            stack,
+            Some(par_dense_sparse),
+        );
+
+        assert!(
+            vectors_are_equal(
+                &sparse_dense_reference_output,
+                &parallel_output,
+                RELATIVE_TOLERANCE,
+                ABSOLUTE_TOLERANCE
+            ),
+            "Parallel CSR sparse-dense implementation with {} threads differs from reference",
+            num_threads
+        );
+
+        let stack_req =
+            simple::sparse_dense_scratch(csr.transpose(), lhs_vector.transpose(), &strategy, par);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::try_new(stack_req)?;
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let mut parallel_output = Mat::zeros(1, matrices.ncols);
+        dense_sparse_row_matmul(
+            parallel_output.as_mut(),
+            faer::Accum::Replace,
+            lhs_vector.as_ref(),
+            csr.as_ref(),
+            1.0,
+            par,
+            &strategy,
+            stack,
+            Some(simple::par_sparse_dense),
+        );
+
+        assert!(
+            vectors_are_equal(
+                &dense_sparse_reference_output.transpose().to_owned(),
+                &parallel_output.transpose().to_owned(),
+                RELATIVE_TOLERANCE,
+                ABSOLUTE_TOLERANCE
+            ),
+            "Parallel CSR dense-sparse implementation with {} threads differs from reference",
+            num_threads
+        );
+
+        println!(
+            "  ✓ CSR entry points with {} threads match reference",
+            num_threads
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_transpose() {
+    let test_cases = [
+        (100, 100, 0.05),
+        (300, 120, 0.1),
+        (120, 300, 0.1),
+        (200, 200, 1.0),
+    ];
+
+    for &(nrows, ncols, density) in &test_cases {
+        let matrices = TestMatrices::create_synthetic(nrows, ncols, density);
+        let csc = matrices.faer_csc.as_ref();
+        let reference = csc.to_row_major().unwrap();
+
+        let mut pars = vec![Par::Seq];
+        pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+        for par in pars {
+            let strategy = SpMvStrategy::new(csc.symbolic(), par);
+            let mut stack_buffer =
+                faer::dyn_stack::MemBuffer::new(transpose_scratch(csc.symbolic(), par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let csr = par_to_row_major(csc, par, &strategy, stack);
+
+            assert_eq!(csr.symbolic().row_ptr(), reference.symbolic().row_ptr());
+            assert_eq!(csr.symbolic().col_idx(), reference.symbolic().col_idx());
+            assert_eq!(csr.val(), reference.val());
+
+            let strategy = SpMvStrategy::new_csr(csr.symbolic(), par);
+            let mut stack_buffer =
+                faer::dyn_stack::MemBuffer::new(transpose_scratch(csr.symbolic().transpose(), par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let round_trip = par_to_col_major(csr.as_ref(), par, &strategy, stack);
+
+            assert_eq!(round_trip.symbolic().col_ptr(), csc.symbolic().col_ptr());
+            assert_eq!(round_trip.symbolic().row_idx(), csc.symbolic().row_idx());
+            assert_eq!(round_trip.val(), csc.val());
+        }
+        println!(
+            "  ✓ Parallel transpose of {} matches reference",
+            matrices.matrix_name
+        );
+    }
+}
+
+#[test]
+fn test_fused_normal_products() {
+    let test_cases = [
+        (100, 100, 0.05),
+        (300, 120, 0.1),
+        (120, 300, 0.1),
+        (200, 200, 1.0),
+    ];
+
+    for &(nrows, ncols, density) in &test_cases {
+        let matrices = TestMatrices::create_synthetic(nrows, ncols, density);
+        let mat = matrices.faer_csc.as_ref();
+        let x_n = Mat::from_fn(ncols, 1, |i, _| (i as f64 * 0.3 + 1.0) % 7.0);
+        let x_m = Mat::from_fn(nrows, 1, |i, _| (i as f64 * 0.7 + 2.0) % 5.0);
+
+        let ata_reference = mat.transpose() * (mat * &x_n);
+        let aat_reference = mat * (mat.transpose() * &x_m);
+
+        let mut pars = vec![Par::Seq];
+        pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+        for par in pars {
+            let strategy = SpMvStrategy::new(mat.symbolic(), par);
+
+            let mut stack_buffer =
+                faer::dyn_stack::MemBuffer::new(ata_scratch(mat, x_n.as_ref(), &strategy, par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut ata_output = Mat::zeros(ncols, 1);
+            ata_matmul(
+                ata_output.as_mut(),
+                faer::Accum::Replace,
+                mat,
+                x_n.as_ref(),
+                1.0,
+                par,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(
+                    &ata_reference,
+                    &ata_output,
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "Fused A^T A x differs from reference with {:?}",
+                par
+            );
+
+            let mut stack_buffer =
+                faer::dyn_stack::MemBuffer::new(aat_scratch(mat, x_m.as_ref(), &strategy, par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut aat_output = Mat::zeros(nrows, 1);
+            aat_matmul(
+                aat_output.as_mut(),
+                faer::Accum::Replace,
+                mat,
+                x_m.as_ref(),
+                1.0,
+                par,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(
+                    &aat_reference,
+                    &aat_output,
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "Fused A A^T x differs from reference with {:?}",
+                par
+            );
+        }
+        println!(
+            "  ✓ Fused normal products on {} match reference",
+            matrices.matrix_name
+        );
+    }
+}
+
+#[test]
+fn test_fused_residual() {
+    let test_cases = [(100, 100, 0.05), (300, 120, 0.1), (2000, 2000, 0.05)];
+
+    for &(nrows, ncols, density) in &test_cases {
+        let matrices = TestMatrices::create_synthetic(nrows, ncols, density);
+        let mat = matrices.faer_csc.as_ref();
+        let x = matrices.rhs_vector.col(0);
+        let b = faer::Col::from_fn(nrows, |i| (i as f64 * 0.7 + 2.0) % 5.0);
+        let z = faer::Col::from_fn(nrows, |i| (i as f64 * 0.2 + 1.0) % 3.0);
+
+        let reference = &b - mat * x;
+        let reference_norm_sq = reference.squared_norm_l2();
+        let reference_dot = reference.transpose() * &z;
+
+        let mut pars = vec![Par::Seq];
+        pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+        for par in pars {
+            let strategy = SpMvStrategy::new(mat.symbolic(), par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(residual_scratch(mat, par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+            let mut r = faer::Col::zeros(nrows);
+            let norms = residual(
+                r.as_mut(),
+                b.as_ref(),
+                mat,
+                x,
+                Some(z.as_ref()),
+                par,
+                &strategy,
+                stack,
+            );
+
+            assert!(
+                vectors_are_equal(&reference, &r, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "Fused residual differs from reference with {:?}",
+                par
+            );
+            assert!((norms.norm_sq - reference_norm_sq).abs() <= 1e-10 * reference_norm_sq);
+            let dot = norms.dot.unwrap();
+            assert!((dot - reference_dot).abs() <= 1e-10 * reference_dot.abs().max(1.0));
+        }
+        println!(
+            "  ✓ Fused residual on {} matches reference",
+            matrices.matrix_name
+        );
+    }
+}
+
+#[test]
+fn test_fused_spmv_dot() {
+    let test_cases = [(100, 100, 0.05), (300, 120, 0.1), (2000, 2000, 0.05)];
+    let alpha = 0.5;
+
+    for &(nrows, ncols, density) in &test_cases {
+        let matrices = TestMatrices::create_synthetic(nrows, ncols, density);
+        let mat = matrices.faer_csc.as_ref();
+        let x = matrices.rhs_vector.col(0);
+        let y = faer::Col::from_fn(nrows, |i| (i as f64 * 0.3 + 1.0) % 4.0);
+        let dst_init = faer::Col::from_fn(nrows, |i| (i as f64 * 0.7 + 2.0) % 5.0);
+        let v = faer::Col::from_fn(nrows, |i| (i as f64 * 0.2 + 1.0) % 3.0);
+        let dst_init_t = faer::Col::from_fn(ncols, |j| (j as f64 * 0.9 + 1.0) % 6.0);
+        let v_t = faer::Col::from_fn(ncols, |j| (j as f64 * 0.4 + 2.0) % 3.0);
+
+        for beta in [Accum::Replace, Accum::Add] {
+            let (reference, reference_t) = match beta {
+                Accum::Replace => (alpha * (mat * x), alpha * (mat.transpose() * &y)),
+                Accum::Add => (
+                    &dst_init + alpha * (mat * x),
+                    &dst_init_t + alpha * (mat.transpose() * &y),
+                ),
+            };
+            let reference_dot = reference.transpose() * &v;
+            let reference_dot_t = reference_t.transpose() * &v_t;
+
+            for n_threads in FIXED_THREAD_COUNTS {
+                let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+                let strategy = SpMvStrategy::new(mat.symbolic(), par);
+
+                let req = simple::sparse_dense_scratch(mat, x.as_mat(), &strategy, par);
+                let mut stack_buffer = faer::dyn_stack::MemBuffer::new(req);
+                let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+                let mut dst = dst_init.clone();
+                let dot = simple::par_sparse_dense_dot(
+                    dst.as_mut(),
+                    beta,
+                    mat,
+                    x,
+                    &alpha,
+                    v.as_ref(),
+                    n_threads,
+                    &strategy,
+                    stack,
+                );
+                assert!(
+                    vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                    "Simple SpMV + dot output differs from reference with {:?}",
+                    par
+                );
+                assert!((dot - reference_dot).abs() <= 1e-10 * reference_dot.abs().max(1.0));
+
+                let req = dense_sparse_scratch(y.as_mat().transpose(), mat, &strategy, par);
+                let mut stack_buffer = faer::dyn_stack::MemBuffer::new(req);
+                let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+                let mut dst_t = dst_init_t.clone();
+                let dot_t = par_dense_sparse_dot(
+                    dst_t.as_mut().transpose_mut(),
+                    beta,
+                    y.transpose(),
+                    mat,
+                    &alpha,
+                    v_t.transpose(),
+                    n_threads,
+                    &strategy,
+                    stack,
+                );
+                assert!(
+                    vectors_are_equal(&reference_t, &dst_t, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                    "Dense sparse SpMV + dot output differs from reference with {:?}",
+                    par
+                );
+                assert!((dot_t - reference_dot_t).abs() <= 1e-10 * reference_dot_t.abs().max(1.0));
+            }
+        }
+        println!(
+            "  ✓ Fused SpMV + dot on {} matches reference",
+            matrices.matrix_name
+        );
+    }
+}
+
+/// Block diagonal matrix with banded `block` x `block` blocks, rows and columns scrambled by
+/// `i -> i * 7919 mod n`
+fn scrambled_block_diagonal(n_blocks: usize, block: usize) -> SparseColMat<usize, f64> {
+    let n = n_blocks * block;
+    let scramble = |i: usize| (i * 7919) % n;
+    let mut triplets = Vec::new();
+    for b in 0..n_blocks {
+        for i in 0..block {
+            for j in i.saturating_sub(3)..(i + 4).min(block) {
+                let value = (i + 2 * j) as f64 % 7.0 + 1.0;
+                triplets.push(Triplet::new(
+                    scramble(b * block + i),
+                    scramble(b * block + j),
+                    value,
+                ));
+            }
+        }
+    }
+    SparseColMat::try_new_from_triplets(n, n, &triplets).unwrap()
+}
+
+#[test]
+fn test_metis_reordering() {
+    let mat = scrambled_block_diagonal(4, 500);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let reference = mat * &x;
+
+    for n_threads in [2, 4] {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let reorderings = [
+            (
+                "symmetric",
+                metis_symmetric(mat.symbolic(), n_threads).unwrap(),
+            ),
+            (
+                "bipartite",
+                metis_bipartite(mat.symbolic(), n_threads).unwrap(),
+            ),
+        ];
+        for (name, reordering) in reorderings {
+            assert_eq!(reordering.row_part_ptr.len(), n_threads + 1);
+            assert_eq!(*reordering.row_part_ptr.last().unwrap(), n);
+            assert_eq!(*reordering.col_part_ptr.last().unwrap(), n);
+
+            let reordered = reordering.permute_matrix(mat);
+            let x_perm = reordering.permute_rhs(x.as_ref());
+            let y_perm = reordered.as_ref() * &x_perm;
+            let y = reordering.unpermute_dst(y_perm.as_ref());
+            assert!(
+                vectors_are_equal(&reference, &y, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "{} reordered product differs from reference",
+                name
+            );
+            assert!(vectors_are_equal(
+                &reordering.permute_dst(reference.as_ref()),
+                &y_perm,
+                RELATIVE_TOLERANCE,
+                ABSOLUTE_TOLERANCE
+            ));
+
+            let report = ForeignWriteReport::new(mat.symbolic(), reordered.symbolic(), par);
+            println!(
+                "  {} METIS reordering, {} threads:\n{}",
+                name, n_threads, report
+            );
+            assert!(report.after.merge < 0.1 * report.before.merge);
+        }
+    }
+}
+
+#[test]
+fn test_rcm_and_metrics() {
+    // tridiagonal: bandwidth 1, one entry left of the diagonal in every row but the first
+    let n = 100;
+    let triplets: Vec<_> = (0..n)
+        .flat_map(|i: usize| {
+            (i.saturating_sub(1)..(i + 2).min(n)).map(move |j| Triplet::new(i, j, 1.0))
+        })
+        .collect();
+    let tridiagonal = SparseColMat::<usize, f64>::try_new_from_triplets(n, n, &triplets).unwrap();
+    assert_eq!(bandwidth(tridiagonal.symbolic()), 1);
+    assert_eq!(profile(tridiagonal.symbolic()), n - 1);
+
+    let mat = scrambled_block_diagonal(4, 500);
+    let mat = mat.as_ref();
+    let x = faer::Col::from_fn(mat.ncols(), |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let reference = mat * &x;
+
+    let reordering = rcm(mat.symbolic());
+    let reordered = reordering.permute_matrix(mat);
+    let y_perm = reordered.as_ref() * reordering.permute_rhs(x.as_ref());
+    let y = reordering.unpermute_dst(y_perm.as_ref());
+    assert!(vectors_are_equal(
+        &reference,
+        &y,
+        RELATIVE_TOLERANCE,
+        ABSOLUTE_TOLERANCE
+    ));
+
+    // the blocks are banded with half bandwidth 3, which RCM recovers
+    assert!(bandwidth(mat.symbolic()) > 1000);
+    assert!(bandwidth(reordered.symbolic()) <= 6);
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let before = MatrixMetrics::new(mat.symbolic(), &SpMvStrategy::new(mat.symbolic(), par));
+        let after = MatrixMetrics::new(
+            reordered.symbolic(),
+            &SpMvStrategy::new(reordered.symbolic(), par),
+        );
+        println!("  {} threads before RCM: {}", n_threads, before);
+        println!("  {} threads after RCM:  {}", n_threads, after);
+        assert!(after.profile < before.profile);
+        assert!(after.avg_row_spread < before.avg_row_spread);
+    }
+}
+
+#[test]
+fn test_permuted_operator() {
+    let mat = scrambled_block_diagonal(4, 500);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let dst_init = faer::Col::from_fn(n, |i| (i as f64 * 0.7 + 2.0) % 5.0);
+    let alpha = 0.5;
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for par in pars {
+        let n_parts = match par {
+            Par::Seq => 1,
+            Par::Rayon(n_threads) => n_threads.get(),
+        };
+        let reorderings = [
+            ("rcm", rcm(mat.symbolic())),
+            (
+                "symmetric",
+                metis_symmetric(mat.symbolic(), n_parts).unwrap(),
+            ),
+            (
+                "bipartite",
+                metis_bipartite(mat.symbolic(), n_parts).unwrap(),
+            ),
+        ];
+        for (name, reordering) in reorderings {
+            let operator = PermutedOperator::new(mat, reordering, par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(operator.scratch());
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+            for beta in [Accum::Replace, Accum::Add] {
+                let reference = match beta {
+                    Accum::Replace => alpha * (mat * &x),
+                    Accum::Add => &dst_init + alpha * (mat * &x),
+                };
+                let mut dst = dst_init.clone();
+                operator.apply(dst.as_mut(), beta, x.as_ref(), &alpha, stack);
+                assert!(
+                    vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                    "{} permuted operator differs from reference with {:?}",
+                    name,
+                    par
+                );
+
+                let reference = match beta {
+                    Accum::Replace => alpha * (mat.transpose() * &x),
+                    Accum::Add => &dst_init + alpha * (mat.transpose() * &x),
+                };
+                let mut dst = dst_init.clone();
+                operator.apply_transpose(dst.as_mut(), beta, x.as_ref(), &alpha, stack);
+                assert!(
+                    vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                    "{} permuted operator transpose differs from reference with {:?}",
+                    name,
+                    par
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_plan_cache() {
+    let mat = scrambled_block_diagonal(4, 500);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let alpha = 1.0;
+
+    let fingerprint = PatternFingerprint::new(mat.symbolic());
+    assert_eq!(fingerprint, PatternFingerprint::new(mat.symbolic()));
+    let other = TestMatrices::create_synthetic(n, n, 0.01);
+    assert_ne!(
+        fingerprint,
+        PatternFingerprint::new(other.faer_csc.symbolic())
+    );
+
+    // same pattern, new values
+    let (symbolic, values) = mat.parts();
+    let new_values: Vec<f64> = values.iter().map(|v| 2.0 * v - 1.0).collect();
+    let new_mat = SparseColMat::new(symbolic.to_owned().unwrap(), new_values.clone());
+
+    let cache = PlanCache::new();
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let first = cache.get(mat.symbolic(), par);
+        let second = cache.get(new_mat.symbolic(), par);
+        assert!(Arc::ptr_eq(&first, &second));
+        let strategy = SpMvStrategy::new(mat.symbolic(), par);
+        assert_eq!(first.thread_cols, strategy.thread_cols);
+        assert_eq!(first.thread_indptrs, strategy.thread_indptrs);
+
+        let mut operator = PermutedOperator::new_cached(mat, rcm(mat.symbolic()), par, &cache);
+        let other_operator = PermutedOperator::new_cached(mat, rcm(mat.symbolic()), par, &cache);
+        assert!(Arc::ptr_eq(
+            operator.shared_strategy(),
+            other_operator.shared_strategy()
+        ));
+
+        operator.update_values(&new_values);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(operator.scratch());
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+        let mut dst = faer::Col::zeros(n);
+        operator.apply(dst.as_mut(), Accum::Replace, x.as_ref(), &alpha, stack);
+        let reference = new_mat.as_ref() * &x;
+        assert!(
+            vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+            "updated permuted operator differs from reference with {} threads",
+            n_threads
+        );
+    }
+    // one plan per thread count for the original pattern and one for the reordered pattern
+    assert_eq!(cache.len(), 2 * FIXED_THREAD_COUNTS.len());
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_plan_io() {
+    let mat = scrambled_block_diagonal(4, 500);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let alpha = 1.0;
+    let dir = std::env::temp_dir();
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let reordering = metis_symmetric(mat.symbolic(), n_threads).unwrap();
+        let reordered = reordering.permute_matrix(mat);
+        let strategy = SpMvStrategy::new(reordered.symbolic(), par);
+        let path = dir.join(format!(
+            "par_matvec_plan_{}_{}.bin",
+            std::process::id(),
+            n_threads
+        ));
+        save_plan(&path, mat.symbolic(), par, &strategy, Some(&reordering)).unwrap();
+
+        let plan = load_plan::<usize>(&path, mat.symbolic(), par).unwrap();
+        assert_eq!(plan.strategy.thread_cols, strategy.thread_cols);
+        assert_eq!(plan.strategy.thread_indptrs, strategy.thread_indptrs);
+        let loaded = plan.reordering.unwrap();
+        assert_eq!(loaded.row_perm, reordering.row_perm);
+        assert_eq!(loaded.col_perm, reordering.col_perm);
+        assert_eq!(loaded.row_part_ptr, reordering.row_part_ptr);
+        assert_eq!(loaded.col_part_ptr, reordering.col_part_ptr);
+
+        let operator = PermutedOperator::with_strategy(mat, loaded, par, Arc::new(plan.strategy));
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(operator.scratch());
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+        let mut dst = faer::Col::zeros(n);
+        operator.apply(dst.as_mut(), Accum::Replace, x.as_ref(), &alpha, stack);
+        assert!(
+            vectors_are_equal(&(mat * &x), &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+            "operator from a loaded plan differs from reference with {} threads",
+            n_threads
+        );
+
+        // a plan whose second thread starts outside its first column is rejected
+        let mut foreign = SpMvStrategy {
+            thread_cols: strategy.thread_cols.clone(),
+            thread_indptrs: strategy.thread_indptrs.clone(),
+            thread_rows: strategy.thread_rows.clone(),
+        };
+        foreign.thread_cols[1] = 0;
+        let reordering = metis_symmetric(mat.symbolic(), n_threads).unwrap();
+        assert!(
+            std::panic::catch_unwind(|| {
+                PermutedOperator::with_strategy(mat, reordering, par, Arc::new(foreign))
+            })
+            .is_err()
+        );
+
+        let other_par = Par::Rayon(NonZero::new(n_threads + 1).unwrap());
+        assert!(matches!(
+            load_plan::<usize>(&path, mat.symbolic(), other_par),
+            Err(PlanIoError::ParMismatch { .. })
+        ));
+        let other = scrambled_block_diagonal(5, 400);
+        assert!(matches!(
+            load_plan::<usize>(&path, other.symbolic(), par),
+            Err(PlanIoError::FingerprintMismatch { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // in memory, without a reordering
+    let par = Par::Rayon(NonZero::new(2).unwrap());
+    let strategy = SpMvStrategy::new(mat.symbolic(), par);
+    let fingerprint = PatternFingerprint::new(mat.symbolic());
+    let mut bytes = Vec::new();
+    write_plan::<usize, _>(&mut bytes, fingerprint, par, &strategy, None).unwrap();
+    let plan = read_plan::<usize, _>(&mut bytes.as_slice()).unwrap();
+    assert_eq!(plan.fingerprint, fingerprint);
+    assert!(plan.reordering.is_none());
+    plan.check(mat.symbolic(), par).unwrap();
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] ^= 1;
+    assert!(matches!(
+        read_plan::<usize, _>(&mut bad_magic.as_slice()),
+        Err(PlanIoError::BadMagic)
+    ));
+    let mut bad_version = bytes.clone();
+    bad_version[PLAN_MAGIC.len()] += 1;
+    assert!(matches!(
+        read_plan::<usize, _>(&mut bad_version.as_slice()),
+        Err(PlanIoError::UnsupportedVersion(_))
+    ));
+    assert!(matches!(
+        read_plan::<usize, _>(&mut &bytes[..bytes.len() - 1]),
+        Err(PlanIoError::Io(_))
+    ));
+
+    // tampered strategies with the right fingerprint, which would drive the kernels out of bounds
+    let tampered_bytes = |tamper: fn(&mut SpMvStrategy)| {
+        let mut tampered = SpMvStrategy::new(mat.symbolic(), par);
+        tamper(&mut tampered);
+        let mut bytes = Vec::new();
+        write_plan::<usize, _>(&mut bytes, fingerprint, par, &tampered, None).unwrap();
+        bytes
+    };
+    let last_col_out_of_bounds = tampered_bytes(|s| *s.thread_cols.last_mut().unwrap() += 1);
+    assert!(matches!(
+        read_plan::<usize, _>(&mut last_col_out_of_bounds.as_slice()),
+        Err(PlanIoError::Corrupt(_))
+    ));
+    let unsorted_indptrs = tampered_bytes(|s| s.thread_indptrs.swap(0, 1));
+    assert!(matches!(
+        read_plan::<usize, _>(&mut unsorted_indptrs.as_slice()),
+        Err(PlanIoError::Corrupt(_))
+    ));
+    // well formed, but the middle thread starts outside its first column
+    let foreign_start = tampered_bytes(|s| s.thread_cols[1] = 0);
+    let plan = read_plan::<usize, _>(&mut foreign_start.as_slice()).unwrap();
+    assert!(matches!(
+        plan.check(mat.symbolic(), par),
+        Err(PlanIoError::Corrupt(_))
+    ));
+    let path = dir.join(format!(
+        "par_matvec_plan_{}_tampered.bin",
+        std::process::id()
+    ));
+    std::fs::write(&path, &foreign_start).unwrap();
+    assert!(matches!(
+        load_plan::<usize>(&path, mat.symbolic(), par),
+        Err(PlanIoError::Corrupt(_))
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_weighted_strategy() {
+    let matrices = TestMatrices::create_synthetic(2000, 2000, 0.02);
+    let mat = matrices.faer_csc.as_ref();
+    let rhs = matrices.rhs_vector.as_ref();
+    let nnz = mat.compute_nnz();
+
+    let mut reference = Mat::zeros(mat.nrows(), 1);
+    faer::sparse::linalg::matmul::sparse_dense_matmul(
+        reference.as_mut(),
+        Accum::Replace,
+        mat,
+        rhs,
+        1.0,
+        Par::Seq,
+    );
+    let lhs = rhs.transpose();
+    let mut dense_sparse_reference = Mat::zeros(1, mat.ncols());
+    faer::sparse::linalg::matmul::dense_sparse_matmul(
+        dense_sparse_reference.as_mut(),
+        Accum::Replace,
+        lhs,
+        mat,
+        1.0,
+        Par::Seq,
+    );
+
+    // every column followed by an unused slot
+    let padded = SymbolicSparseColMat::new_checked(
+        mat.nrows(),
+        mat.ncols(),
+        (0..=mat.ncols()).map(|j| mat.col_ptr()[j] + j).collect(),
+        Some((0..mat.ncols()).map(|j| mat.col_range(j).len()).collect()),
+        (0..mat.ncols())
+            .flat_map(|j| mat.row_idx_of_col_raw(j).iter().copied().chain([0]))
+            .collect(),
+    );
+
+    let calibrated = CostModel::calibrate(mat);
+    println!("  calibrated cost model: {:?}", calibrated);
+    assert_eq!(calibrated.nnz_cost, 1.0);
+    assert!(calibrated.col_cost.is_finite() && calibrated.col_cost >= 0.0);
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let mut weights = vec![1.0; n_threads];
+        weights[0] = 3.0;
+        let cases = [
+            ("nnz", CostModel::default(), None),
+            (
+                "weighted nnz",
+                CostModel::default(),
+                Some(weights.as_slice()),
+            ),
+            ("columns", CostModel::new(1.0, 50.0), None),
+            ("calibrated", calibrated, Some(weights.as_slice())),
+        ];
+        for (name, cost, thread_weights) in cases {
+            let strategy = SpMvStrategy::new_weighted(mat.symbolic(), par, &cost, thread_weights);
+            assert_eq!(strategy.thread_indptrs.len(), n_threads + 1);
+            assert_eq!(strategy.thread_cols.len(), n_threads + 1);
+            assert_eq!(strategy.thread_indptrs[n_threads], nnz);
+            assert!(strategy.thread_indptrs.is_sorted());
+            assert!(strategy.thread_cols.is_sorted());
+            for tid in 1..n_threads {
+                let idx = strategy.thread_indptrs[tid];
+                let col = strategy.thread_cols[tid];
+                assert!(idx >= mat.col_range(col).start);
+                assert!(idx < mat.col_range(col).end || idx == nnz);
+            }
+
+            let thread_nnz: Vec<usize> = strategy
+                .thread_indptrs
+                .windows(2)
+                .map(|w| w[1] - w[0])
+                .collect();
+            if name == "nnz" {
+                let max = thread_nnz.iter().max().unwrap();
+                let min = thread_nnz.iter().min().unwrap();
+                assert!(max - min <= 1, "{:?}", thread_nnz);
+            }
+            if name == "weighted nnz" {
+                let share = thread_nnz[0] as f64 / nnz as f64;
+                let expected = 3.0 / (n_threads + 2) as f64;
+                assert!((share - expected).abs() < 0.01, "{:?}", thread_nnz);
+            }
+
+            // the same plan, shifted into the storage of the columns, without compressed columns
+            let uncompressed =
+                SpMvStrategy::new_weighted(padded.as_ref(), par, &cost, thread_weights);
+            assert_eq!(uncompressed.thread_cols, strategy.thread_cols);
+            for tid in 0..=n_threads {
+                let col = strategy.thread_cols[tid];
+                assert_eq!(
+                    uncompressed.thread_indptrs[tid] - padded.col_range(col).start,
+                    strategy.thread_indptrs[tid] - mat.col_range(col).start
+                );
+            }
+
+            let stack_req = simple::sparse_dense_scratch(mat, rhs, &strategy, par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(stack_req);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut output = Mat::zeros(mat.nrows(), 1);
+            sparse_dense_matmul(
+                output.as_mut(),
+                Accum::Replace,
+                mat,
+                rhs,
+                1.0,
+                par,
+                &strategy,
+                stack,
+                Some(simple::par_sparse_dense),
+            );
+            assert!(
+                vectors_are_equal(&reference, &output, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "simple with {} plan and {} threads differs from reference",
+                name,
+                n_threads
+            );
+
+            let stack_req = dense_sparse_scratch(lhs, mat, &strategy, par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(stack_req);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut output = Mat::zeros(1, mat.ncols());
+            dense_sparse_matmul(
+                output.as_mut(),
+                Accum::Replace,
+                lhs,
+                mat,
+                1.0,
+                par,
+                &strategy,
+                stack,
+                Some(par_dense_sparse),
+            );
+            assert!(
+                vectors_are_equal(
+                    &dense_sparse_reference.transpose().to_owned(),
+                    &output.transpose().to_owned(),
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "dense-sparse with {} plan and {} threads differs from reference",
+                name,
+                n_threads
+            );
+        }
+    }
+}
+
+#[test]
+fn test_row_partition() {
+    // arrow matrix: the first rows are dense, so an even split of the rows is badly balanced
+    let n = 3000;
+    let dense_rows = 20;
+    let mut triplets = Vec::new();
+    for j in 0..n {
+        for i in 0..dense_rows {
+            triplets.push(Triplet::new(i, j, 1.0 + (i + j) as f64 * 1e-3));
+        }
+        if j >= dense_rows {
+            triplets.push(Triplet::new(j, j, 2.0));
+        }
+    }
+    let mat = SparseColMat::<usize, f64>::try_new_from_triplets(n, n, &triplets).unwrap();
+    let mat = mat.as_ref();
+    let nnz = mat.compute_nnz();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let b = faer::Col::from_fn(n, |i| (i as f64 * 0.7 + 2.0) % 5.0);
+    let reference = mat * &x;
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        for strategy in [
+            SpMvStrategy::new(mat.symbolic(), par),
+            SpMvStrategy::new_weighted(mat.symbolic(), par, &CostModel::new(1.0, 4.0), None),
+        ] {
+            let thread_rows = &strategy.thread_rows;
+            assert_eq!(thread_rows.len(), n_threads + 1);
+            assert_eq!(thread_rows[0], 0);
+            assert_eq!(thread_rows[n_threads], n);
+            assert!(thread_rows.is_sorted());
+            // the dense rows hold most of the output work, so the first thread gets few rows
+            assert!(thread_rows[1] < n / n_threads, "{:?}", thread_rows);
+            let row_work = |rows: std::ops::Range<usize>| {
+                rows.map(|i| 1 + if i < dense_rows { n } else { 1 })
+                    .sum::<usize>()
+            };
+            let max_work = (0..n_threads)
+                .map(|tid| row_work(strategy.thread_row_range(tid)))
+                .max()
+                .unwrap();
+            assert!(max_work <= (n + nnz) / n_threads + n + 1);
+
+            let stack_req = simple::sparse_dense_scratch(mat, x.as_mat(), &strategy, par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(stack_req);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut dst = faer::Col::zeros(n);
+            simple::par_sparse_dense(
+                dst.as_mut(),
+                Accum::Replace,
+                mat,
+                x.as_ref(),
+                &1.0,
+                n_threads,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "simple with a weighted row partition and {} threads differs from reference",
+                n_threads
+            );
+
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(residual_scratch(mat, par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut r = faer::Col::zeros(n);
+            residual(
+                r.as_mut(),
+                b.as_ref(),
+                mat,
+                x.as_ref(),
+                None,
+                par,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(
+                    &(&b - &reference),
+                    &r,
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "fused residual with a weighted row partition and {} threads differs from reference",
+                n_threads
+            );
+        }
+    }
+}
+
+#[test]
+fn test_owner_kernels_unpinned() {
+    // tall matrix with dense top rows, spanning several `buffer_foreign` row blocks: the row
+    // partition gives the first thread a few dense rows while the columns split evenly, so most
+    // contributions of every thread land in rows owned by another one
+    let (m, n) = (40_000, 3000);
+    let dense_rows = 30;
+    let mut triplets = Vec::new();
+    for j in 0..n {
+        for i in 0..dense_rows {
+            triplets.push(Triplet::new(i, j, 1.0 + (i + j) as f64 * 1e-3));
+        }
+        for k in 0..10 {
+            let i = dense_rows + (j * 131 + k * 3989) % (m - dense_rows);
+            triplets.push(Triplet::new(i, j, 0.5 + k as f64 * 0.1));
+        }
+    }
+    let mat = SparseColMat::<usize, f64>::try_new_from_triplets(m, n, &triplets).unwrap();
+    let mat = mat.as_ref();
+    let x = faer::Col::from_fn(n, |i| (i as f64 * 0.1 + 1.0) % 10.0);
+    let reference = mat * &x;
+    let offset = faer::Col::from_fn(m, |i| (i % 7) as f64);
+
+    // pinning would need a core per thread
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let strategy = SpMvStrategy::new(mat.symbolic(), par);
+        assert!(strategy.thread_rows[1] * n < strategy.thread_cols[1] * m);
+
+        let names = ["merge", "buffer_foreign"];
+        let scratch_fns = [
+            merge::sparse_dense_scratch,
+            buffer_foreign::sparse_dense_scratch,
+        ];
+        let kernels = [
+            merge::par_sparse_dense_unpinned,
+            buffer_foreign::par_sparse_dense_unpinned,
+        ];
+        for (name, (scratch, kernel)) in names.iter().zip(scratch_fns.iter().zip(kernels.iter())) {
+            let mut stack_buffer =
+                faer::dyn_stack::MemBuffer::new(scratch(mat, x.as_mat(), &strategy, par));
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut dst = offset.clone();
+            kernel(
+                dst.as_mut(),
+                Accum::Add,
+                mat,
+                x.as_ref(),
+                &1.0,
+                n_threads,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(
+                    &(&reference + &offset),
+                    &dst,
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "{name} accumulating with {n_threads} threads differs from reference"
+            );
+            kernel(
+                dst.as_mut(),
+                Accum::Replace,
+                mat,
+                x.as_ref(),
+                &1.0,
+                n_threads,
+                &strategy,
+                stack,
+            );
+            assert!(
+                vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "{name} with {n_threads} threads differs from reference"
+            );
+        }
+    }
+}
+
+/// The scaled `side` triangle of `mat` with a dominant diagonal, and the same without the diagonal
+fn triangular_parts(
+    mat: faer::sparse::SparseColMatRef<'_, usize, f64>,
+    side: Side,
+) -> (SparseColMat<usize, f64>, SparseColMat<usize, f64>) {
+    let n = mat.nrows();
+    let mut strict = Vec::new();
+    for j in 0..n {
+        for (i, &v) in mat.row_idx_of_col(j).zip(mat.val_of_col(j)) {
+            let keep = match side {
+                Side::Lower => i > j,
+                Side::Upper => i < j,
+            };
+            if keep {
+                // small off-diagonal entries keep the unit diagonal solve well conditioned
+                strict.push(Triplet::new(i, j, 0.01 * v));
+            }
+        }
+    }
+    let mut full = strict.clone();
+    full.extend((0..n).map(|i| Triplet::new(i, i, 4.0 + (i % 7) as f64)));
+    (
+        SparseColMat::try_new_from_triplets(n, n, &full).unwrap(),
+        SparseColMat::try_new_from_triplets(n, n, &strict).unwrap(),
+    )
+}
+
+#[test]
+fn test_triangular_solve() {
+    let random = TestMatrices::create_synthetic(3000, 3000, 0.002);
+    // two levels of 10k independent rows each, wide enough for the parallel path
+    let n = 20000;
+    let wide = SparseColMat::<usize, f64>::try_new_from_triplets(
+        n,
+        n,
+        &(0..n / 2)
+            .flat_map(|i| {
+                [
+                    Triplet::new(i + n / 2, i, 0.5),
+                    Triplet::new(i, i + n / 2, 0.5),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for (name, mat) in [
+        ("random", random.faer_csc.as_ref()),
+        ("wide", wide.as_ref()),
+    ] {
+        let n = mat.nrows();
+        let x_true = faer::Col::from_fn(n, |i| 1.0 + (i % 10) as f64 * 0.1);
+        for side in [Side::Lower, Side::Upper] {
+            let (full, strict) = triangular_parts(mat, side);
+            for par in pars.iter().copied() {
+                let plan = TriangularPlan::new(full.symbolic(), side, par);
+                if name == "wide" {
+                    assert_eq!(plan.n_levels(), 2);
+                }
+                for diag in [Diag::Unit, Diag::NonUnit] {
+                    let mut x = match diag {
+                        Diag::Unit => &strict * &x_true + &x_true,
+                        Diag::NonUnit => &full * &x_true,
+                    };
+                    plan.solve_in_place(full.as_ref(), diag, x.as_mut());
+                    assert!(
+                        vectors_are_equal(&x_true, &x, 1e-10, 1e-10),
+                        "{:?} {:?} triangular solve on {} differs with {:?}",
+                        side,
+                        diag,
+                        name,
+                        par
+                    );
+                }
+            }
+        }
+        println!("  ✓ Triangular solves on {} match", name);
+    }
+}
+
+/// Plain Gauss–Seidel sweep over the rows in the given order
+fn sequential_gauss_seidel(
+    mat: faer::sparse::SparseRowMatRef<'_, usize, f64>,
+    x: &mut faer::Col<f64>,
+    b: &faer::Col<f64>,
+    order: impl Iterator<Item = usize>,
+) {
+    for i in order {
+        let mut sum = 0.0;
+        let mut diag = 0.0;
+        for (j, &v) in mat.col_idx_of_row(i).zip(mat.val_of_row(i)) {
+            if j == i {
+                diag = v;
+            } else {
+                sum += v * x[j];
+            }
+        }
+        x[i] = (b[i] - sum) / diag;
+    }
+}
+
+#[test]
+fn test_gauss_seidel() {
+    // large enough for the colors to be split over the threads
+    let mat = laplacian_2d(100, 100);
+    let mat = mat.as_ref();
+    let csr = mat.to_row_major().unwrap();
+    let n = mat.nrows();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+
+    let plan = GaussSeidelPlan::new(mat.symbolic(), Par::Seq);
+    // red-black ordering of the 5-point stencil
+    assert_eq!(plan.n_colors(), 2);
+    let mut color_of_row = vec![0; n];
+    for c in 0..plan.n_colors() {
+        for &i in plan.color_rows(c) {
+            color_of_row[i] = c;
+        }
+    }
+    for j in 0..n {
+        for i in mat.row_idx_of_col(j) {
+            assert!(i == j || color_of_row[i] != color_of_row[j]);
+        }
+    }
+    let color_order: Vec<usize> = (0..plan.n_colors())
+        .flat_map(|c| plan.color_rows(c).to_vec())
+        .collect();
+
+    let n_sweeps = 25;
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for sweep in [Sweep::Forward, Sweep::Backward, Sweep::Symmetric] {
+        // the same sweep done sequentially in color order
+        let mut reference = faer::Col::zeros(n);
+        for _ in 0..n_sweeps {
+            if sweep != Sweep::Backward {
+                let order = color_order.iter().copied();
+                sequential_gauss_seidel(csr.as_ref(), &mut reference, &b, order);
+            }
+            if sweep != Sweep::Forward {
+                // rows within a color are independent, so reversing the colors is enough
+                let order = (0..plan.n_colors())
+                    .rev()
+                    .flat_map(|c| plan.color_rows(c).to_vec());
+                sequential_gauss_seidel(csr.as_ref(), &mut reference, &b, order);
+            }
+        }
+
+        for par in pars.iter().copied() {
+            let plan = GaussSeidelPlan::new(mat.symbolic(), par);
+            let mut x = faer::Col::zeros(n);
+            plan.smooth(mat, x.as_mut(), b.as_ref(), sweep, n_sweeps);
+            assert!(
+                vectors_are_equal(&reference, &x, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "{:?} multicolor Gauss-Seidel differs from the sequential sweep with {:?}",
+                sweep,
+                par
+            );
+        }
+    }
+
+    // converges at about the rate of the natural order sweep
+    let mat = laplacian_2d(12, 10);
+    let mat = mat.as_ref();
+    let csr = mat.to_row_major().unwrap();
+    let n = mat.nrows();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+    let residual_norm = |x: &faer::Col<f64>| (&b - mat * x).norm_l2();
+    let plan = GaussSeidelPlan::new(mat.symbolic(), Par::Seq);
+    let n_sweeps = 100;
+    for sweep in [Sweep::Forward, Sweep::Backward, Sweep::Symmetric] {
+        let mut multicolor = faer::Col::zeros(n);
+        plan.smooth(mat, multicolor.as_mut(), b.as_ref(), sweep, n_sweeps);
+        let mut natural = faer::Col::zeros(n);
+        for _ in 0..n_sweeps {
+            if sweep != Sweep::Backward {
+                sequential_gauss_seidel(csr.as_ref(), &mut natural, &b, 0..n);
+            }
+            if sweep != Sweep::Forward {
+                sequential_gauss_seidel(csr.as_ref(), &mut natural, &b, (0..n).rev());
+            }
+        }
+        let initial = b.norm_l2();
+        let multicolor = residual_norm(&multicolor);
+        let natural = residual_norm(&natural);
+        println!(
+            "  {:?}: residual {:.3e} multicolor, {:.3e} natural order (initial {:.3e})",
+            sweep, multicolor, natural, initial
+        );
+        assert!(multicolor < 1e-2 * initial);
+        // a symmetric sweep relaxes the last color twice in a row, so with two colors it gains
+        // little over a forward sweep, unlike in natural order
+        if sweep != Sweep::Symmetric {
+            let rate = |residual: f64| (residual / initial).powf(1.0 / n_sweeps as f64);
+            assert!(rate(multicolor) < 1.01 * rate(natural));
+        }
+    }
+}
+
+#[test]
+fn test_jacobi() {
+    let mat = laplacian_2d(100, 100);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+    let n_sweeps = 20;
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for (diagonal, omega) in [
+        (JacobiDiagonal::Plain, 2.0 / 3.0),
+        (JacobiDiagonal::L1, 1.0),
+    ] {
+        // D is 4 plus, for l1, one per neighbour in the grid
+        let d = |i: usize| {
+            let neighbours = mat.row_idx_of_col(i).filter(|&k| k != i).count();
+            match diagonal {
+                JacobiDiagonal::Plain => 4.0,
+                JacobiDiagonal::L1 => 4.0 + neighbours as f64,
+            }
+        };
+        let mut reference = faer::Col::<f64>::zeros(n);
+        for _ in 0..n_sweeps {
+            let r = &b - mat * &reference;
+            reference = faer::Col::from_fn(n, |i| reference[i] + omega / d(i) * r[i]);
+        }
+
+        for par in pars.iter().copied() {
+            let smoother = JacobiSmoother::new(mat, diagonal, omega, par);
+            for i in 0..n {
+                assert!((smoother.scaled_inv_diag()[i] - omega / d(i)).abs() < 1e-15);
+            }
+
+            let mut x = faer::Col::zeros(n);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(smoother.scratch());
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            smoother.smooth(mat, x.as_mut(), b.as_ref(), n_sweeps, stack);
+            assert!(
+                vectors_are_equal(&reference, &x, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "{:?} Jacobi differs from the reference with {:?}",
+                diagonal,
+                par
+            );
+            assert!((&b - mat * &x).norm_l2() < b.norm_l2());
+        }
+    }
+
+    // new values with the same pattern
+    let mut scaled = laplacian_2d(100, 100);
+    scaled.val_mut().iter_mut().for_each(|v| *v *= 2.0);
+    for par in pars {
+        let mut smoother = JacobiSmoother::new(mat, JacobiDiagonal::L1, 1.0, par);
+        smoother.update_values(scaled.as_ref());
+        let fresh = JacobiSmoother::new(scaled.as_ref(), JacobiDiagonal::L1, 1.0, par);
+        assert_eq!(smoother.scaled_inv_diag(), fresh.scaled_inv_diag());
+    }
+
+    // a missing diagonal entry would silently give an infinite `omega / D_ii`
+    let triplets: Vec<_> = (0..n)
+        .flat_map(|j| {
+            mat.row_idx_of_col(j)
+                .zip(mat.val_of_col(j))
+                .filter(move |&(i, _)| (i, j) != (n / 2, n / 2))
+                .map(move |(i, &v)| Triplet::new(i, j, v))
+        })
+        .collect();
+    let missing = SparseColMat::<usize, f64>::try_new_from_triplets(n, n, &triplets).unwrap();
+    for par in [Par::Seq, Par::Rayon(NonZero::new(2).unwrap())] {
+        assert!(
+            std::panic::catch_unwind(|| {
+                JacobiSmoother::new(missing.as_ref(), JacobiDiagonal::Plain, 1.0, par)
+            })
+            .is_err()
+        );
+    }
+}
+
+#[test]
+fn test_chebyshev() {
+    let mat = laplacian_2d(100, 100);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+    let degree = 4;
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+
+    // the spectrum of D^-1 A of the Laplacian lies in (0, 2) and reaches close to 2
+    for par in pars.iter().copied() {
+        let smoother = ChebyshevSmoother::new(mat, degree, par);
+        let estimate = smoother.lambda_max() / LAMBDA_MAX_SAFETY;
+        println!("  lambda_max estimate {:.4} with {:?}", estimate, par);
+        assert!(estimate > 1.5 && estimate <= 2.0);
+        assert!(smoother.lambda_min() < smoother.lambda_max());
+    }
+
+    // the three-term recurrence with D = 4
+    let (lambda_min, lambda_max) = (0.1, 2.1);
+    let theta = (lambda_max + lambda_min) / 2.0;
+    let delta = (lambda_max - lambda_min) / 2.0;
+    let sigma = theta / delta;
+    let mut reference = faer::Col::<f64>::zeros(n);
+    let mut d = faer::Col::<f64>::zeros(n);
+    let mut rho = 1.0 / sigma;
+    for step in 0..degree {
+        let r = &b - mat * &reference;
+        if step == 0 {
+            d = faer::Col::from_fn(n, |i| r[i] / 4.0 / theta);
+        } else {
+            let rho_next = 1.0 / (2.0 * sigma - rho);
+            d = faer::Col::from_fn(n, |i| {
+                rho_next * rho * d[i] + 2.0 * rho_next / delta * r[i] / 4.0
+            });
+            rho = rho_next;
+        }
+        reference += &d;
+    }
+
+    for par in pars.iter().copied() {
+        let smoother = ChebyshevSmoother::with_bounds(mat, degree, lambda_min, lambda_max, par);
+        let mut x = faer::Col::zeros(n);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(smoother.scratch());
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+        smoother.smooth(mat, x.as_mut(), b.as_ref(), stack);
+        assert!(
+            vectors_are_equal(&reference, &x, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+            "Chebyshev differs from the reference with {:?}",
+            par
+        );
+    }
+
+    // beats weighted Jacobi with the same number of SpMVs
+    let n_applications = 10;
+    let chebyshev = ChebyshevSmoother::new(mat, degree, Par::Seq);
+    let jacobi = JacobiSmoother::new(mat, JacobiDiagonal::Plain, 2.0 / 3.0, Par::Seq);
+    let mut stack_buffer =
+        faer::dyn_stack::MemBuffer::new(chebyshev.scratch().or(jacobi.scratch()));
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let mut x_chebyshev = faer::Col::zeros(n);
+    for _ in 0..n_applications {
+        chebyshev.smooth(mat, x_chebyshev.as_mut(), b.as_ref(), stack);
+    }
+    let mut x_jacobi = faer::Col::zeros(n);
+    jacobi.smooth(
+        mat,
+        x_jacobi.as_mut(),
+        b.as_ref(),
+        n_applications * degree,
+        stack,
+    );
+    let chebyshev = (&b - mat * &x_chebyshev).norm_l2();
+    let jacobi = (&b - mat * &x_jacobi).norm_l2();
+    println!(
+        "  residual {:.3e} Chebyshev, {:.3e} Jacobi (initial {:.3e})",
+        chebyshev,
+        jacobi,
+        b.norm_l2()
+    );
+    assert!(chebyshev < jacobi);
+}
+
+#[test]
+fn test_operator_backends() {
+    let (m, n) = (300, 200);
+    let matrices = TestMatrices::create_synthetic(m, n, 0.05);
+    let mat = matrices.faer_csc.as_ref();
+    let x = faer::Col::from_fn(n, |i| 1.0 + (i % 9) as f64 * 0.1);
+    let y = faer::Col::from_fn(m, |i| 1.0 + (i % 5) as f64 * 0.2);
+    let reference = mat * &x;
+    let reference_transpose = mat.transpose() * &y;
+    let reference_dot = reference.transpose() * &y;
+
+    // the merge and buffer_foreign kernels pin their threads to cores, so they only run up to the
+    // core count
+    let cpus = num_cpus::get();
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for par in pars {
+        for backend in SpMvBackend::ALL {
+            if let Par::Rayon(n_threads) = par
+                && backend != SpMvBackend::Simple
+                && n_threads.get() > cpus
+            {
+                continue;
+            }
+            let a = SparseOperator::with_backend(mat, backend, par);
+            assert_eq!(a.backend(), backend);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(a.scratch_req());
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+            let mut dst = faer::Col::zeros(m);
+            a.apply(dst.as_mut(), x.as_ref(), stack);
+            assert!(
+                vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+                "{} operator differs from reference with {:?}",
+                backend.name(),
+                par
+            );
+
+            let mut dst = faer::Col::zeros(m);
+            let dot = a.apply_dot(dst.as_mut(), x.as_ref(), y.as_ref(), stack);
+            assert!(vectors_are_equal(
+                &reference,
+                &dst,
+                RELATIVE_TOLERANCE,
+                ABSOLUTE_TOLERANCE
+            ));
+            assert!((dot - reference_dot).abs() <= 1e-12 * reference_dot.abs());
+
+            let mut dst = faer::Col::zeros(n);
+            a.apply_transpose(dst.as_mut(), y.as_ref(), stack);
+            assert!(
+                vectors_are_equal(
+                    &reference_transpose,
+                    &dst,
+                    RELATIVE_TOLERANCE,
+                    ABSOLUTE_TOLERANCE
+                ),
+                "{} operator transpose differs from reference with {:?}",
+                backend.name(),
+                par
+            );
+        }
+    }
+}
+
+#[test]
+fn test_operator_composition() {
+    let mat = convection_diffusion_2d(6, 5, 3.0, 2.0);
+    let n = mat.nrows();
+    let other = TestMatrices::create_synthetic(n, n, 0.1);
+    let prolongation = TestMatrices::create_synthetic(n, 10, 0.2);
+    let (a_dense, b_dense, p_dense) = (
+        mat.to_dense(),
+        other.faer_csc.to_dense(),
+        prolongation.faer_csc.to_dense(),
+    );
+
+    // every composite is checked against its dense matrix, with one stack of its `scratch_req`
+    let check = |op: &dyn LinearOperator<f64>, expected: &Mat<f64>, name: &str, par: Par| {
+        assert_eq!(
+            (op.nrows(), op.ncols()),
+            (expected.nrows(), expected.ncols())
+        );
+        let x = faer::Col::from_fn(op.ncols(), |i| 1.0 + (i % 7) as f64 * 0.1);
+        let y = faer::Col::from_fn(op.nrows(), |i| 1.0 + (i % 5) as f64 * 0.2);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(op.scratch_req());
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let reference = expected * &x;
+        let mut dst = faer::Col::zeros(op.nrows());
+        op.apply(dst.as_mut(), x.as_ref(), stack);
+        assert!(
+            vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+            "{} differs from reference with {:?}",
+            name,
+            par
+        );
+
+        let mut dst = faer::Col::zeros(op.nrows());
+        let dot = op.apply_dot(dst.as_mut(), x.as_ref(), y.as_ref(), stack);
+        let reference_dot = reference.transpose() * &y;
+        assert!((dot - reference_dot).abs() <= 1e-12 * reference_dot.abs());
+
+        let reference = expected.transpose() * &y;
+        let mut dst = faer::Col::zeros(op.ncols());
+        op.apply_transpose(dst.as_mut(), y.as_ref(), stack);
+        assert!(
+            vectors_are_equal(&reference, &dst, RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE),
+            "{} transpose differs from reference with {:?}",
+            name,
+            par
+        );
+    };
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for par in pars {
+        let a = SparseOperator::new(mat.as_ref(), par);
+        let b = SparseOperator::new(other.faer_csc.as_ref(), par);
+        let p = SparseOperator::new(prolongation.faer_csc.as_ref(), par);
+
+        let p_t = TransposedOperator::new(&p);
+        check(&p_t, &p_dense.transpose().to_owned(), "transpose", par);
+        check(
+            &SumOperator::new(&a, &b, par),
+            &(&a_dense + &b_dense),
+            "sum",
+            par,
+        );
+        check(
+            &ProductOperator::new(&a, &b),
+            &(&a_dense * &b_dense),
+            "product",
+            par,
+        );
+        check(
+            &ScaledOperator::new(&a, 2.0, 0.5, par),
+            &(2.0 * &a_dense + 0.5 * Mat::<f64>::identity(n, n)),
+            "scaled",
+            par,
+        );
+        check(
+            &ScaledOperator::shifted(&a, -1.5, par),
+            &(&a_dense - 1.5 * Mat::<f64>::identity(n, n)),
+            "shifted",
+            par,
+        );
+
+        let galerkin = p_dense.transpose() * &a_dense * &p_dense;
+        check(&GalerkinOperator::new(&a, &p), &galerkin, "galerkin", par);
+        // the same triple product built from nested composites
+        let a_p = ProductOperator::new(&a, &p);
+        check(
+            &ProductOperator::new(&p_t, &a_p),
+            &galerkin,
+            "nested galerkin",
+            par,
+        );
+        let shifted = ScaledOperator::shifted(&a, 1.0, par);
+        let sum = SumOperator::new(&shifted, &b, par);
+        check(
+            &ProductOperator::new(&sum, &a),
+            &((&a_dense + Mat::<f64>::identity(n, n) + &b_dense) * &a_dense),
+            "nested sum",
+            par,
+        );
+    }
+}
+
+#[test]
+fn test_spgemm() {
+    let a = convection_diffusion_2d(10, 8, 3.0, 2.0);
+    let n = a.nrows();
+    let b = TestMatrices::create_synthetic(n, 30, 0.05).faer_csc;
+    let p = TestMatrices::create_synthetic(n, 20, 0.05).faer_csc;
+    let r = p.as_ref().transpose().to_col_major().unwrap();
+    let (a_dense, b_dense, p_dense, r_dense) =
+        (a.to_dense(), b.to_dense(), p.to_dense(), r.to_dense());
+    let reference_product = &a_dense * &b_dense;
+    let reference_rap = &r_dense * &a_dense * &p_dense;
+    let matches = |c: &SparseColMat<usize, f64>, reference: &Mat<f64>| {
+        let (m, n, col_ptr, _, row_idx) = c.symbolic().parts();
+        // panics unless the row indices are sorted and in bounds
+        faer::sparse::SymbolicSparseColMatRef::new_checked(m, n, col_ptr, None, row_idx);
+        (c.to_dense() - reference).norm_max() <= 1e-12 * reference.norm_max()
+    };
+
+    let product = spgemm(a.as_ref(), b.as_ref(), Par::Seq);
+    let galerkin = rap(r.as_ref(), a.as_ref(), p.as_ref(), Par::Seq);
+    assert!(matches(&product, &reference_product));
+    assert!(matches(&galerkin, &reference_rap));
+
+    // the values of `a` change but not its pattern
+    let mut a_scaled = a.clone();
+    a_scaled.val_mut().iter_mut().for_each(|v| *v *= 2.0);
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let plan = SpGemmPlan::new(a.symbolic(), b.symbolic(), par);
+        let triple_plan = SpGemmPlan::new_triple(r.symbolic(), a.symbolic(), p.symbolic(), par);
+        for (plan, ncols) in [(&plan, b.ncols()), (&triple_plan, p.ncols())] {
+            let thread_cols = plan.thread_cols();
+            assert_eq!(thread_cols.len(), n_threads + 1);
+            assert_eq!((thread_cols[0], thread_cols[n_threads]), (0, ncols));
+            assert!(thread_cols.windows(2).all(|w| w[0] <= w[1]));
+        }
+        let mut stack_buffer =
+            faer::dyn_stack::MemBuffer::new(plan.scratch::<f64>().or(triple_plan.scratch::<f64>()));
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        // every column is computed the same way on any partition
+        let parallel_product = plan.product(a.as_ref(), b.as_ref(), stack);
+        assert_eq!(
+            PatternFingerprint::new(parallel_product.symbolic()),
+            PatternFingerprint::new(product.symbolic())
+        );
+        assert_eq!(parallel_product.val(), product.val());
+        let parallel_galerkin =
+            triple_plan.triple_product(r.as_ref(), a.as_ref(), p.as_ref(), stack);
+        assert_eq!(
+            PatternFingerprint::new(parallel_galerkin.symbolic()),
+            PatternFingerprint::new(galerkin.symbolic())
+        );
+        assert_eq!(parallel_galerkin.val(), galerkin.val());
+
+        let mut values = vec![0.0; galerkin.val().len()];
+        triple_plan.triple_product_into(
+            &mut values,
+            r.as_ref(),
+            a_scaled.as_ref(),
+            p.as_ref(),
+            stack,
+        );
+        assert!(
+            values
+                .iter()
+                .zip(galerkin.val())
+                .all(|(v, g)| (v - 2.0 * g).abs() <= 1e-12 * g.abs().max(1.0))
+        );
+    }
+}
+
+#[test]
+fn test_spmspv() {
+    let a = TestMatrices::create_synthetic(300, 200, 0.05).faer_csc;
+    let (m, n) = (a.nrows(), a.ncols());
+    // a sparse frontier, with a repeated value to keep a few columns equal
+    let x_dense = faer::Col::from_fn(n, |j| match j % 7 {
+        0 => 1.0 + j as f64 / n as f64,
+        3 => -0.5,
+        _ => 0.0,
+    });
+    let x = SparseVec::<usize, f64>::from_dense(x_dense.as_ref());
+    assert_eq!(x.to_dense(), x_dense);
+    let reference = a.to_dense() * &x_dense;
+    let close =
+        |y: faer::ColRef<'_, f64>| (y - &reference).norm_max() <= 1e-12 * reference.norm_max();
+
+    let empty = SparseVec::<usize, f64>::new(n, vec![], vec![]);
+    for par in std::iter::once(Par::Seq).chain(
+        FIXED_THREAD_COUNTS
+            .iter()
+            .map(|&t| Par::Rayon(NonZero::new(t).unwrap())),
+    ) {
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(spmspv_scratch::<f64>(x.nnz(), par));
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let y = spmspv(a.as_ref(), &x, par, stack);
+        assert_eq!(y.len(), m);
+        assert!(y.indices().windows(2).all(|w| w[0] < w[1]));
+        assert!(close(y.to_dense().as_ref()));
+        // only the rows of the selected columns appear
+        let touched = (0..n)
+            .filter(|&j| x_dense[j] != 0.0)
+            .flat_map(|j| a.row_idx_of_col(j))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert!(y.indices().iter().eq(touched.iter()));
+
+        let mut dense = faer::Col::from_fn(m, |i| i as f64);
+        spmspv_dense(dense.as_mut(), Accum::Add, a.as_ref(), &x, par, stack);
+        let offset = faer::Col::from_fn(m, |i| i as f64);
+        assert!(close((&dense - &offset).as_ref()));
+        spmspv_dense(dense.as_mut(), Accum::Replace, a.as_ref(), &x, par, stack);
+        assert!(close(dense.as_ref()));
+
+        assert_eq!(spmspv(a.as_ref(), &empty, par, stack).nnz(), 0);
+        spmspv_dense(
+            dense.as_mut(),
+            Accum::Replace,
+            a.as_ref(),
+            &empty,
+            par,
+            stack,
+        );
+        assert_eq!(dense.norm_max(), 0.0);
+    }
+}
+
+#[test]
+fn test_cg() {
+    // a badly scaled Laplacian S A S, which Jacobi preconditioning undoes
+    let mut mat = laplacian_2d(30, 30);
+    let n = mat.nrows();
+    let scale = |i: usize| 1.0 + (i % 5) as f64;
+    let entries: Vec<(usize, usize)> = (0..n)
+        .flat_map(|j| {
+            mat.row_idx_of_col(j)
+                .map(move |i| (i, j))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for (v, &(i, j)) in mat.val_mut().iter_mut().zip(&entries) {
+        *v *= scale(i) * scale(j);
+    }
+    let mat = mat.as_ref();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+    let criteria = StoppingCriteria {
+        rtol: 1e-10,
+        ..Default::default()
+    };
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    let mut reference = None;
+    for par in pars {
+        let a = SparseOperator::new(mat, par);
+        let jacobi = JacobiSmoother::new(mat, JacobiDiagonal::Plain, 1.0, par);
+        let permuted = PermutedOperator::new(mat, rcm(mat.symbolic()), par);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(
+            cg_scratch(&a, Some(&jacobi)).or(cg_scratch(&permuted, None)),
+        );
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let mut x_cg = faer::Col::zeros(n);
+        let info_cg = cg(&a, x_cg.as_mut(), b.as_ref(), &criteria, par, stack);
+        let mut x_pcg = faer::Col::zeros(n);
+        let info_pcg = pcg(
+            &a,
+            &jacobi,
+            x_pcg.as_mut(),
+            b.as_ref(),
+            &criteria,
+            par,
+            stack,
+        );
+        let mut x_permuted = faer::Col::zeros(n);
+        let info_permuted = cg(
+            &permuted,
+            x_permuted.as_mut(),
+            b.as_ref(),
+            &criteria,
+            par,
+            stack,
+        );
+        println!(
+            "  {:?}: {} iterations CG, {} PCG, {} CG on the RCM ordering",
+            par, info_cg.iterations, info_pcg.iterations, info_permuted.iterations
+        );
+
+        for (x, info) in [
+            (&x_cg, &info_cg),
+            (&x_pcg, &info_pcg),
+            (&x_permuted, &info_permuted),
+        ] {
+            assert!(info.converged);
+            assert_eq!(info.history.len(), info.iterations + 1);
+            assert!((info.history[0] - b.norm_l2()).abs() <= 1e-12 * b.norm_l2());
+            assert!(info.residual_norm <= 1e-10 * b.norm_l2());
+            assert!((&b - mat * x).norm_l2() < 1e-8 * b.norm_l2());
+        }
+        assert!(info_pcg.iterations < info_cg.iterations);
+
+        // the vector operations are independent of the thread count, only the SpMV rounds
+        // differently
+        let iterations = (info_cg.iterations, info_pcg.iterations);
+        let reference = reference.get_or_insert(iterations);
+        assert!(reference.0.abs_diff(iterations.0) <= 2);
+        assert!(reference.1.abs_diff(iterations.1) <= 2);
+    }
+
+    // the fused dot of the operator matches the separate one
+    let par = Par::Rayon(NonZero::new(3).unwrap());
+    let a = SparseOperator::new(mat, par);
+    let mut stack_buffer = faer::dyn_stack::MemBuffer::new(a.scratch_req());
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let mut ab = faer::Col::zeros(n);
+    let dot = a.apply_dot(ab.as_mut(), b.as_ref(), b.as_ref(), stack);
+    let expected = (mat * &b).transpose() * &b;
+    assert!((dot - expected).abs() <= 1e-12 * expected.abs());
+}
+
+#[test]
+fn test_nonsymmetric_solvers() {
+    let mat = convection_diffusion_2d(20, 20, 10.0, 5.0);
+    let mat = mat.as_ref();
+    let n = mat.nrows();
+    let b = faer::Col::from_fn(n, |i| 1.0 + (i % 7) as f64 * 0.1);
+    let criteria = StoppingCriteria {
+        rtol: 1e-10,
+        ..Default::default()
+    };
+    let restart = 20;
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for par in pars {
+        let a = SparseOperator::new(mat, par);
+        let jacobi = JacobiSmoother::new(mat, JacobiDiagonal::Plain, 1.0, par);
+        let mut stack_buffer = faer::dyn_stack::MemBuffer::new(
+            gmres_scratch(&a, Some(&jacobi), restart).or(bicgstab_scratch(&a, Some(&jacobi))),
+        );
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        for m in [None, Some(&jacobi as &dyn LinearOperator<f64>)] {
+            let mut x_gmres = faer::Col::zeros(n);
+            let info_gmres = gmres(
+                &a,
+                m,
+                x_gmres.as_mut(),
+                b.as_ref(),
+                restart,
+                &criteria,
+                par,
+                stack,
+            );
+            let mut x_bicgstab = faer::Col::zeros(n);
+            let info_bicgstab = bicgstab(
+                &a,
+                m,
+                x_bicgstab.as_mut(),
+                b.as_ref(),
+                &criteria,
+                par,
+                stack,
+            );
+            println!(
+                "  {:?}, preconditioned {}: {} iterations GMRES({}), {} BiCGStab",
+                par,
+                m.is_some(),
+                info_gmres.iterations,
+                restart,
+                info_bicgstab.iterations
+            );
+
+            for (x, info) in [(&x_gmres, &info_gmres), (&x_bicgstab, &info_bicgstab)] {
+                assert!(info.converged);
+                assert_eq!(info.history.len(), info.iterations + 1);
+                assert!((info.history[0] - b.norm_l2()).abs() <= 1e-12 * b.norm_l2());
+                assert!(info.residual_norm <= 1e-10 * b.norm_l2());
+                assert!((&b - mat * x).norm_l2() < 1e-8 * b.norm_l2());
+            }
+            // GMRES minimizes the residual over a growing space within a cycle
+            for cycle in info_gmres.history[1..].chunks(restart) {
+                assert!(cycle.windows(2).all(|w| w[1] <= w[0] * (1.0 + 1e-12)));
+            }
+        }
+    }
+
+    // restarting after every step still converges on a diagonally dominant matrix, and a
+    // small iteration limit is reported as not converged
+    let a = SparseOperator::new(mat, Par::Seq);
+    let mut stack_buffer = faer::dyn_stack::MemBuffer::new(gmres_scratch(&a, None, 3));
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let mut x = faer::Col::zeros(n);
+    let info = gmres(
+        &a,
+        None,
+        x.as_mut(),
+        b.as_ref(),
+        1,
+        &criteria,
+        Par::Seq,
+        stack,
+    );
+    assert!(info.converged);
+    let mut x = faer::Col::zeros(n);
+    let limited = StoppingCriteria {
+        max_iters: 5,
+        ..criteria.clone()
+    };
+    let info = gmres(
+        &a,
+        None,
+        x.as_mut(),
+        b.as_ref(),
+        3,
+        &limited,
+        Par::Seq,
+        stack,
+    );
+    assert!(!info.converged);
+    assert_eq!(info.iterations, 5);
+    assert_eq!(info.history.len(), 6);
+}
+
+#[test]
+fn test_spectral() {
+    let (nx, ny) = (12, 10);
+    let mat = laplacian_2d(nx, ny);
+    let mat = mat.as_ref();
+    let mut exact: Vec<f64> = (1..=nx)
+        .flat_map(|i| {
+            (1..=ny).map(move |j| {
+                let theta_i = std::f64::consts::PI * i as f64 / (nx + 1) as f64;
+                let theta_j = std::f64::consts::PI * j as f64 / (ny + 1) as f64;
+                4.0 - 2.0 * theta_i.cos() - 2.0 * theta_j.cos()
+            })
+        })
+        .collect();
+    exact.sort_by(f64::total_cmp);
+    let (exact_min, exact_max) = (exact[0], exact[exact.len() - 1]);
+    // a Hermitian operator has an eigenvalue within the residual of every estimate
+    let distance = |value: f64| {
+        exact
+            .iter()
+            .map(|lambda| (lambda - value).abs())
+            .fold(f64::INFINITY, f64::min)
+    };
+
+    let mut pars = vec![Par::Seq];
+    pars.extend(FIXED_THREAD_COUNTS.map(|n| Par::Rayon(NonZero::new(n).unwrap())));
+    for par in pars {
+        let a = SparseOperator::new(mat, par);
+        let mut stack_buffer =
+            faer::dyn_stack::MemBuffer::new(power_iteration_scratch(&a).or(lanczos_scratch(&a)));
+        let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+
+        let power = power_iteration(&a, 200, par, stack);
+        let lanczos = lanczos(&a, 40, par, stack);
+        println!(
+            "  {:?}: power {:.6} ({:.1e}), lanczos [{:.6}, {:.6}] ({:.1e}, {:.1e}) in {} steps",
+            par,
+            power.value,
+            power.residual,
+            lanczos.min.value,
+            lanczos.max.value,
+            lanczos.min.residual,
+            lanczos.max.residual,
+            lanczos.steps
+        );
+
+        assert!(power.value <= exact_max * (1.0 + 1e-12));
+        assert!(distance(power.value) <= power.residual * (1.0 + 1e-8));
+        assert!((power.value - exact_max).abs() < 1e-2 * exact_max);
+
+        assert_eq!(lanczos.steps, 40);
+        for estimate in [&lanczos.min, &lanczos.max] {
+            assert!(distance(estimate.value) <= estimate.residual * (1.0 + 1e-8) + 1e-12);
+        }
+        // Ritz values lie within the spectrum and the extremal ones converge first
+        assert!(lanczos.min.value >= exact_min * (1.0 - 1e-12));
+        assert!(lanczos.max.value <= exact_max * (1.0 + 1e-12));
+        assert!((lanczos.max.value - exact_max).abs() < 1e-8 * exact_max);
+        assert!((lanczos.min.value - exact_min).abs() < 1e-6 * exact_min);
+        let condition_number = exact_max / exact_min;
+        assert!((lanczos.condition_number() - condition_number).abs() < 1e-6 * condition_number);
+    }
+
+    // the Krylov space of a diagonal matrix with three distinct values becomes invariant after
+    // three steps
+    let diag = SparseColMat::<usize, f64>::try_new_from_triplets(
+        6,
+        6,
+        &(0..6)
+            .map(|i| Triplet::new(i, i, [1.0, 2.0, 5.0][i % 3]))
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+    let a = SparseOperator::new(diag.as_ref(), Par::Seq);
+    let mut stack_buffer = faer::dyn_stack::MemBuffer::new(lanczos_scratch(&a));
+    let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+    let estimate = lanczos(&a, 10, Par::Seq, stack);
+    assert_eq!(estimate.steps, 3);
+    assert!((estimate.min.value - 1.0).abs() < 1e-12 && estimate.min.residual < 1e-12);
+    assert!((estimate.max.value - 5.0).abs() < 1e-12 && estimate.max.residual < 1e-12);
+}
+
+#[test]
+fn test_partition_report() {
+    let matrices = TestMatrices::create_synthetic(2000, 2000, 0.05);
+    let mat = matrices.faer_csc.as_ref();
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let strategy = SpMvStrategy::new(mat.symbolic(), par);
+        let report = PartitionReport::new(mat, &strategy, par);
+        println!("  {} threads:\n{}", n_threads, report);
+
+        assert_eq!(report.threads.len(), n_threads);
+        assert_eq!(
+            report.threads.iter().map(|t| t.nnz).sum::<usize>(),
+            matrices.nnz
+        );
+        assert!(report.threads.iter().map(|t| t.cols).sum::<usize>() >= mat.ncols());
+        assert!(report.nnz_imbalance() < 1.01);
+        // a single row block, owned by the thread owning its middle row
+        let owner = strategy
+            .thread_rows
+            .windows(2)
+            .position(|rows| (rows[0]..rows[1]).contains(&(mat.nrows() / 2)))
+            .unwrap();
+        for (tid, thread) in report.threads.iter().enumerate() {
+            if tid == owner {
+                assert_eq!(thread.foreign_blocks, 0);
+                assert_eq!(thread.owned_row_fraction, 1.0);
+            } else {
+                assert_eq!(thread.foreign_blocks, 1);
+                assert_eq!(thread.owned_row_fraction, 0.0);
+            }
+        }
+        assert_eq!(
+            report.workspace_bytes.simple,
+            mat.nrows() * n_threads * size_of::<f64>()
+        );
+    }
+
+    let report = PartitionReport::new(mat, &SpMvStrategy::new(mat.symbolic(), Par::Seq), Par::Seq);
+    assert!(report.threads.is_empty());
+    assert_eq!(report.workspace_bytes.simple, 0);
+}
+
+#[test]
+fn test_numa_first_touch() {
+    assert_eq!(
+        parse_cpulist("0-3,8-9,16\n"),
+        Some(vec![0, 1, 2, 3, 8, 9, 16])
+    );
+    assert_eq!(parse_cpulist(""), Some(vec![]));
+    assert_eq!(parse_cpulist("0-x"), None);
+    if let Some(topology) = NumaTopology::detect() {
+        println!("  NUMA nodes: {:?}", topology.node_cpus);
+        assert!(topology.n_nodes() >= 1);
+    }
+
+    let matrices = TestMatrices::create_synthetic(2000, 2000, 0.05);
+    let mat = matrices.faer_csc.as_ref();
+    let rhs = matrices.rhs_vector.as_ref();
+    let reference = mat * rhs;
+
+    for n_threads in FIXED_THREAD_COUNTS {
+        let par = Par::Rayon(NonZero::new(n_threads).unwrap());
+        let strategy = SpMvStrategy::new(mat.symbolic(), par);
+
+        for copy in [
+            pinned_first_touch_copy(mat, &strategy, par),
+            first_touch_copy(mat, &strategy, par),
+        ] {
+            assert_eq!(copy.symbolic().col_ptr(), mat.symbolic().col_ptr());
+            assert_eq!(copy.symbolic().row_idx(), mat.symbolic().row_idx());
+            assert_eq!(copy.val(), mat.val());
+
+            let req = simple::sparse_dense_scratch(copy.as_ref(), rhs, &strategy, par);
+            let mut stack_buffer = faer::dyn_stack::MemBuffer::new(req);
+            let stack = faer::dyn_stack::MemStack::new(&mut stack_buffer);
+            let mut result = Mat::zeros(mat.nrows(), 1);
+            sparse_dense_matmul(
+                result.as_mut(),
+                Accum::Replace,
+                copy.as_ref(),
+                rhs,
+                1.0,
+                par,
+                &strategy,
+                stack,
+                Some(simple::par_sparse_dense),
+            );
+            assert!(vectors_are_equal(
+                &reference,
+                &result,
+                RELATIVE_TOLERANCE,
+                ABSOLUTE_TOLERANCE
+            ));
+        }
+    }
+}
+
 #[test]
 fn test_synthetic_matrices() {
     let test_cases = [
@@ -295,6 +2442,7 @@ fn test_synthetic_matrices() {
         test_sequential_implementations(&matrices).expect("Sequential tests failed");
         if matrices.nnz > 1000 {
             test_parallel_implementations(&matrices).expect("Parallel tests failed");
+            test_csr_implementations(&matrices).expect("CSR tests failed");
         }
     }
 
@@ -310,17 +2458,15 @@ fn test_matrix_market_files() {
             println!("\nTesting matrix file: {}", matrix_path.display());
             match TestMatrices::load_from_matrix_market(&matrix_path, 1) {
                 Ok(matrices) => {
-                    test_sequential_implementations(&matrices).expect(&format!(
-                        "Sequential tests failed on {}",
-                        matrix_path.display()
-                    ));
+                    test_sequential_implementations(&matrices).unwrap_or_else(|e| {
+                        panic!("Sequential tests failed on {}: {e}", matrix_path.display())
+                    });
 
                     // Only test parallel if matrix has enough non-zeros
                     if matrices.nnz > 32 {
-                        test_parallel_implementations(&matrices).expect(&format!(
-                            "Parallel tests failed on {}",
-                            matrix_path.display()
-                        ));
+                        test_parallel_implementations(&matrices).unwrap_or_else(|e| {
+                            panic!("Parallel tests failed on {}: {e}", matrix_path.display())
+                        });
                     } else {
                         println!(
                             "  Skipping parallel test (too few non-zeros: {})",
@@ -353,6 +2499,7 @@ fn test_edge_cases() {
         .expect("Sequential tests failed on dense matrix");
     let dense_matrices = TestMatrices::create_synthetic(200, 200, 1.0);
     test_parallel_implementations(&dense_matrices).expect("Parallel tests failed on dense matrix");
+    test_csr_implementations(&dense_matrices).expect("CSR tests failed on dense matrix");
 
     println!("Edge case tests passed!");
 }